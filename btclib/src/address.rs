@@ -0,0 +1,299 @@
+use {
+    crate::{
+        crypto::PublicKey,
+        error::AddressError,
+    },
+    std::{
+        fmt,
+        str::FromStr,
+    },
+};
+
+/// Hash of a public key, which outputs paying an address lock to
+/// (see `PublicKey::pubkey_hash`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct PubkeyHash(pub [u8; 20]);
+
+impl PubkeyHash {
+    // first 20 bytes of the double SHA-256 of `data`, which is the
+    // compressed key for public keys
+    pub fn digest(data: &[u8]) -> Self {
+        let hash = sha256::digest(hex::decode(sha256::digest(data)).unwrap());
+        let hash_bytes = hex::decode(hash).unwrap();
+        PubkeyHash(hash_bytes[..20].try_into().unwrap())
+    }
+}
+
+impl fmt::Display for PubkeyHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", hex::encode(self.0))
+    }
+}
+
+/// Network an address is meant for, so that coins are not sent to
+/// an address of another network by mistake
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Network {
+    #[default]
+    Mainnet,
+    Testnet,
+    Regtest,
+}
+
+impl Network {
+    // human-readable part of the network's addresses
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            Network::Mainnet => "btc",
+            Network::Testnet => "tbtc",
+            Network::Regtest => "rbtc",
+        }
+    }
+
+    pub fn from_prefix(prefix: &str) -> Option<Self> {
+        [Network::Mainnet, Network::Testnet, Network::Regtest]
+            .into_iter()
+            .find(|network| network.prefix() == prefix)
+    }
+}
+
+impl FromStr for Network {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mainnet" => Ok(Network::Mainnet),
+            "testnet" => Ok(Network::Testnet),
+            "regtest" => Ok(Network::Regtest),
+            _ => {
+                Err(format!(
+                    "unknown network {s}, expected mainnet, testnet or regtest"
+                ))
+            }
+        }
+    }
+}
+
+/// A public key hash for a network, written in Bech32m (BIP350):
+/// the network prefix, the separator `1`, an address version and
+/// the hash in base 32, followed by a checksum that catches any
+/// four typos
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Address {
+    pub network: Network,
+    pub pubkey_hash: PubkeyHash,
+}
+
+impl Address {
+    pub fn new(network: Network, pubkey_hash: PubkeyHash) -> Self {
+        Address {
+            network,
+            pubkey_hash,
+        }
+    }
+
+    pub fn from_public_key(network: Network, public_key: &PublicKey) -> Self {
+        Address::new(network, public_key.pubkey_hash())
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let prefix = self.network.prefix();
+        let mut data = vec![ADDRESS_VERSION];
+        data.extend(convert_bits(&self.pubkey_hash.0, 8, 5, true).unwrap());
+        let checksum = bech32m_checksum(prefix, &data);
+        write!(f, "{prefix}1")?;
+        for value in data.into_iter().chain(checksum) {
+            write!(f, "{}", BECH32_CHARSET[value as usize] as char)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Address {
+    type Err = AddressError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (prefix, data) = bech32m_decode(s)?;
+        let network = Network::from_prefix(&prefix).ok_or(AddressError::UnknownPrefix(prefix))?;
+        if data.is_empty() {
+            return Err(AddressError::InvalidLength);
+        }
+        let (version, hash) = (data[0], &data[1..]);
+        if version != ADDRESS_VERSION {
+            return Err(AddressError::UnsupportedVersion(version));
+        }
+        let hash = convert_bits(hash, 5, 8, false).ok_or(AddressError::InvalidLength)?;
+        let pubkey_hash = hash.try_into().map_err(|_| AddressError::InvalidLength)?;
+        Ok(Address::new(network, PubkeyHash(pubkey_hash)))
+    }
+}
+
+// version of public key hash addresses, leaving room for other kinds
+const ADDRESS_VERSION: u8 = 0;
+const BECH32_CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+// what the checksum polynomial of a valid Bech32m string evaluates to
+const BECH32M_CONST: u32 = 0x2bc8_30a3;
+
+// the prefix and the data without the checksum of a Bech32m string
+fn bech32m_decode(s: &str) -> Result<(String, Vec<u8>), AddressError> {
+    if s.len() > 90 {
+        return Err(AddressError::InvalidLength);
+    }
+    if let Some(c) = s.chars().find(|c| !('!'..='~').contains(c)) {
+        return Err(AddressError::InvalidCharacter(c));
+    }
+    if s.chars().any(|c| c.is_ascii_lowercase()) && s.chars().any(|c| c.is_ascii_uppercase()) {
+        return Err(AddressError::MixedCase);
+    }
+    let s = s.to_ascii_lowercase();
+    let (prefix, data) = s.rsplit_once('1').ok_or(AddressError::MissingSeparator)?;
+    let data = data
+        .chars()
+        .map(|c| {
+            BECH32_CHARSET
+                .iter()
+                .position(|&b| b as char == c)
+                .map(|value| value as u8)
+                .ok_or(AddressError::InvalidCharacter(c))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if prefix.is_empty() || data.len() < 6 {
+        return Err(AddressError::InvalidLength);
+    }
+    if bech32_polymod(prefix, &data) != BECH32M_CONST {
+        return Err(AddressError::InvalidChecksum);
+    }
+    Ok((prefix.to_string(), data[..data.len() - 6].to_vec()))
+}
+
+fn bech32_polymod(prefix: &str, data: &[u8]) -> u32 {
+    const GENERATOR: [u32; 5] = [
+        0x3b6a_57b2,
+        0x2650_8e6d,
+        0x1ea1_19fa,
+        0x3d42_33dd,
+        0x2a14_62b3,
+    ];
+    // the prefix is mixed in by its high bits, then its low bits
+    let values = prefix
+        .bytes()
+        .map(|b| b >> 5)
+        .chain([0])
+        .chain(prefix.bytes().map(|b| b & 31))
+        .chain(data.iter().copied());
+    let mut checksum = 1u32;
+    for value in values {
+        let top = checksum >> 25;
+        checksum = (checksum & 0x1ff_ffff) << 5 ^ value as u32;
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if top >> i & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    checksum
+}
+
+fn bech32m_checksum(prefix: &str, data: &[u8]) -> Vec<u8> {
+    let mut values = data.to_vec();
+    values.extend([0; 6]);
+    let polymod = bech32_polymod(prefix, &values) ^ BECH32M_CONST;
+    (0..6)
+        .map(|i| (polymod >> (5 * (5 - i)) & 31) as u8)
+        .collect()
+}
+
+// regroup `from` bit values into `to` bit values. Without padding,
+// leftover bits have to be zero and fewer than `from`
+fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Option<Vec<u8>> {
+    let mut accumulator = 0u32;
+    let mut bits = 0;
+    let mut result = vec![];
+    for &value in data {
+        accumulator = accumulator << from | value as u32;
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            result.push((accumulator >> bits & ((1 << to) - 1)) as u8);
+        }
+    }
+    if pad {
+        if bits > 0 {
+            result.push((accumulator << (to - bits) & ((1 << to) - 1)) as u8);
+        }
+    } else if bits >= from || accumulator & ((1 << bits) - 1) != 0 {
+        return None;
+    }
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::crypto::PrivateKey,
+    };
+
+    // valid Bech32m strings of BIP350
+    #[test]
+    fn bech32m_valid_vectors() {
+        for s in [
+            "A1LQFN3A",
+            "a1lqfn3a",
+            "an83characterlonghumanreadablepartthatcontainsthetheexcludedcharactersbioandnumber11sg7hg6",
+            "abcdef1l7aum6echk45nj3s0wdvt2fg8x9yrzpqzd3ryx",
+            "11llllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllllludsr8",
+            "split1checkupstagehandshakeupstreamerranterredcaperredlc445v",
+            "?1v759aa",
+        ] {
+            assert!(bech32m_decode(s).is_ok(), "{s}");
+        }
+    }
+
+    // invalid Bech32m strings of BIP350, and a valid Bech32 one
+    #[test]
+    fn bech32m_invalid_vectors() {
+        for s in [
+            "\u{20}1xj0phk",
+            "\u{7f}1g6xzxy",
+            "\u{80}1vctc34",
+            "an84characterslonghumanreadablepartthatcontainsthetheexcludedcharactersbioandnumber11858amb",
+            "qyrz8wqd2c9m",
+            "1qyrz8wqd2c9m",
+            "y1b0jsk6g",
+            "lt1igcx5c0",
+            "in1muywd",
+            "mm1crxm3i",
+            "au1s5cgom",
+            "M1VUXWEZ",
+            "16plkw9",
+            "1p2gdwpf",
+            "A12UEL5L",
+        ] {
+            assert!(bech32m_decode(s).is_err(), "{s:?}");
+        }
+    }
+
+    #[test]
+    fn address_round_trip() {
+        let pubkey_hash = PrivateKey::new_key().public_key().pubkey_hash();
+        for network in [Network::Mainnet, Network::Testnet, Network::Regtest] {
+            let address = Address::new(network, pubkey_hash);
+            let s = address.to_string();
+            assert_eq!(s.parse::<Address>(), Ok(address));
+            assert_eq!(s.to_ascii_uppercase().parse::<Address>(), Ok(address));
+        }
+    }
+
+    #[test]
+    fn address_typo_is_rejected() {
+        let address = Address::new(Network::Mainnet, PubkeyHash([7; 20])).to_string();
+        let mut typo = address.into_bytes();
+        let last = typo.len() - 1;
+        typo[last] = if typo[last] == b'q' { b'p' } else { b'q' };
+        let typo = String::from_utf8(typo).unwrap();
+        assert_eq!(typo.parse::<Address>(), Err(AddressError::InvalidChecksum));
+    }
+}
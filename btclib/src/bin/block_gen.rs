@@ -3,6 +3,7 @@ use {
         crypto::PrivateKey,
         sha256::Hash,
         types::{
+            amount::Amount,
             block::Block,
             block_header::BlockHeader,
             transaction::{
@@ -31,12 +32,13 @@ fn main() {
         exit(1);
     };
     let private_key = PrivateKey::new_key();
-    let transactions = vec![Transaction::new(
+    let transactions = vec![Transaction::new_coinbase(
+        0,
         vec![],
         vec![TransactionOutput {
             unique_id: Uuid::new_v4(),
-            value: btclib::INITIAL_REWARD * 10u64.pow(8),
-            pubkey: private_key.public_key(),
+            value: Amount::from_btc(btclib::INITIAL_REWARD),
+            lock: private_key.public_key().into(),
         }],
     )];
     let merkle_root = MerkleRoot::calculate(&transactions);
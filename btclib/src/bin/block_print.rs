@@ -1,5 +1,6 @@
 use {
     btclib::{
+        address::Network,
         types::block::Block,
         util::Saveable,
     },
@@ -14,11 +15,23 @@ fn main() {
     let path = if let Some(arg) = env::args().nth(1) {
         arg
     } else {
-        eprintln!("Usage: block_print");
+        eprintln!("Usage: block_print <block_file> [network]");
         exit(1);
     };
+    let network = env::args()
+        .nth(2)
+        .map(|arg| arg.parse::<Network>().expect("Invalid network"))
+        .unwrap_or_default();
     if let Ok(file) = File::open(path) {
         let block = Block::load(file).expect("Failed to load block");
         println!("{:#?}", block);
+        for transaction in block.transactions.iter() {
+            for output in transaction.outputs.iter() {
+                match output.lock.address(network) {
+                    Some(address) => println!("{} to {address}", output.value),
+                    None => println!("{} to a script or multisig", output.value),
+                }
+            }
+        }
     }
 }
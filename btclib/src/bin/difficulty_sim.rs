@@ -0,0 +1,103 @@
+use {
+    btclib::{
+        difficulty::DifficultyAdjustment,
+        sha256::Hash,
+        types::{
+            block_header::BlockHeader,
+            transaction::Transaction,
+        },
+        util::MerkleRoot,
+    },
+    chrono::{
+        Duration,
+        Utc,
+    },
+    rand::Rng,
+    std::{
+        env,
+        process::exit,
+    },
+};
+
+// hashrate multipliers the simulated network goes through, the
+// first phase letting the target settle
+const PHASES: [(&str, f64); 5] = [
+    ("warm up", 1.0),
+    ("steady", 1.0),
+    ("miners join (10x)", 10.0),
+    ("miners leave (0.1x)", 0.1),
+    ("recovery", 1.0),
+];
+// the genesis target is this much harder than the minimum target,
+// so that the target can also go up when miners leave
+const BASE_DIFFICULTY: u64 = 1000;
+
+// a chain mined by a network whose hashrate follows PHASES, with
+// solve times drawn from an exponential distribution. The base
+// hashrate mines the genesis target in IDEAL_BLOCK_TIME on average
+fn simulate(difficulty: DifficultyAdjustment, blocks_per_phase: usize) -> Vec<(f64, f64)> {
+    let algorithm = difficulty.algorithm();
+    let merkle_root = MerkleRoot::calculate(&[Transaction::new_coinbase(0, vec![], vec![])]);
+    let genesis = BlockHeader::new(
+        Utc::now(),
+        0,
+        Hash::zero(),
+        merkle_root.clone(),
+        btclib::MIN_TARGET / BASE_DIFFICULTY,
+    );
+    let base_hashrate = genesis.work().low_u128() as f64 / btclib::IDEAL_BLOCK_TIME as f64;
+    let mut rng = rand::thread_rng();
+    let mut timestamp = genesis.timestamp;
+    let mut headers = vec![genesis];
+    let mut results = vec![];
+
+    for (_, multiplier) in PHASES.iter() {
+        let mut total_time = 0.0;
+        let mut longest_time: f64 = 0.0;
+        for _ in 0..blocks_per_phase {
+            let target = {
+                let headers = headers.iter().collect::<Vec<_>>();
+                algorithm.next_target(&headers)
+            };
+            let mut header =
+                BlockHeader::new(timestamp, 0, Hash::zero(), merkle_root.clone(), target);
+            let expected_time = header.work().low_u128() as f64 / (base_hashrate * multiplier);
+            let solve_time = -(1.0 - rng.gen::<f64>()).ln() * expected_time;
+            timestamp += Duration::milliseconds((solve_time * 1000.0) as i64);
+            header.timestamp = timestamp;
+            headers.push(header);
+            total_time += solve_time;
+            longest_time = longest_time.max(solve_time);
+        }
+        results.push((total_time / blocks_per_phase as f64, longest_time));
+    }
+    results
+}
+
+fn main() {
+    let blocks_per_phase = match env::args().nth(1).map(|arg| arg.parse::<usize>()) {
+        None => 500,
+        Some(Ok(blocks)) if blocks > 0 => blocks,
+        _ => {
+            eprintln!("Usage: difficulty_sim [blocks_per_phase]");
+            exit(1);
+        }
+    };
+    println!(
+        "Simulating {blocks_per_phase} blocks per phase, ideal block time {}s",
+        btclib::IDEAL_BLOCK_TIME
+    );
+
+    for difficulty in [
+        DifficultyAdjustment::Epoch,
+        DifficultyAdjustment::Lwma,
+        DifficultyAdjustment::Asert,
+    ] {
+        println!("\n{difficulty:?}");
+        for ((phase, _), (average, longest)) in
+            PHASES.iter().zip(simulate(difficulty, blocks_per_phase))
+        {
+            println!("  {phase:<20} average {average:>8.1}s, longest {longest:>8.1}s");
+        }
+    }
+}
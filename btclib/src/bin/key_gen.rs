@@ -1,5 +1,9 @@
 use {
     btclib::{
+        address::{
+            Address,
+            Network,
+        },
         crypto::PrivateKey,
         util::Saveable,
     },
@@ -8,10 +12,15 @@ use {
 
 fn main() {
     let name = env::args().nth(1).expect("Please provide a name");
+    let network = env::args()
+        .nth(2)
+        .map(|arg| arg.parse::<Network>().expect("Invalid network"))
+        .unwrap_or_default();
     let private_key = PrivateKey::new_key();
     let public_key = private_key.public_key();
     let public_key_file = name.clone() + ".pub.pem";
     let private_key_file = name + ".priv.cbor";
     private_key.save_to_file(&private_key_file).unwrap();
     public_key.save_to_file(&public_key_file).unwrap();
+    println!("{}", Address::from_public_key(network, &public_key));
 }
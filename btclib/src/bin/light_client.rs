@@ -0,0 +1,233 @@
+use {
+    btclib::{
+        address::{
+            Address,
+            PubkeyHash,
+        },
+        difficulty::DifficultyAdjustment,
+        filter::{
+            outpoint_element,
+            pubkey_hash_element,
+        },
+        network::Message,
+        sha256::Hash,
+        spv::HeaderChain,
+        types::{
+            amount::Amount,
+            block_header::BlockHeader,
+            transaction::Transaction,
+        },
+        util::Saveable,
+    },
+    std::{
+        collections::HashMap,
+        env,
+        net::TcpStream,
+        path::Path,
+        process::exit,
+    },
+};
+
+// send a request to the node and wait for its response
+fn request(stream: &mut TcpStream, message: Message) -> Message {
+    if let Err(e) = message.send(stream) {
+        eprintln!("Failed to send request: {e}");
+        exit(1);
+    }
+    match Message::receive(stream) {
+        Ok(response) => response,
+        Err(e) => {
+            eprintln!("Failed to receive response: {e}");
+            exit(1);
+        }
+    }
+}
+
+// download the headers the chain does not have yet
+fn sync_headers(stream: &mut TcpStream, chain: &mut HeaderChain) {
+    loop {
+        let Message::Headers(headers) = request(stream, Message::FetchHeaders(chain.height()))
+        else {
+            eprintln!("Unexpected response to FetchHeaders");
+            exit(1);
+        };
+        if headers.is_empty() {
+            return;
+        }
+        for header in headers {
+            if let Err(e) = chain.add_header(header) {
+                eprintln!("Node sent an invalid header at {}: {e}", chain.height());
+                exit(1);
+            }
+        }
+    }
+}
+
+// download the transactions of the block at `height` that pay the
+// address or spend `outpoints`, and check their merkle proof
+// against the chain
+fn fetch_filtered_block(
+    stream: &mut TcpStream,
+    chain: &HeaderChain,
+    height: u64,
+    pubkey_hash: PubkeyHash,
+    outpoints: Vec<Hash>,
+) -> Vec<Transaction> {
+    let message = Message::FetchFilteredBlock(height, pubkey_hash, outpoints);
+    let Message::FilteredBlock(Some(block)) = request(stream, message) else {
+        eprintln!("Node did not send the block at {height}");
+        exit(1);
+    };
+    if block.height != height {
+        eprintln!(
+            "Node sent the block at {} instead of {height}",
+            block.height
+        );
+        exit(1);
+    }
+    if let Err(e) = chain.verify_filtered_block(&block) {
+        eprintln!("Node sent an invalid block at {height}: {e}");
+        exit(1);
+    }
+    block.transactions
+}
+
+fn main() {
+    let args = env::args().collect::<Vec<_>>();
+    let (Some(node_address), Some(address)) = (args.get(1), args.get(2)) else {
+        eprintln!("Usage: light_client <node_address> <address> [headers_file] [difficulty]");
+        exit(1);
+    };
+    let address = match address.parse::<Address>() {
+        Ok(address) => address,
+        Err(e) => {
+            eprintln!("Invalid address: {e}");
+            exit(1);
+        }
+    };
+    let headers_file = args.get(3).map_or("headers.cbor", String::as_str);
+    let difficulty = match args.get(4).map(|arg| arg.parse::<DifficultyAdjustment>()) {
+        None => DifficultyAdjustment::default(),
+        Some(Ok(difficulty)) => difficulty,
+        Some(Err(e)) => {
+            eprintln!("{e}");
+            exit(1);
+        }
+    };
+    let mut chain = if Path::new(headers_file).exists() {
+        match HeaderChain::load_from_file(headers_file) {
+            Ok(chain) => chain,
+            Err(e) => {
+                eprintln!("Failed to load headers: {e}");
+                exit(1);
+            }
+        }
+    } else {
+        HeaderChain::new(difficulty)
+    };
+    // the difficulty adjustment is not saved with the headers
+    chain.set_difficulty(difficulty);
+
+    let mut stream = match TcpStream::connect(node_address) {
+        Ok(stream) => stream,
+        Err(e) => {
+            eprintln!("Failed to connect to node: {e}");
+            exit(1);
+        }
+    };
+    sync_headers(&mut stream, &mut chain);
+    if let Err(e) = chain.save_to_file(headers_file) {
+        eprintln!("Failed to save headers: {e}");
+        exit(1);
+    }
+    println!(
+        "Synced {} headers with {} work",
+        chain.height(),
+        chain.chainwork()
+    );
+
+    // outputs paying the address, until a later transaction spends them
+    let mut outputs = HashMap::new();
+    let mut prev_filter_header = Hash::zero();
+    let mut height = 0;
+    while height < chain.height() {
+        let count = chain.height() - height;
+        let Message::CFHeaders(filter_headers) =
+            request(&mut stream, Message::GetCFHeaders(height, count))
+        else {
+            eprintln!("Unexpected response to GetCFHeaders");
+            exit(1);
+        };
+        if filter_headers.is_empty() {
+            eprintln!("Node has no filters from {height} on");
+            exit(1);
+        }
+        // receive the whole batch before asking for blocks
+        if let Err(e) = Message::GetCFilters(height, filter_headers.len() as u64).send(&mut stream)
+        {
+            eprintln!("Failed to send request: {e}");
+            exit(1);
+        }
+        let filters = filter_headers
+            .iter()
+            .map(|_| {
+                match Message::receive(&mut stream) {
+                    Ok(Message::CFilter(block_hash, filter)) => (block_hash, filter),
+                    Err(e) => {
+                        eprintln!("Failed to receive filter: {e}");
+                        exit(1);
+                    }
+                    Ok(_) => {
+                        eprintln!("Unexpected response to GetCFilters");
+                        exit(1);
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+
+        for ((block_hash, filter), filter_header) in filters.into_iter().zip(filter_headers) {
+            // the filter has to be the one of the chain's block that
+            // the node committed to
+            if chain.header(height).map(BlockHeader::hash) != Some(block_hash)
+                || filter.header(&prev_filter_header) != filter_header
+            {
+                eprintln!("Node sent an invalid filter at {height}");
+                exit(1);
+            }
+            prev_filter_header = filter_header;
+
+            let mut elements = vec![pubkey_hash_element(&address.pubkey_hash)];
+            elements.extend(outputs.keys().map(outpoint_element));
+            if filter.matches_any(&block_hash, &elements) {
+                let outpoints = outputs.keys().copied().collect();
+                let transactions = fetch_filtered_block(
+                    &mut stream,
+                    &chain,
+                    height,
+                    address.pubkey_hash,
+                    outpoints,
+                );
+                for transaction in transactions.iter() {
+                    let mut relevant = false;
+                    for input in transaction.inputs.iter() {
+                        relevant |= outputs.remove(&input.prev_tx_output_hash).is_some();
+                    }
+                    for output in transaction.outputs.iter() {
+                        if output.lock.pubkey_hash() == Some(address.pubkey_hash) {
+                            outputs.insert(output.hash(), output.value);
+                            relevant = true;
+                        }
+                    }
+                    if relevant {
+                        println!("Block {height}: transaction {}", transaction.hash());
+                    }
+                }
+            }
+            height += 1;
+        }
+    }
+    println!(
+        "Confirmed balance: {}",
+        outputs.values().copied().sum::<Amount>()
+    );
+}
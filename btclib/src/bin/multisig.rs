@@ -0,0 +1,187 @@
+use {
+    btclib::{
+        address::Address,
+        crypto::{
+            PrivateKey,
+            PublicKey,
+            Signature,
+        },
+        types::{
+            amount::Amount,
+            partial_transaction::PartialTransaction,
+            transaction::{
+                Lock,
+                Transaction,
+                TransactionInput,
+                TransactionOutput,
+            },
+        },
+        util::Saveable,
+    },
+    std::{
+        env,
+        process::exit,
+    },
+    uuid::Uuid,
+};
+
+const USAGE: &str = "Usage:
+    multisig fund <tx_file> <funding_tx_file> <private_key_file> <fee_sats> <threshold> <public_key_file>...
+    multisig new <partial_file> <funding_tx_file> <address> <fee_sats>
+    multisig sign <partial_file> <private_key_file>
+    multisig combine <partial_file> <other_partial_file>...
+    multisig finalize <partial_file> <tx_file>";
+
+fn usage() -> ! {
+    eprintln!("{USAGE}");
+    exit(1);
+}
+
+fn load_partial(path: &str) -> PartialTransaction {
+    PartialTransaction::load_from_file(path).expect("Failed to load partial transaction")
+}
+
+fn save_partial(partial: &PartialTransaction, path: &str) {
+    partial
+        .save_to_file(path)
+        .expect("Failed to save partial transaction");
+    println!("{} signatures missing", partial.missing_signatures());
+}
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        // spend the outputs of a transaction that belong to the
+        // private key to the multisig keys
+        ["fund", tx_file, funding_tx_file, private_key_file, fee, threshold, ref public_key_files @ ..]
+            if !public_key_files.is_empty() =>
+        {
+            let funding =
+                Transaction::load_from_file(funding_tx_file).expect("Failed to load transaction");
+            let private_key =
+                PrivateKey::load_from_file(private_key_file).expect("Failed to load private key");
+            let fee = Amount::from_sat(fee.parse().unwrap_or_else(|_| usage()));
+            let threshold = threshold.parse::<usize>().unwrap_or_else(|_| usage());
+            let pubkeys = public_key_files
+                .iter()
+                .map(|file| PublicKey::load_from_file(file).expect("Failed to load public key"))
+                .collect::<Vec<_>>();
+            let Some(lock) = Lock::multisig(threshold, pubkeys) else {
+                eprintln!(
+                    "Threshold has to be between 1 and the amount of keys, which have to be \
+                     different and at most {}",
+                    btclib::MAX_MULTISIG_KEYS
+                );
+                exit(1);
+            };
+            let public_key = private_key.public_key();
+            let spent = funding
+                .outputs
+                .into_iter()
+                .filter(|output| output.lock.pubkey_hash() == Some(public_key.pubkey_hash()))
+                .collect::<Vec<_>>();
+            if spent.is_empty() {
+                eprintln!("The transaction pays nothing to the private key");
+                exit(1);
+            }
+            let value = Amount::checked_sum(spent.iter().map(|output| output.value))
+                .and_then(|value| value.checked_sub(fee))
+                .unwrap_or_else(|| {
+                    eprintln!("Fee is more than the outputs are worth");
+                    exit(1);
+                });
+            let inputs = spent
+                .iter()
+                .map(|output| {
+                    TransactionInput {
+                        prev_tx_output_hash: output.hash(),
+                        signature: Signature::sign(&output.hash(), &private_key),
+                        sequence: btclib::SEQUENCE_FINAL,
+                        // paying an address only commits to the key's
+                        // hash, so the key has to be revealed
+                        pubkey: matches!(output.lock, Lock::PubkeyHash(_))
+                            .then(|| public_key.clone()),
+                        unlock: Default::default(),
+                        extra_signatures: vec![],
+                    }
+                })
+                .collect();
+            let mut transaction = Transaction::new(
+                inputs,
+                vec![TransactionOutput {
+                    unique_id: Uuid::new_v4(),
+                    value,
+                    lock,
+                }],
+            );
+            let signature_hashes = transaction.signature_hashes();
+            for (input, signature_hash) in transaction.inputs.iter_mut().zip(signature_hashes) {
+                input.signature = Signature::sign(&signature_hash, &private_key);
+            }
+            transaction
+                .save_to_file(tx_file)
+                .expect("Failed to save transaction");
+        }
+        // spend the multisig outputs of a transaction to an address
+        ["new", partial_file, funding_tx_file, address, fee] => {
+            let funding =
+                Transaction::load_from_file(funding_tx_file).expect("Failed to load transaction");
+            let address = address.parse::<Address>().unwrap_or_else(|e| {
+                eprintln!("Invalid address: {e}");
+                exit(1);
+            });
+            let fee = Amount::from_sat(fee.parse().unwrap_or_else(|_| usage()));
+            let spent = funding
+                .outputs
+                .into_iter()
+                .filter(|output| matches!(output.lock, Lock::Multisig { .. }))
+                .collect::<Vec<_>>();
+            let value = Amount::checked_sum(spent.iter().map(|output| output.value))
+                .and_then(|value| value.checked_sub(fee))
+                .unwrap_or_else(|| {
+                    eprintln!("Fee is more than the multisig outputs are worth");
+                    exit(1);
+                });
+            let output = TransactionOutput {
+                unique_id: Uuid::new_v4(),
+                value,
+                lock: address.into(),
+            };
+            let partial = PartialTransaction::new(&spent, vec![output])
+                .expect("Failed to create partial transaction");
+            save_partial(&partial, partial_file);
+        }
+        ["sign", partial_file, private_key_file] => {
+            let mut partial = load_partial(partial_file);
+            let private_key =
+                PrivateKey::load_from_file(private_key_file).expect("Failed to load private key");
+            let signed = partial.sign(&private_key);
+            println!("Signed {signed} inputs");
+            save_partial(&partial, partial_file);
+        }
+        ["combine", partial_file, ref other_files @ ..] if !other_files.is_empty() => {
+            let mut partial = load_partial(partial_file);
+            for other_file in other_files {
+                if let Err(e) = partial.combine(&load_partial(other_file)) {
+                    eprintln!("{other_file} is not the same transaction: {e}");
+                    exit(1);
+                }
+            }
+            save_partial(&partial, partial_file);
+        }
+        ["finalize", partial_file, tx_file] => {
+            let partial = load_partial(partial_file);
+            let Some(transaction) = partial.finalize() else {
+                eprintln!(
+                    "{} signatures are still missing",
+                    partial.missing_signatures()
+                );
+                exit(1);
+            };
+            transaction
+                .save_to_file(tx_file)
+                .expect("Failed to save transaction");
+        }
+        _ => usage(),
+    }
+}
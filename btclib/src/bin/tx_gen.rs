@@ -1,9 +1,14 @@
 use {
     btclib::{
+        address::Address,
         crypto::PrivateKey,
-        types::transaction::{
-            Transaction,
-            TransactionOutput,
+        types::{
+            amount::Amount,
+            transaction::{
+                Lock,
+                Transaction,
+                TransactionOutput,
+            },
         },
         util::Saveable,
     },
@@ -17,16 +22,24 @@ fn main() {
     let path = if let Some(arg) = env::args().nth(1) {
         arg
     } else {
-        eprintln!("Usage: tx_gen <tx_file>");
+        eprintln!("Usage: tx_gen <tx_file> [address]");
         exit(1);
     };
-    let private_key = PrivateKey::new_key();
+    // pay the address if there is one, a new key otherwise
+    let lock = match env::args().nth(2).map(|arg| arg.parse::<Address>()) {
+        None => Lock::PubkeyHash(PrivateKey::new_key().public_key().pubkey_hash()),
+        Some(Ok(address)) => Lock::from(address),
+        Some(Err(e)) => {
+            eprintln!("Invalid address: {e}");
+            exit(1);
+        }
+    };
     let transaction = Transaction::new(
         vec![],
         vec![TransactionOutput {
             unique_id: Uuid::new_v4(),
-            value: btclib::INITIAL_REWARD * 10u64.pow(8),
-            pubkey: private_key.public_key(),
+            value: Amount::from_btc(btclib::INITIAL_REWARD),
+            lock,
         }],
     );
     transaction
@@ -1,5 +1,6 @@
 use {
     btclib::{
+        address::Network,
         types::transaction::Transaction,
         util::Saveable,
     },
@@ -13,11 +14,21 @@ fn main() {
     let path = if let Some(arg) = env::args().nth(1) {
         arg
     } else {
-        eprintln!("Usage: tx_print <tx_file>");
+        eprintln!("Usage: tx_print <tx_file> [network]");
         exit(1);
     };
+    let network = env::args()
+        .nth(2)
+        .map(|arg| arg.parse::<Network>().expect("Invalid network"))
+        .unwrap_or_default();
     if let Ok(file) = File::open(path) {
         let tx = Transaction::load(file).expect("Failed to load transaction");
         println!("{:#?}", tx);
+        for output in tx.outputs.iter() {
+            match output.lock.address(network) {
+                Some(address) => println!("{} to {address}", output.value),
+                None => println!("{} to a script or multisig", output.value),
+            }
+        }
     }
 }
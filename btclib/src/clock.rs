@@ -0,0 +1,131 @@
+use {
+    chrono::{
+        DateTime,
+        Duration,
+        Utc,
+    },
+    std::{
+        fmt::Debug,
+        sync::Mutex,
+    },
+};
+
+/// Source of the current time that block timestamps are checked
+/// against, so that nodes can plug in network-adjusted time and
+/// simulations a time of their own
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The system clock
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock that always reports the same time
+#[derive(Debug, Clone, Copy)]
+pub struct FixedClock(pub DateTime<Utc>);
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.0
+    }
+}
+
+/// The system clock moved by the median offset of other nodes'
+/// clocks, like Bitcoin's network-adjusted time. Nothing moves until
+/// MIN_TIME_SAMPLES nodes reported their time, and a median beyond
+/// MAX_TIME_ADJUSTMENT is ignored rather than followed
+#[derive(Debug, Default)]
+pub struct NetworkClock {
+    // offsets of the other nodes' clocks in seconds, the first
+    // MAX_TIME_SAMPLES reported
+    offsets: Mutex<Vec<i64>>,
+}
+
+impl NetworkClock {
+    // record the time another node reported
+    pub fn add_sample(&self, time: DateTime<Utc>) {
+        self.add_offset((time - Utc::now()).num_seconds());
+    }
+
+    fn add_offset(&self, offset: i64) {
+        let mut offsets = self.offsets.lock().unwrap();
+        if offsets.len() < crate::MAX_TIME_SAMPLES {
+            offsets.push(offset);
+        }
+    }
+
+    // how far the clock is moved from the system clock
+    pub fn offset(&self) -> Duration {
+        let mut offsets = self.offsets.lock().unwrap().clone();
+        if offsets.len() < crate::MIN_TIME_SAMPLES {
+            return Duration::zero();
+        }
+        offsets.sort();
+        let median = offsets[offsets.len() / 2];
+        if median.unsigned_abs() > crate::MAX_TIME_ADJUSTMENT {
+            return Duration::zero();
+        }
+        Duration::seconds(median)
+    }
+}
+
+impl Clock for NetworkClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now() + self.offset()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn network_time_follows_the_median_offset() {
+        let clock = NetworkClock::default();
+        for offset in [60, -30, 600, 45] {
+            clock.add_offset(offset);
+        }
+        // too few samples to move at all
+        assert_eq!(clock.offset(), Duration::zero());
+        clock.add_offset(50);
+        assert_eq!(clock.offset(), Duration::seconds(50));
+        // a single node can not drag the median along
+        clock.add_offset(-100_000);
+        assert_eq!(clock.offset(), Duration::seconds(50));
+    }
+
+    #[test]
+    fn network_time_ignores_large_offsets() {
+        let clock = NetworkClock::default();
+        let limit = crate::MAX_TIME_ADJUSTMENT as i64;
+        for _ in 0..crate::MIN_TIME_SAMPLES {
+            clock.add_offset(limit);
+        }
+        assert_eq!(clock.offset(), Duration::seconds(limit));
+
+        let clock = NetworkClock::default();
+        for _ in 0..crate::MIN_TIME_SAMPLES {
+            clock.add_offset(-limit - 1);
+        }
+        assert_eq!(clock.offset(), Duration::zero());
+    }
+
+    #[test]
+    fn network_time_keeps_the_first_samples() {
+        let clock = NetworkClock::default();
+        for _ in 0..crate::MAX_TIME_SAMPLES {
+            clock.add_offset(10);
+        }
+        for _ in 0..crate::MAX_TIME_SAMPLES {
+            clock.add_offset(20);
+        }
+        assert_eq!(clock.offset(), Duration::seconds(10));
+    }
+}
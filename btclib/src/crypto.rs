@@ -1,8 +1,10 @@
 use {
     crate::{
+        address::PubkeyHash,
         sha256::Hash,
         util::Saveable,
     },
+    ciborium::Value,
     ecdsa::{
         signature::{
             Signer,
@@ -12,7 +14,38 @@ use {
         SigningKey,
         VerifyingKey,
     },
-    k256::Secp256k1,
+    k256::{
+        elliptic_curve::{
+            group::Group,
+            ops::{
+                LinearCombinationExt,
+                Reduce,
+            },
+            scalar::IsHigh,
+            Field,
+            PrimeField,
+        },
+        schnorr::{
+            self,
+            Signature as SchnorrSignature,
+        },
+        sha2::{
+            Digest,
+            Sha256,
+        },
+        FieldBytes,
+        ProjectivePoint,
+        Scalar,
+        Secp256k1,
+        U256 as K256U256,
+    },
+    serde::{
+        de::Error as _,
+        Deserialize,
+        Deserializer,
+        Serialize,
+        Serializer,
+    },
     spki::EncodePublicKey,
     std::io::{
         Error as IoError,
@@ -23,29 +56,270 @@ use {
     },
 };
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Signature(pub ECDSASignature<Secp256k1>);
+/// Signature of a spent output's hash, either ECDSA or BIP340
+/// Schnorr. Both verify against the same public keys, Schnorr ones
+/// against the key's x coordinate only
+#[derive(Debug, Clone)]
+pub enum Signature {
+    Ecdsa(ECDSASignature<Secp256k1>),
+    Schnorr(SchnorrSignature),
+}
+
+// marks Schnorr signatures in scripts and in the serialized form
+const SCHNORR_TAG: &str = "schnorr";
+
+// ECDSA signatures serialize as they did before there were Schnorr
+// ones, which keeps old transaction hashes. Schnorr ones are a map,
+// which ECDSA ones never are
+impl Serialize for Signature {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Signature::Ecdsa(signature) => signature.serialize(serializer),
+            Signature::Schnorr(signature) => {
+                Value::Map(vec![(
+                    Value::Text(SCHNORR_TAG.to_string()),
+                    Value::Bytes(signature.to_bytes().to_vec()),
+                )])
+                .serialize(serializer)
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Signature {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        match Value::deserialize(deserializer)? {
+            Value::Map(entries) => {
+                match &entries[..] {
+                    [(Value::Text(tag), Value::Bytes(bytes))] if tag == SCHNORR_TAG => {
+                        SchnorrSignature::try_from(&bytes[..])
+                            .map(Signature::Schnorr)
+                            .map_err(D::Error::custom)
+                    }
+                    _ => Err(D::Error::custom("invalid Schnorr signature")),
+                }
+            }
+            value => {
+                value
+                    .deserialized()
+                    .map(Signature::Ecdsa)
+                    .map_err(D::Error::custom)
+            }
+        }
+    }
+}
 
 impl Signature {
-    // sign a crate::types::TransactionOutput from its Sha256 hash
-    pub fn sign_output(output_hash: &Hash, private_key: &PrivateKey) -> Self {
+    // sign a transaction input by its signature hash (see
+    // `Transaction::signature_hashes`). The nonce is derived from
+    // the key and the hash (RFC6979), so signing the same input twice
+    // gives the same signature, and s is always the low one
+    pub fn sign(signature_hash: &Hash, private_key: &PrivateKey) -> Self {
         let signing_key = &private_key.0;
-        let signature = signing_key.sign(&output_hash.as_bytes());
-        Signature(signature)
+        let signature = signing_key.sign(&signature_hash.as_bytes());
+        Signature::Ecdsa(signature)
+    }
+
+    // sign the signature hash itself with BIP340, using fresh
+    // auxiliary randomness
+    pub fn sign_schnorr(signature_hash: &Hash, private_key: &PrivateKey) -> Self {
+        let signing_key = schnorr::SigningKey::from(*private_key.0.as_nonzero_scalar());
+        let aux_rand = rand::random::<[u8; 32]>();
+        let signature = signing_key
+            .sign_raw(&signature_hash.as_bytes(), &aux_rand)
+            .expect("signing a 32 byte message cannot fail");
+        Signature::Schnorr(signature)
     }
 
     // verify signature
-    pub fn verify(&self, output_hash: &Hash, public_key: &PublicKey) -> bool {
-        public_key
-            .0
-            .verify(&output_hash.as_bytes(), &self.0)
-            .is_ok()
+    pub fn verify(&self, signature_hash: &Hash, public_key: &PublicKey) -> bool {
+        match self {
+            Signature::Ecdsa(signature) => {
+                public_key
+                    .0
+                    .verify(&signature_hash.as_bytes(), signature)
+                    .is_ok()
+            }
+            Signature::Schnorr(signature) => {
+                public_key
+                    .schnorr_key()
+                    .verify_raw(&signature_hash.as_bytes(), signature)
+                    .is_ok()
+            }
+        }
+    }
+
+    // whether s is at most half the group order. BIP340 signatures
+    // cannot be altered that way, so they always are
+    pub fn is_low_s(&self) -> bool {
+        match self {
+            Signature::Ecdsa(signature) => !bool::from(signature.s().is_high()),
+            Signature::Schnorr(_) => true,
+        }
+    }
+
+    // the same signature with the low s, which is valid wherever
+    // the original one would be if high s were allowed
+    pub fn normalize_s(&self) -> Self {
+        match self {
+            Signature::Ecdsa(signature) => {
+                Signature::Ecdsa(signature.normalize_s().unwrap_or(*signature))
+            }
+            Signature::Schnorr(_) => self.clone(),
+        }
+    }
+
+    pub fn is_schnorr(&self) -> bool {
+        matches!(self, Signature::Schnorr(_))
+    }
+
+    // the 64 bytes of r and s, as scripts see signatures. Schnorr
+    // signatures get a trailing zero byte to tell them apart
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Signature::Ecdsa(signature) => signature.to_bytes().to_vec(),
+            Signature::Schnorr(signature) => {
+                let mut bytes = signature.to_bytes().to_vec();
+                bytes.push(0);
+                bytes
+            }
+        }
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        match bytes {
+            [signature @ .., 0] if signature.len() == 64 => {
+                SchnorrSignature::try_from(signature)
+                    .ok()
+                    .map(Signature::Schnorr)
+            }
+            _ => ECDSASignature::from_slice(bytes).ok().map(Signature::Ecdsa),
+        }
     }
 }
 
+/// Signatures checked together. ECDSA signatures are verified right
+/// away, Schnorr ones are collected and verified at once by
+/// `verify`, which is faster than one by one
+#[derive(Debug, Default)]
+pub struct SignatureBatch {
+    schnorr: Vec<(Hash, PublicKey, SchnorrSignature)>,
+}
+
+impl SignatureBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // verify an ECDSA signature, or add a Schnorr one to the batch
+    // and assume it is valid until the batch is verified
+    pub fn check(
+        &mut self,
+        signature: &Signature,
+        signature_hash: &Hash,
+        public_key: &PublicKey,
+    ) -> bool {
+        match signature {
+            Signature::Ecdsa(_) => signature.verify(signature_hash, public_key),
+            Signature::Schnorr(schnorr) => {
+                self.schnorr
+                    .push((*signature_hash, public_key.clone(), *schnorr));
+                true
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.schnorr.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.schnorr.is_empty()
+    }
+
+    // whether every Schnorr signature of the batch is valid, by
+    // checking a random linear combination of their equations
+    // s * G = R + e * P. An invalid signature passes only with
+    // negligible probability
+    pub fn verify(&self) -> bool {
+        if let [(hash, public_key, signature)] = &self.schnorr[..] {
+            return Signature::Schnorr(*signature).verify(hash, public_key);
+        }
+        let mut rng = rand::thread_rng();
+        let mut terms = Vec::with_capacity(2 * self.schnorr.len() + 1);
+        let mut s_sum = Scalar::ZERO;
+        for (i, (hash, public_key, signature)) in self.schnorr.iter().enumerate() {
+            let bytes = signature.to_bytes();
+            let Ok(r) = schnorr::VerifyingKey::from_bytes(&bytes[..32]) else {
+                return false;
+            };
+            let Some(s) = Option::<Scalar>::from(Scalar::from_repr(FieldBytes::clone_from_slice(
+                &bytes[32..],
+            ))) else {
+                return false;
+            };
+            let public_key = public_key.schnorr_key();
+            let e = challenge(&bytes[..32], &public_key.to_bytes(), &hash.as_bytes());
+            // the first coefficient can be 1 without weakening the check
+            let a = if i == 0 {
+                Scalar::ONE
+            } else {
+                Scalar::random(&mut rng)
+            };
+            s_sum += a * s;
+            terms.push((ProjectivePoint::from(*r.as_affine()), -a));
+            terms.push((ProjectivePoint::from(*public_key.as_affine()), -(a * e)));
+        }
+        terms.push((ProjectivePoint::GENERATOR, s_sum));
+        ProjectivePoint::lincomb_ext(&terms[..])
+            .is_identity()
+            .into()
+    }
+}
+
+// BIP340 tagged hash: sha256(sha256(tag) || sha256(tag) || data...)
+pub fn tagged_hash(tag: &str, data: &[&[u8]]) -> [u8; 32] {
+    let tag_hash = Sha256::digest(tag.as_bytes());
+    let mut hasher = Sha256::new();
+    hasher.update(tag_hash);
+    hasher.update(tag_hash);
+    for data in data {
+        hasher.update(data);
+    }
+    hasher.finalize().into()
+}
+
+// BIP340 challenge of a signature's nonce `r` for a key's x
+// coordinate and a message
+pub fn challenge(r: &[u8], public_key: &[u8], message: &[u8]) -> Scalar {
+    let hash = tagged_hash("BIP0340/challenge", &[r, public_key, message]);
+    <Scalar as Reduce<K256U256>>::reduce_bytes(&hash.into())
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct PublicKey(pub VerifyingKey<Secp256k1>);
 
+impl PublicKey {
+    pub fn pubkey_hash(&self) -> PubkeyHash {
+        PubkeyHash::digest(&self.to_bytes())
+    }
+
+    // the compressed SEC1 encoding, as scripts see public keys
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.0.to_encoded_point(true).as_bytes().to_vec()
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        VerifyingKey::from_sec1_bytes(bytes).ok().map(PublicKey)
+    }
+
+    // the key by its x coordinate only, as Schnorr signatures use it
+    pub fn schnorr_key(&self) -> schnorr::VerifyingKey {
+        schnorr::VerifyingKey::from_bytes(&self.to_bytes()[1..])
+            .expect("x coordinate of a valid key")
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PrivateKey(#[serde(with = "signkey_serde")] pub SigningKey<Secp256k1>);
 
@@ -117,3 +391,87 @@ impl Saveable for PublicKey {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a batch of Schnorr signatures by different keys of different
+    // hashes, the one at `bad` signing the wrong hash
+    fn batch(count: usize, bad: Option<usize>) -> SignatureBatch {
+        let mut batch = SignatureBatch::new();
+        for i in 0..count {
+            let private_key = PrivateKey::new_key();
+            let hash = Hash::hash(&i);
+            let signed = if Some(i) == bad {
+                Hash::hash(&"wrong")
+            } else {
+                hash
+            };
+            let signature = Signature::sign_schnorr(&signed, &private_key);
+            assert!(batch.check(&signature, &hash, &private_key.public_key()));
+        }
+        batch
+    }
+
+    #[test]
+    fn batch_verifies_valid_signatures() {
+        assert!(SignatureBatch::new().verify());
+        for count in [1, 2, 10] {
+            assert_eq!(batch(count, None).len(), count);
+            assert!(batch(count, None).verify());
+        }
+    }
+
+    #[test]
+    fn batch_rejects_one_bad_signature() {
+        for (count, bad) in [(1, 0), (2, 1), (10, 0), (10, 9)] {
+            assert!(!batch(count, Some(bad)).verify());
+        }
+    }
+
+    #[test]
+    fn signing_is_deterministic_and_low_s() {
+        let private_key = PrivateKey::new_key();
+        for i in 0..20 {
+            let hash = Hash::hash(&i);
+            let signature = Signature::sign(&hash, &private_key);
+            assert_eq!(
+                signature.to_bytes(),
+                Signature::sign(&hash, &private_key).to_bytes()
+            );
+            assert!(signature.is_low_s());
+        }
+    }
+
+    #[test]
+    fn normalizing_gives_a_verifying_low_s_signature() {
+        let private_key = PrivateKey::new_key();
+        let hash = Hash::hash(&"message");
+        let low = Signature::sign(&hash, &private_key);
+        let Signature::Ecdsa(signature) = &low else {
+            unreachable!();
+        };
+        let (r, s) = signature.split_scalars();
+        let high = Signature::Ecdsa(ECDSASignature::from_scalars(r, -*s).unwrap());
+        assert!(!high.is_low_s());
+        assert!(!high.verify(&hash, &private_key.public_key()));
+
+        let normalized = high.normalize_s();
+        assert!(normalized.is_low_s());
+        assert!(normalized.verify(&hash, &private_key.public_key()));
+        assert_eq!(normalized.to_bytes(), low.to_bytes());
+        assert_eq!(low.normalize_s().to_bytes(), low.to_bytes());
+    }
+
+    #[test]
+    fn batch_checks_ecdsa_right_away() {
+        let private_key = PrivateKey::new_key();
+        let hash = Hash::hash(&"message");
+        let signature = Signature::sign(&hash, &private_key);
+        let mut batch = SignatureBatch::new();
+        assert!(batch.check(&signature, &hash, &private_key.public_key()));
+        assert!(!batch.check(&signature, &hash, &PrivateKey::new_key().public_key()));
+        assert!(batch.is_empty());
+    }
+}
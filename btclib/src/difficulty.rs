@@ -0,0 +1,378 @@
+use {
+    crate::{
+        types::block_header::BlockHeader,
+        U256,
+    },
+    std::{
+        fmt::Debug,
+        str::FromStr,
+    },
+};
+
+/// Computes the target of the next block from the headers of the
+/// chain, oldest first
+pub trait DifficultyAlgorithm: Debug + Send + Sync {
+    fn next_target(&self, headers: &[&BlockHeader]) -> U256;
+}
+
+/// Difficulty adjustment algorithm of a chain. Every node of a
+/// network has to use the same one
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DifficultyAdjustment {
+    /// Retarget every `DIFFICULTY_UPDATE_INTERVAL` blocks
+    #[default]
+    Epoch,
+    /// Retarget every block from a weighted average of the last
+    /// `LWMA_WINDOW` blocks
+    Lwma,
+    /// Retarget every block exponentially from how far the chain is
+    /// ahead or behind schedule
+    Asert,
+}
+
+impl DifficultyAdjustment {
+    pub fn algorithm(&self) -> Box<dyn DifficultyAlgorithm> {
+        match self {
+            DifficultyAdjustment::Epoch => {
+                Box::new(Epoch {
+                    interval: crate::DIFFICULTY_UPDATE_INTERVAL,
+                    block_time: crate::IDEAL_BLOCK_TIME,
+                })
+            }
+            DifficultyAdjustment::Lwma => {
+                Box::new(Lwma {
+                    window: crate::LWMA_WINDOW,
+                    block_time: crate::IDEAL_BLOCK_TIME,
+                })
+            }
+            DifficultyAdjustment::Asert => {
+                Box::new(Asert {
+                    half_life: crate::ASERT_HALF_LIFE,
+                    block_time: crate::IDEAL_BLOCK_TIME,
+                })
+            }
+        }
+    }
+}
+
+impl FromStr for DifficultyAdjustment {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "epoch" => Ok(DifficultyAdjustment::Epoch),
+            "lwma" => Ok(DifficultyAdjustment::Lwma),
+            "asert" => Ok(DifficultyAdjustment::Asert),
+            _ => {
+                Err(format!(
+                    "unknown difficulty adjustment {s}, expected epoch, lwma or asert"
+                ))
+            }
+        }
+    }
+}
+
+/// Scale the target by the time the last `interval` blocks took,
+/// changing it by a factor of 4 at most
+#[derive(Debug, Clone, Copy)]
+pub struct Epoch {
+    pub interval: u64,
+    pub block_time: u64,
+}
+
+impl DifficultyAlgorithm for Epoch {
+    fn next_target(&self, headers: &[&BlockHeader]) -> U256 {
+        let Some(last_header) = headers.last() else {
+            return crate::MIN_TARGET;
+        };
+        if !(headers.len() as u64).is_multiple_of(self.interval) {
+            return last_header.target();
+        }
+
+        //measure the time it took to mine the last interval blocks
+        let start_time = headers[headers.len() - self.interval as usize].timestamp;
+        let time_diff = last_header.timestamp - start_time;
+
+        //convert time_diff to seconds, timestamps only have to
+        // exceed the median time past so the difference can be negative
+        let time_diff_seconds = time_diff.num_seconds().max(0) as u64;
+        // calcualte the ideal number of seconds
+        let target_seconds = self.block_time * self.interval;
+        // clamp the time so that the target changes by a factor of 4 at most
+        let time_diff_seconds = time_diff_seconds.clamp(target_seconds / 4, target_seconds * 4);
+        // multiply the current target by actual time didvided by ideal time
+        let new_target = scale_target(last_header.target(), time_diff_seconds, target_seconds);
+
+        // if the new target is more than the minimum target, set it to the minimum
+        // target
+        new_target.min(crate::MIN_TARGET)
+    }
+}
+
+/// Linearly weighted moving average (zawy12's LWMA-1): the average
+/// target of the last `window` blocks, scaled by their solve times
+/// with recent blocks weighing more
+#[derive(Debug, Clone, Copy)]
+pub struct Lwma {
+    pub window: u64,
+    pub block_time: u64,
+}
+
+impl DifficultyAlgorithm for Lwma {
+    fn next_target(&self, headers: &[&BlockHeader]) -> U256 {
+        let window = self.window as usize;
+        let Some(last_header) = headers.last() else {
+            return crate::MIN_TARGET;
+        };
+        // keep the target until there are enough blocks
+        if headers.len() <= window {
+            return last_header.target();
+        }
+
+        let mut previous_time = headers[headers.len() - window - 1].timestamp.timestamp();
+        let mut weighted_time = 0;
+        let mut target_sum = U256::zero();
+        for (weight, header) in (1..).zip(headers[headers.len() - window..].iter()) {
+            // out of order timestamps count as one second, and very
+            // slow blocks as six ideal block times
+            let time = header.timestamp.timestamp().max(previous_time + 1);
+            let solve_time = ((time - previous_time) as u64).min(6 * self.block_time);
+            previous_time = time;
+            weighted_time += solve_time * weight;
+            target_sum = target_sum.saturating_add(header.target());
+        }
+
+        // sum of the weights times the ideal block time
+        let ideal_weighted_time = self.window * (self.window + 1) / 2 * self.block_time;
+        let average_target = target_sum / U256::from(self.window);
+        scale_target(average_target, weighted_time, ideal_weighted_time)
+            .clamp(U256::one(), crate::MIN_TARGET)
+    }
+}
+
+/// Absolutely scheduled exponentially rising targets (aserti3-2d):
+/// the target doubles for every `half_life` seconds the chain falls
+/// behind the schedule set by the genesis block, and halves for
+/// every `half_life` seconds it gets ahead
+#[derive(Debug, Clone, Copy)]
+pub struct Asert {
+    pub half_life: u64,
+    pub block_time: u64,
+}
+
+impl DifficultyAlgorithm for Asert {
+    fn next_target(&self, headers: &[&BlockHeader]) -> U256 {
+        let (Some(anchor), Some(last_header)) = (headers.first(), headers.last()) else {
+            return crate::MIN_TARGET;
+        };
+        // aserti3-2d measures time from the anchor's parent and adds
+        // one block to the height difference for it. The genesis
+        // block has no parent, so time is measured from the anchor
+        // itself, which is the same as taking its parent to be one
+        // block time older
+        let time_diff = (last_header.timestamp - anchor.timestamp).num_seconds();
+        let height_diff = headers.len() as i64 - 1;
+        // how far the chain is behind schedule, in half lives as a
+        // 16.16 fixed point number
+        let exponent =
+            (time_diff - self.block_time as i64 * height_diff) * 65536 / self.half_life as i64;
+        let shifts = exponent >> 16;
+        let fraction = (exponent & 0xFFFF) as u128;
+        // 2^fraction approximated by a cubic polynomial, again as a
+        // 16.16 fixed point number
+        let factor = 65536
+            + ((195_766_423_245_049 * fraction
+                + 971_821_376 * fraction * fraction
+                + 5127 * fraction * fraction * fraction
+                + (1 << 47))
+                >> 48) as u64;
+
+        let target = scale_target(anchor.target(), factor, 65536);
+        let target = if shifts < 0 {
+            target >> (-shifts) as usize
+        } else if target.bits() as i64 + shifts > 255 {
+            crate::MIN_TARGET
+        } else {
+            target << shifts as usize
+        };
+        target.clamp(U256::one(), crate::MIN_TARGET)
+    }
+}
+
+// target * numerator / denominator, rounded down. Dividing first
+// keeps the product from overflowing, and the remainder's share
+// keeps the result exact
+pub fn scale_target(target: U256, numerator: u64, denominator: u64) -> U256 {
+    let (numerator, denominator) = (U256::from(numerator), U256::from(denominator));
+    let (quotient, remainder) = target.div_mod(denominator);
+    quotient
+        .saturating_mul(numerator)
+        .saturating_add(remainder * numerator / denominator)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            sha256::Hash,
+            util::MerkleRoot,
+        },
+        chrono::{
+            Duration,
+            Utc,
+        },
+    };
+
+    // blocks mined in every phase of a simulation
+    const PHASE_BLOCKS: usize = 3000;
+
+    // a genesis header this much harder than the minimum target, so
+    // that the target can also go up when miners leave
+    fn genesis() -> BlockHeader {
+        BlockHeader::new(
+            Utc::now(),
+            0,
+            Hash::zero(),
+            MerkleRoot::calculate(&[]),
+            crate::MIN_TARGET / 1000,
+        )
+    }
+
+    // mine `blocks` blocks, each taking exactly the expected time of
+    // `hashrate` at its target, and return the average solve time of
+    // the last quarter of them
+    fn mine(
+        algorithm: &dyn DifficultyAlgorithm,
+        headers: &mut Vec<BlockHeader>,
+        hashrate: f64,
+        blocks: usize,
+    ) -> f64 {
+        let mut solve_times = vec![];
+        for _ in 0..blocks {
+            let target = algorithm.next_target(&headers.iter().collect::<Vec<_>>());
+            let last = headers.last().unwrap();
+            let mut header = BlockHeader::new(
+                last.timestamp,
+                0,
+                last.hash(),
+                last.merkle_root.clone(),
+                target,
+            );
+            let solve_time = header.work().low_u128() as f64 / hashrate;
+            header.timestamp += Duration::milliseconds((solve_time * 1000.0) as i64);
+            headers.push(header);
+            solve_times.push(solve_time);
+        }
+        let tail = &solve_times[blocks * 3 / 4..];
+        tail.iter().sum::<f64>() / tail.len() as f64
+    }
+
+    // the average block time has to get back to within 20% of the
+    // ideal one after the hashrate rises tenfold and after it drops
+    // to a tenth of the original
+    fn assert_converges(difficulty: DifficultyAdjustment) {
+        let algorithm = difficulty.algorithm();
+        let mut headers = vec![genesis()];
+        let hashrate = headers[0].work().low_u128() as f64 / crate::IDEAL_BLOCK_TIME as f64;
+        let ideal = crate::IDEAL_BLOCK_TIME as f64;
+        for (phase, multiplier) in [("steady", 1.0), ("spike", 10.0), ("drop", 0.1)] {
+            let average = mine(
+                &*algorithm,
+                &mut headers,
+                hashrate * multiplier,
+                PHASE_BLOCKS,
+            );
+            assert!(
+                (average - ideal).abs() < ideal * 0.2,
+                "{difficulty:?} {phase}: average block time {average:.2}s"
+            );
+        }
+    }
+
+    #[test]
+    fn epoch_converges() {
+        assert_converges(DifficultyAdjustment::Epoch);
+    }
+
+    #[test]
+    fn lwma_converges() {
+        assert_converges(DifficultyAdjustment::Lwma);
+    }
+
+    #[test]
+    fn asert_converges() {
+        assert_converges(DifficultyAdjustment::Asert);
+    }
+
+    #[test]
+    fn scaling_is_exact_at_the_clamp_bounds() {
+        let target = crate::MIN_TARGET / 1000 + U256::from(3u64);
+        assert_eq!(scale_target(target, 2000, 500), target * 4);
+        assert_eq!(scale_target(target, 125, 500), target / 4);
+        assert_eq!(scale_target(target, 500, 500), target);
+        // dividing first does not lose the remainder's share
+        assert_eq!(scale_target(U256::MAX, 125, 500), U256::MAX / 4);
+        assert_eq!(scale_target(U256::MAX, 1, 1), U256::MAX);
+        assert_eq!(scale_target(U256::MAX, 2, 1), U256::MAX);
+    }
+
+    #[test]
+    fn epoch_changes_the_target_by_a_factor_of_4_at_most() {
+        let epoch = Epoch {
+            interval: 10,
+            block_time: 10,
+        };
+        // every block of the interval taking `solve_time` seconds
+        let next_target = |solve_time: i64| {
+            let mut headers = vec![genesis()];
+            for _ in 1..epoch.interval {
+                let last = headers.last().unwrap();
+                let mut header = last.clone();
+                header.prev_block_hash = last.hash();
+                header.timestamp += Duration::seconds(solve_time);
+                headers.push(header);
+            }
+            epoch.next_target(&headers.iter().collect::<Vec<_>>())
+        };
+        let target = genesis().target();
+        // 9 solve times of the 10 blocks count
+        assert_eq!(next_target(10), scale_target(target, 90, 100));
+        assert_eq!(next_target(1000), target * 4);
+        assert_eq!(next_target(45), target * 4);
+        assert_eq!(next_target(0), target / 4);
+        assert_eq!(next_target(-1000), target / 4);
+    }
+
+    #[test]
+    fn asert_matches_the_reference_arithmetic() {
+        let asert = Asert {
+            half_life: 2880,
+            block_time: 10,
+        };
+        // the target after 100 blocks, the last one `behind` seconds
+        // behind schedule
+        let next_target = |behind: i64| {
+            let mut headers = vec![genesis()];
+            for height in 1..=100 {
+                let mut header = headers[0].clone();
+                header.timestamp += Duration::seconds(height * 10);
+                headers.push(header);
+            }
+            headers.last_mut().unwrap().timestamp += Duration::seconds(behind);
+            asert.next_target(&headers.iter().collect::<Vec<_>>())
+        };
+        let target = genesis().target();
+        assert_eq!(next_target(0), target);
+        assert_eq!(next_target(2880), target * 2);
+        assert_eq!(next_target(-2880), target / 2);
+        assert_eq!(next_target(2 * 2880), target * 4);
+        // 2^0.5 in the reference's 16.16 fixed point is 92674 / 65536
+        let sqrt_2 = scale_target(target, 92674, 65536);
+        assert_eq!(next_target(1440), sqrt_2);
+        assert_eq!(next_target(-1440), sqrt_2 >> 1);
+        assert_eq!(next_target(2880 + 1440), sqrt_2 << 1);
+        // and 2^0.25 is 77938 / 65536
+        assert_eq!(next_target(720), scale_target(target, 77938, 65536));
+    }
+}
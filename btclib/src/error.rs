@@ -1,4 +1,10 @@
-use thiserror::Error;
+use {
+    crate::{
+        sha256::Hash,
+        types::amount::Amount,
+    },
+    thiserror::Error,
+};
 
 #[derive(Error, Debug)]
 pub enum BtcError {
@@ -22,6 +28,111 @@ pub enum BtcError {
     InvalidPublicKey,
     #[error("Invalid private key")]
     InvalidPrivateKey,
+    #[error("Transaction rejected: {0}")]
+    Rejected(#[from] RejectReason),
+    #[error("Invalid address: {0}")]
+    InvalidAddress(#[from] AddressError),
+}
+
+/// Why a script failed
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ScriptError {
+    #[error("script is larger than MAX_SCRIPT_SIZE")]
+    ScriptTooLarge,
+    #[error("stack element is larger than MAX_SCRIPT_ELEMENT_SIZE")]
+    ElementTooLarge,
+    #[error("stack has more than MAX_SCRIPT_STACK_SIZE elements")]
+    StackOverflow,
+    #[error("not enough elements on the stack")]
+    StackUnderflow,
+    #[error("more than MAX_SCRIPT_OPS operations")]
+    TooManyOps,
+    #[error("more than MAX_MULTISIG_KEYS keys or a threshold above the keys")]
+    InvalidMultisig,
+    #[error("unlocking script does more than push data")]
+    NotPushOnly,
+    #[error("unbalanced conditional")]
+    UnbalancedConditional,
+    #[error("number longer than 8 bytes")]
+    InvalidNumber,
+    #[error("number, push or condition not in its minimal encoding")]
+    NonMinimal,
+    #[error("verify failed")]
+    VerifyFailed,
+    #[error("return reached")]
+    Return,
+    #[error("lock time not reached")]
+    LockTime,
+    #[error("relative lock time not reached")]
+    Sequence,
+    #[error("script ended with a false value")]
+    EvalFalse,
+}
+
+/// Why a string is not a valid address
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum AddressError {
+    #[error("mixes upper and lower case")]
+    MixedCase,
+    #[error("has no separator")]
+    MissingSeparator,
+    #[error("unknown network prefix {0}")]
+    UnknownPrefix(String),
+    #[error("invalid character {0}")]
+    InvalidCharacter(char),
+    #[error("invalid length")]
+    InvalidLength,
+    #[error("invalid checksum")]
+    InvalidChecksum,
+    #[error("unsupported address version {0}")]
+    UnsupportedVersion(u8),
+}
+
+/// Why a transaction was not accepted into the mempool
+#[derive(Error, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RejectReason {
+    #[error("already in the mempool")]
+    AlreadyKnown,
+    #[error("coinbase transactions are only valid in blocks")]
+    Coinbase,
+    #[error("no inputs")]
+    NoInputs,
+    #[error("a signature has a high s value")]
+    HighS,
+    #[error("lock time has not passed yet")]
+    NonFinal,
+    #[error("relative lock of input {0} has not expired yet")]
+    SequenceLocked(Hash),
+    #[error("input {0} is not an unspent output")]
+    UnknownInput(Hash),
+    #[error("input {0} spends a coinbase output that is not mature yet")]
+    ImmatureCoinbase(Hash),
+    #[error("input {0} does not satisfy the lock of the output it spends")]
+    InvalidSignature(Hash),
+    #[error("input {0} is spent twice")]
+    DuplicateInput(Hash),
+    #[error("outputs are worth more than inputs")]
+    OutputsExceedInputs,
+    #[error("inputs or outputs are worth more than MAX_MONEY")]
+    AmountOutOfRange,
+    #[error("output of {0} is below the dust threshold")]
+    Dust(Amount),
+    #[error("fee of {fee} is below the minimum relay fee of {min_fee}")]
+    FeeTooLow { fee: Amount, min_fee: Amount },
+    #[error("mempool is full and the fee rate is too low to evict anything")]
+    MempoolFull,
+    #[error("too many unconfirmed ancestors")]
+    TooManyAncestors,
+    #[error("an unconfirmed ancestor has too many descendants")]
+    TooManyDescendants,
+    #[error("conflicts with {0}, which does not signal replaceability")]
+    NotReplaceable(Hash),
+    #[error("fee of {fee} does not pay for the {required} needed to replace")]
+    InsufficientReplacementFee { fee: Amount, required: Amount },
+    #[error("fee rate is not higher than the one of {0}, which it replaces")]
+    ReplacementFeeRateTooLow(Hash),
+    #[error("would replace {0} transactions")]
+    TooManyReplacements(usize),
 }
 
 pub type Result<T> = std::result::Result<T, BtcError>;
@@ -0,0 +1,346 @@
+use {
+    crate::{
+        address::PubkeyHash,
+        sha256::Hash,
+        types::block::Block,
+    },
+    std::collections::BTreeSet,
+};
+
+/// Golomb-coded set of the key hashes outputs are locked to and the
+/// spent outpoints of a block (BIP158's basic filter). Light clients
+/// download it to find the blocks relevant to them without telling
+/// the node what they look for. False positives happen once in
+/// `FILTER_M` queries
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BlockFilter {
+    /// Amount of elements in the set
+    pub element_count: u64,
+    /// Golomb-Rice coded differences between the sorted hashes of
+    /// the elements
+    pub data: Vec<u8>,
+}
+
+impl BlockFilter {
+    pub fn new(block: &Block) -> Self {
+        let mut elements = BTreeSet::new();
+        for transaction in block.transactions.iter() {
+            for input in transaction.inputs.iter() {
+                elements.insert(outpoint_element(&input.prev_tx_output_hash));
+            }
+            for output in transaction.outputs.iter() {
+                if let Some(pubkey_hash) = output.lock.pubkey_hash() {
+                    elements.insert(pubkey_hash_element(&pubkey_hash));
+                }
+            }
+        }
+        let element_count = elements.len() as u64;
+        let key = filter_key(&block.hash());
+        let range = element_count.saturating_mul(crate::FILTER_M);
+        let mut hashes = elements
+            .iter()
+            .map(|element| hash_to_range(key, element, range))
+            .collect::<Vec<_>>();
+        hashes.sort();
+
+        let mut writer = BitWriter::default();
+        let mut last = 0;
+        for hash in hashes {
+            golomb_encode(&mut writer, hash - last);
+            last = hash;
+        }
+        BlockFilter {
+            element_count,
+            data: writer.bytes,
+        }
+    }
+
+    pub fn hash(&self) -> Hash {
+        Hash::hash(self)
+    }
+
+    // header committing to this filter and, through `prev_header`,
+    // to the filters of all earlier blocks
+    pub fn header(&self, prev_header: &Hash) -> Hash {
+        Hash::hash(&[self.hash(), *prev_header])
+    }
+
+    // whether any of the elements may be in the filter of the block
+    // with `block_hash`
+    pub fn matches_any<E: AsRef<[u8]>>(
+        &self,
+        block_hash: &Hash,
+        elements: impl IntoIterator<Item = E>,
+    ) -> bool {
+        let key = filter_key(block_hash);
+        let range = self.element_count.saturating_mul(crate::FILTER_M);
+        let mut queries = elements
+            .into_iter()
+            .map(|element| hash_to_range(key, element.as_ref(), range))
+            .collect::<Vec<_>>();
+        if queries.is_empty() {
+            return false;
+        }
+        queries.sort();
+
+        // walk both sorted lists at once
+        let mut reader = BitReader::new(&self.data);
+        let mut queries = queries.into_iter().peekable();
+        let mut value = 0u64;
+        for _ in 0..self.element_count {
+            let Some(delta) = golomb_decode(&mut reader) else {
+                return false;
+            };
+            value = value.saturating_add(delta);
+            while let Some(&query) = queries.peek() {
+                if query == value {
+                    return true;
+                }
+                if query > value {
+                    break;
+                }
+                queries.next();
+            }
+            if queries.peek().is_none() {
+                return false;
+            }
+        }
+        false
+    }
+
+    pub fn matches(&self, block_hash: &Hash, element: &[u8]) -> bool {
+        self.matches_any(block_hash, [element])
+    }
+}
+
+// filter element for outputs locked to the key with `pubkey_hash`,
+// whether by the key itself or by its hash
+pub fn pubkey_hash_element(pubkey_hash: &PubkeyHash) -> Vec<u8> {
+    pubkey_hash.0.to_vec()
+}
+
+// filter element for inputs spending the output with `hash`
+pub fn outpoint_element(hash: &Hash) -> Vec<u8> {
+    hash.as_bytes().to_vec()
+}
+
+// the SipHash key is the first 16 bytes of the block hash, so every
+// block's filter has different false positives
+fn filter_key(block_hash: &Hash) -> (u64, u64) {
+    let bytes = block_hash.as_bytes();
+    (
+        u64::from_le_bytes(bytes[0..8].try_into().unwrap()),
+        u64::from_le_bytes(bytes[8..16].try_into().unwrap()),
+    )
+}
+
+// map the element uniformly onto [0, range)
+fn hash_to_range(key: (u64, u64), element: &[u8], range: u64) -> u64 {
+    ((siphash24(key, element) as u128 * range as u128) >> 64) as u64
+}
+
+// the quotient in unary, then FILTER_P bits of remainder
+fn golomb_encode(writer: &mut BitWriter, value: u64) {
+    for _ in 0..value >> crate::FILTER_P {
+        writer.write_bit(true);
+    }
+    writer.write_bit(false);
+    writer.write_bits(value, crate::FILTER_P);
+}
+
+fn golomb_decode(reader: &mut BitReader) -> Option<u64> {
+    let mut quotient = 0u64;
+    while reader.read_bit()? {
+        quotient = quotient.checked_add(1)?;
+    }
+    let remainder = reader.read_bits(crate::FILTER_P)?;
+    quotient
+        .checked_shl(crate::FILTER_P)
+        .filter(|shifted| shifted >> crate::FILTER_P == quotient)
+        .map(|shifted| shifted | remainder)
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    bits: usize,
+}
+
+impl BitWriter {
+    fn write_bit(&mut self, bit: bool) {
+        if self.bits == self.bytes.len() * 8 {
+            self.bytes.push(0);
+        }
+        if bit {
+            *self.bytes.last_mut().unwrap() |= 0x80 >> (self.bits % 8);
+        }
+        self.bits += 1;
+    }
+
+    // the lowest `count` bits of `value`, most significant first
+    fn write_bits(&mut self, value: u64, count: u32) {
+        for i in (0..count).rev() {
+            self.write_bit(value >> i & 1 == 1);
+        }
+    }
+}
+
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    bits: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        BitReader { bytes, bits: 0 }
+    }
+
+    fn read_bit(&mut self) -> Option<bool> {
+        let byte = self.bytes.get(self.bits / 8)?;
+        let bit = byte & (0x80 >> (self.bits % 8)) != 0;
+        self.bits += 1;
+        Some(bit)
+    }
+
+    fn read_bits(&mut self, count: u32) -> Option<u64> {
+        let mut value = 0;
+        for _ in 0..count {
+            value = value << 1 | self.read_bit()? as u64;
+        }
+        Some(value)
+    }
+}
+
+// SipHash-2-4 of `data` with the 128 bit key (k0, k1)
+fn siphash24((k0, k1): (u64, u64), data: &[u8]) -> u64 {
+    let mut v = [
+        k0 ^ 0x736f_6d65_7073_6575,
+        k1 ^ 0x646f_7261_6e64_6f6d,
+        k0 ^ 0x6c79_6765_6e65_7261,
+        k1 ^ 0x7465_6462_7974_6573,
+    ];
+    let compress = |v: &mut [u64; 4], m: u64, rounds: usize| {
+        v[3] ^= m;
+        for _ in 0..rounds {
+            v[0] = v[0].wrapping_add(v[1]);
+            v[1] = v[1].rotate_left(13) ^ v[0];
+            v[0] = v[0].rotate_left(32);
+            v[2] = v[2].wrapping_add(v[3]);
+            v[3] = v[3].rotate_left(16) ^ v[2];
+            v[0] = v[0].wrapping_add(v[3]);
+            v[3] = v[3].rotate_left(21) ^ v[0];
+            v[2] = v[2].wrapping_add(v[1]);
+            v[1] = v[1].rotate_left(17) ^ v[2];
+            v[2] = v[2].rotate_left(32);
+        }
+        v[0] ^= m;
+    };
+
+    let mut chunks = data.chunks_exact(8);
+    for chunk in chunks.by_ref() {
+        compress(&mut v, u64::from_le_bytes(chunk.try_into().unwrap()), 2);
+    }
+    // the last block holds the remaining bytes and the length
+    let mut last = [0u8; 8];
+    last[..chunks.remainder().len()].copy_from_slice(chunks.remainder());
+    last[7] = data.len() as u8;
+    compress(&mut v, u64::from_le_bytes(last), 2);
+
+    v[2] ^= 0xff;
+    compress(&mut v, 0, 4);
+    v[0] ^ v[1] ^ v[2] ^ v[3]
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            crypto::PrivateKey,
+            types::{
+                amount::Amount,
+                block_header::BlockHeader,
+                transaction::{
+                    Lock,
+                    Transaction,
+                    TransactionOutput,
+                },
+            },
+            util::MerkleRoot,
+        },
+        chrono::Utc,
+        uuid::Uuid,
+    };
+
+    // reference vectors of the SipHash paper, with the key 00..0f and
+    // the message 00..(length - 1)
+    #[test]
+    fn siphash24_reference_vectors() {
+        let key = (0x0706_0504_0302_0100, 0x0f0e_0d0c_0b0a_0908);
+        let message = (0..16).collect::<Vec<u8>>();
+        assert_eq!(siphash24(key, &message[..0]), 0x726f_db47_dd0e_0e31);
+        assert_eq!(siphash24(key, &message[..1]), 0x74f8_39c5_93dc_67fd);
+        assert_eq!(siphash24(key, &message[..8]), 0x93f5_f579_9a93_2462);
+        assert_eq!(siphash24(key, &message[..15]), 0xa129_ca61_49be_45e5);
+    }
+
+    #[test]
+    fn golomb_round_trip() {
+        let values = [
+            0,
+            1,
+            2,
+            1 << crate::FILTER_P,
+            (1 << crate::FILTER_P) - 1,
+            123_456_789,
+        ];
+        let mut writer = BitWriter::default();
+        for value in values {
+            golomb_encode(&mut writer, value);
+        }
+        let mut reader = BitReader::new(&writer.bytes);
+        for value in values {
+            assert_eq!(golomb_decode(&mut reader), Some(value));
+        }
+        // only the padding of the last byte is left
+        assert!(golomb_decode(&mut reader).is_none());
+    }
+
+    #[test]
+    fn filter_matches_its_elements() {
+        let pubkey_hashes = (0..20)
+            .map(|_| PrivateKey::new_key().public_key().pubkey_hash())
+            .collect::<Vec<_>>();
+        let outputs = pubkey_hashes[..10]
+            .iter()
+            .map(|pubkey_hash| {
+                TransactionOutput {
+                    value: Amount::from_sat(1),
+                    unique_id: Uuid::new_v4(),
+                    lock: Lock::PubkeyHash(*pubkey_hash),
+                }
+            })
+            .collect();
+        let transactions = vec![Transaction::new_coinbase(0, vec![], outputs)];
+        let block = Block::new(
+            BlockHeader::new(
+                Utc::now(),
+                0,
+                Hash::zero(),
+                MerkleRoot::calculate(&transactions),
+                crate::MIN_TARGET,
+            ),
+            transactions,
+        );
+        let filter = BlockFilter::new(&block);
+        assert_eq!(filter.element_count, 10);
+        for pubkey_hash in pubkey_hashes[..10].iter() {
+            assert!(filter.matches(&block.hash(), &pubkey_hash_element(pubkey_hash)));
+        }
+        // false positives are rare enough not to happen among ten
+        assert!(!filter.matches_any(
+            &block.hash(),
+            pubkey_hashes[10..].iter().map(pubkey_hash_element)
+        ));
+    }
+}
@@ -1,7 +1,15 @@
+pub mod address;
+pub mod clock;
 pub mod crypto;
+pub mod difficulty;
 pub mod error;
+pub mod filter;
+pub mod musig;
 pub mod network;
+pub mod script;
 pub mod sha256;
+pub mod spv;
+pub mod template;
 pub mod types;
 pub mod util;
 
@@ -9,17 +17,83 @@ extern crate ciborium;
 #[macro_use]
 extern crate serde;
 extern crate sha256 as sha256_lib;
-use uint::construct_uint;
+use {
+    types::{
+        amount::Amount,
+        fee_rate::FeeRate,
+    },
+    uint::construct_uint,
+};
 
 construct_uint! {
     #[derive(Serialize, Deserialize)]
     pub struct U256(4);
 }
 
+impl U256 {
+    // encode a target in the compact "bits" format: the size in
+    // bytes in the highest byte, followed by the three most
+    // significant bytes. Precision beyond those is lost
+    pub fn to_compact(&self) -> u32 {
+        let mut size = (self.bits() as u32).div_ceil(8);
+        let mut mantissa = if size <= 3 {
+            (self.low_u64() << (8 * (3 - size))) as u32
+        } else {
+            (*self >> (8 * (size - 3))).low_u32()
+        };
+        // the 0x00800000 bit is a sign bit, so move the mantissa out
+        // of its way
+        if mantissa & 0x0080_0000 != 0 {
+            mantissa >>= 8;
+            size += 1;
+        }
+        mantissa | (size << 24)
+    }
+
+    // decode a target in the compact "bits" format, None if it is
+    // negative or does not fit in 256 bits
+    pub fn from_compact(bits: u32) -> Option<U256> {
+        let size = bits >> 24;
+        let mantissa = bits & 0x007F_FFFF;
+        if mantissa == 0 {
+            return Some(U256::zero());
+        }
+        if bits & 0x0080_0000 != 0 {
+            return None;
+        }
+        if size <= 3 {
+            return Some(U256::from(mantissa >> (8 * (3 - size))));
+        }
+        let mantissa = U256::from(mantissa);
+        if mantissa.bits() as u32 + 8 * (size - 3) > 256 {
+            return None;
+        }
+        Some(mantissa << (8 * (size - 3) as usize))
+    }
+
+    // expected amount of hashes needed to find one at or below
+    // this target, 2^256 / (target + 1). A zero target, which
+    // invalid compact bits decode to, counts for no work
+    pub fn work(&self) -> U256 {
+        if self.is_zero() {
+            return U256::zero();
+        }
+        match self.checked_add(U256::one()) {
+            Some(target_plus_one) => (!*self / target_plus_one) + U256::one(),
+            None => U256::one(),
+        }
+    }
+}
+
 // initial reward in bitcoin - multiply by 10^8 to get satoshis
 pub const INITIAL_REWARD: u64 = 50;
 // halving interval in blocks
 pub const HALVING_INTERVAL: u64 = 210;
+// satoshis in a bitcoin
+pub const SATS_PER_BTC: u64 = 100_000_000;
+// upper bound of the coins the block subsidies add up to, in satoshis;
+// no amount can be larger
+pub const MAX_MONEY: u64 = 2 * HALVING_INTERVAL * INITIAL_REWARD * SATS_PER_BTC;
 // ideal block time in seconds
 pub const IDEAL_BLOCK_TIME: u64 = 10;
 // minimum target
@@ -31,7 +105,152 @@ pub const MIN_TARGET: U256 = U256([
 ]);
 // difficulty update interval in blocks
 pub const DIFFICULTY_UPDATE_INTERVAL: u64 = 50;
+// amount of blocks the LWMA difficulty adjustment averages over
+pub const LWMA_WINDOW: u64 = 45;
+// seconds the chain has to fall behind schedule for the ASERT difficulty
+// adjustment to double the target, 288 blocks like Bitcoin Cash's two days
+pub const ASERT_HALF_LIFE: u64 = 288 * IDEAL_BLOCK_TIME;
 // maximum mempool transaction age in seconds
 pub const MAX_MEMPOOL_TRANSACTION_AGE: u64 = 600;
-// maximum amount of transactions allowed in a block
-pub const BLOCK_TRANSACTION_CAP: usize = 20;
+// maximum total size of the mempool's transactions in bytes
+pub const MAX_MEMPOOL_SIZE: usize = 50_000_000;
+// minimum fee rate for a transaction to be relayed
+pub const MIN_RELAY_FEE_RATE: FeeRate = FeeRate::from_sat_per_vbyte(1);
+// outputs worth less than this many satoshis are not relayed
+pub const DUST_THRESHOLD: Amount = Amount::from_sat(546);
+// maximum amount of unconfirmed ancestors of a mempool transaction, itself
+// included
+pub const MAX_MEMPOOL_ANCESTORS: usize = 25;
+// maximum amount of unconfirmed descendants of a mempool transaction, itself
+// included
+pub const MAX_MEMPOOL_DESCENDANTS: usize = 25;
+// default input sequence number, opting out of replace-by-fee
+pub const SEQUENCE_FINAL: u32 = 0xFFFF_FFFF;
+// inputs with a sequence number below this signal replace-by-fee
+pub const SEQUENCE_RBF_THRESHOLD: u32 = 0xFFFF_FFFE;
+// lock times below this are block heights, the others unix timestamps
+pub const LOCKTIME_THRESHOLD: u64 = 500_000_000;
+// sequence numbers with this bit set hold no relative lock
+pub const SEQUENCE_LOCKTIME_DISABLE_FLAG: u32 = 1 << 31;
+// relative locks with this bit set are measured in time, not blocks
+pub const SEQUENCE_LOCKTIME_TYPE_FLAG: u32 = 1 << 22;
+// bits of the sequence number holding the relative lock
+pub const SEQUENCE_LOCKTIME_MASK: u32 = 0x0000_FFFF;
+// relative time locks count in units of 2^9 = 512 seconds
+pub const SEQUENCE_LOCKTIME_GRANULARITY: u32 = 9;
+// how far ahead of the current time a block timestamp may be, in seconds
+pub const MAX_FUTURE_BLOCK_TIME: u64 = 2 * 60 * 60;
+// furthest other nodes' clocks may move the network-adjusted time, in
+// seconds
+pub const MAX_TIME_ADJUSTMENT: u64 = 70 * 60;
+// other nodes' clocks needed before the network-adjusted time moves
+pub const MIN_TIME_SAMPLES: usize = 5;
+// other nodes' clocks the network-adjusted time takes into account
+pub const MAX_TIME_SAMPLES: usize = 200;
+// amount of blocks whose median timestamp is the median time past
+pub const MEDIAN_TIME_SPAN: usize = 11;
+// maximum amount of block headers a node sends in one message
+pub const MAX_HEADERS_PER_MESSAGE: usize = 2000;
+// maximum amount of block filters a node sends for one request
+pub const MAX_CFILTERS_PER_REQUEST: u64 = 1000;
+// bits of remainder in the Golomb-Rice coding of block filters
+pub const FILTER_P: u32 = 19;
+// inverse false positive rate of block filters
+pub const FILTER_M: u64 = 784_931;
+// maximum amount of mempool transactions a replacement may evict,
+// descendants included
+pub const MAX_REPLACEMENT_EVICTIONS: usize = 100;
+// confirmations a coinbase output needs before it can be spent
+pub const COINBASE_MATURITY: u64 = 100;
+// maximum size of the miner's extra data in a coinbase transaction
+pub const MAX_COINBASE_EXTRA_DATA: usize = 100;
+// maximum weight of a block, header included
+pub const MAX_BLOCK_WEIGHT: usize = 4_000_000;
+// weight units per byte of non-signature data, signature bytes weigh one
+pub const WITNESS_SCALE_FACTOR: usize = 4;
+// maximum size of a locking or unlocking script in bytes
+pub const MAX_SCRIPT_SIZE: usize = 10_000;
+// maximum size of a script stack element in bytes
+pub const MAX_SCRIPT_ELEMENT_SIZE: usize = 520;
+// maximum amount of elements on a script's stack
+pub const MAX_SCRIPT_STACK_SIZE: usize = 1000;
+// maximum amount of non-push operations a script executes, the keys
+// of multisig checks counting one each
+pub const MAX_SCRIPT_OPS: usize = 201;
+// maximum amount of public keys of a multisig lock or script check
+pub const MAX_MULTISIG_KEYS: usize = 20;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compact_bits_round_trip() {
+        for bits in [
+            0x1d00_ffff,
+            0x1b04_04cb,
+            0x0312_3456,
+            0x207f_ffff,
+            MIN_TARGET.to_compact(),
+        ] {
+            assert_eq!(U256::from_compact(bits).unwrap().to_compact(), bits);
+        }
+        let target = U256::from(0xffffu64) << 208;
+        assert_eq!(target.to_compact(), 0x1d00_ffff);
+        assert_eq!(U256::from_compact(0x1d00_ffff), Some(target));
+        // precision beyond three bytes is lost
+        assert_eq!(
+            U256::from_compact(MIN_TARGET.to_compact()),
+            Some(U256::from(0xffffu64) << 224)
+        );
+        assert_eq!(U256::from(0x12u64).to_compact(), 0x0112_0000);
+        assert_eq!(U256::from_compact(0x0112_0000), Some(U256::from(0x12u64)));
+    }
+
+    #[test]
+    fn compact_bits_keep_clear_of_the_sign_bit() {
+        assert_eq!(U256::from(0x80u64).to_compact(), 0x0200_8000);
+        assert_eq!(U256::from(0x9234_0000u64).to_compact(), 0x0500_9234);
+        assert_eq!(
+            U256::from_compact(0x0500_9234),
+            Some(U256::from(0x9234_0000u64))
+        );
+        // negative targets do not exist
+        assert_eq!(U256::from_compact(0x0492_3456), None);
+        assert_eq!(U256::from_compact(0x1d80_ffff), None);
+    }
+
+    #[test]
+    fn compact_bits_beyond_256_bits_do_not_decode() {
+        assert_eq!(
+            U256::from_compact(0x2100_ffff),
+            Some(U256::from(0xffffu64) << 240)
+        );
+        assert_eq!(U256::from_compact(0x2101_ffff), None);
+        assert_eq!(U256::from_compact(0x2200_ffff), None);
+        assert_eq!(U256::from_compact(0xff12_3456), None);
+    }
+
+    #[test]
+    fn zero_targets_decode_but_count_for_no_work() {
+        assert_eq!(U256::zero().to_compact(), 0);
+        assert_eq!(U256::from_compact(0), Some(U256::zero()));
+        // the mantissa is shifted out entirely
+        assert_eq!(U256::from_compact(0x0100_3456), Some(U256::zero()));
+        // a zero mantissa is zero whatever the sign bit says
+        assert_eq!(U256::from_compact(0x0480_0000), Some(U256::zero()));
+        assert_eq!(U256::zero().work(), U256::zero());
+    }
+
+    #[test]
+    fn work_is_two_to_the_256_over_target_plus_one() {
+        assert_eq!(U256::MAX.work(), U256::one());
+        assert_eq!(U256::one().work(), U256::one() << 255);
+        // MIN_TARGET is 2^240 - 1
+        assert_eq!(MIN_TARGET.work(), U256::from(1u64 << 16));
+        assert_eq!(
+            U256::from_compact(0x1d00_ffff).unwrap().work(),
+            U256::from(0x1_0001_0001u64)
+        );
+    }
+}
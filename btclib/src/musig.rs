@@ -0,0 +1,325 @@
+// MuSig2 (BIP327) without tweaks: the holders of several keys
+// produce one Schnorr signature for their aggregate key, so that an
+// output locked to it looks and verifies like any single key output
+use {
+    crate::{
+        crypto::{
+            challenge,
+            tagged_hash,
+            PrivateKey,
+            PublicKey,
+            Signature,
+        },
+        sha256::Hash,
+    },
+    ecdsa::VerifyingKey,
+    k256::{
+        elliptic_curve::{
+            group::Group,
+            ops::{
+                LinearCombinationExt,
+                Reduce,
+            },
+            point::AffineCoordinates,
+            sec1::ToEncodedPoint,
+            PrimeField,
+        },
+        schnorr::Signature as SchnorrSignature,
+        AffinePoint,
+        NonZeroScalar,
+        ProjectivePoint,
+        Scalar,
+        U256 as K256U256,
+    },
+};
+
+/// Public keys combined into one. The order of the keys matters, so
+/// every signer has to use the same list
+#[derive(Debug, Clone)]
+pub struct KeyAggregation {
+    pubkeys: Vec<PublicKey>,
+    coefficients: Vec<Scalar>,
+    aggregate: AffinePoint,
+}
+
+impl KeyAggregation {
+    // None if there are no keys, or they cancel each other out
+    pub fn new(pubkeys: &[PublicKey]) -> Option<Self> {
+        let encoded = pubkeys.iter().map(PublicKey::to_bytes).collect::<Vec<_>>();
+        let first = encoded.first()?;
+        let list_hash = tagged_hash(
+            "KeyAgg list",
+            &encoded.iter().map(Vec::as_slice).collect::<Vec<_>>(),
+        );
+        // the second distinct key gets coefficient 1, which saves a
+        // multiplication when verifying
+        let second = encoded.iter().find(|pubkey| *pubkey != first);
+        let coefficients = encoded
+            .iter()
+            .map(|pubkey| {
+                if Some(pubkey) == second {
+                    Scalar::ONE
+                } else {
+                    reduce(tagged_hash("KeyAgg coefficient", &[&list_hash, pubkey]))
+                }
+            })
+            .collect::<Vec<_>>();
+        let terms = pubkeys
+            .iter()
+            .zip(coefficients.iter())
+            .map(|(pubkey, coefficient)| (point(pubkey), *coefficient))
+            .collect::<Vec<_>>();
+        let aggregate = ProjectivePoint::lincomb_ext(&terms[..]);
+        if bool::from(aggregate.is_identity()) {
+            return None;
+        }
+        Some(KeyAggregation {
+            pubkeys: pubkeys.to_vec(),
+            coefficients,
+            aggregate: aggregate.to_affine(),
+        })
+    }
+
+    pub fn pubkeys(&self) -> &[PublicKey] {
+        &self.pubkeys
+    }
+
+    // the aggregate key, to lock outputs to like any other key
+    pub fn public_key(&self) -> PublicKey {
+        PublicKey(VerifyingKey::from_affine(self.aggregate).expect("aggregate is not the identity"))
+    }
+
+    fn coefficient(&self, pubkey: &PublicKey) -> Option<Scalar> {
+        let index = self.pubkeys.iter().position(|key| key == pubkey)?;
+        Some(self.coefficients[index])
+    }
+
+    // -1 if the aggregate key has an odd y, as BIP340 only uses keys
+    // with an even one
+    fn parity(&self) -> Scalar {
+        if bool::from(self.aggregate.y_is_odd()) {
+            -Scalar::ONE
+        } else {
+            Scalar::ONE
+        }
+    }
+}
+
+/// The two secret nonces of a signer for one signing session. Signing
+/// consumes it, as signing twice with the same nonces would reveal
+/// the private key
+pub struct SecretNonce {
+    k1: NonZeroScalar,
+    k2: NonZeroScalar,
+}
+
+/// The public points of a signer's nonces, sent to the other signers
+/// before anyone signs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PublicNonce(pub PublicKey, pub PublicKey);
+
+/// A signer's share of the signature, sent to whoever aggregates them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartialSignature(pub [u8; 32]);
+
+// fresh random nonces for one signing session
+pub fn generate_nonce() -> (SecretNonce, PublicNonce) {
+    let mut rng = rand::thread_rng();
+    let k1 = NonZeroScalar::random(&mut rng);
+    let k2 = NonZeroScalar::random(&mut rng);
+    let public = PublicNonce(nonce_point(&k1), nonce_point(&k2));
+    (SecretNonce { k1, k2 }, public)
+}
+
+/// Signing of one message once the public nonces of all signers are
+/// known. Every signer builds the same session
+#[derive(Debug)]
+pub struct SigningSession<'a> {
+    key_aggregation: &'a KeyAggregation,
+    nonces: Vec<PublicNonce>,
+    /// Coefficient of the second nonces
+    b: Scalar,
+    /// Nonce point of the final signature
+    r: AffinePoint,
+    e: Scalar,
+}
+
+impl<'a> SigningSession<'a> {
+    // `nonces` are the public nonces of the signers, in the order of
+    // the aggregated keys
+    pub fn new(
+        key_aggregation: &'a KeyAggregation,
+        nonces: &[PublicNonce],
+        message: &Hash,
+    ) -> Self {
+        let r1 = nonces
+            .iter()
+            .map(|nonce| point(&nonce.0))
+            .sum::<ProjectivePoint>();
+        let r2 = nonces
+            .iter()
+            .map(|nonce| point(&nonce.1))
+            .sum::<ProjectivePoint>();
+        let aggregate_x = key_aggregation.aggregate.x();
+        let message = message.as_bytes();
+        let b = reduce(tagged_hash(
+            "MuSig/noncecoef",
+            &[
+                &encode_point(&r1),
+                &encode_point(&r2),
+                &aggregate_x,
+                &message,
+            ],
+        ));
+        let mut r = r1 + r2 * b;
+        // BIP327 replaces an identity nonce by the generator
+        if bool::from(r.is_identity()) {
+            r = ProjectivePoint::GENERATOR;
+        }
+        let r = r.to_affine();
+        let e = challenge(&r.x(), &aggregate_x, &message);
+        SigningSession {
+            key_aggregation,
+            nonces: nonces.to_vec(),
+            b,
+            r,
+            e,
+        }
+    }
+
+    // the share of the holder of `private_key`, None if the key is not
+    // one of the aggregated ones
+    pub fn sign(&self, nonce: SecretNonce, private_key: &PrivateKey) -> Option<PartialSignature> {
+        let a = self
+            .key_aggregation
+            .coefficient(&private_key.public_key())?;
+        let d = **private_key.0.as_nonzero_scalar() * self.key_aggregation.parity();
+        let s = (*nonce.k1 + self.b * *nonce.k2) * self.nonce_parity() + self.e * a * d;
+        Some(PartialSignature(s.to_bytes().into()))
+    }
+
+    // whether the share of the signer at `index` is valid, to find
+    // out who spoiled a signature that does not verify
+    pub fn verify_partial(&self, partial: &PartialSignature, index: usize) -> bool {
+        let (Some(pubkey), Some(nonce)) = (
+            self.key_aggregation.pubkeys.get(index),
+            self.nonces.get(index),
+        ) else {
+            return false;
+        };
+        let Some(s) = Option::<Scalar>::from(Scalar::from_repr(partial.0.into())) else {
+            return false;
+        };
+        let a = self.key_aggregation.coefficients[index];
+        let g = self.key_aggregation.parity();
+        let r = (point(&nonce.0) + point(&nonce.1) * self.b) * self.nonce_parity();
+        ProjectivePoint::GENERATOR * s == r + point(pubkey) * (self.e * a * g)
+    }
+
+    // the Schnorr signature of the aggregate key from the shares of
+    // all signers
+    pub fn aggregate(&self, partials: &[PartialSignature]) -> Option<Signature> {
+        let mut s = Scalar::ZERO;
+        for partial in partials {
+            s += Option::<Scalar>::from(Scalar::from_repr(partial.0.into()))?;
+        }
+        let mut bytes = self.r.x().to_vec();
+        bytes.extend_from_slice(&s.to_bytes());
+        SchnorrSignature::try_from(&bytes[..])
+            .ok()
+            .map(Signature::Schnorr)
+    }
+
+    // nonces are negated when the final one has an odd y
+    fn nonce_parity(&self) -> Scalar {
+        if bool::from(self.r.y_is_odd()) {
+            -Scalar::ONE
+        } else {
+            Scalar::ONE
+        }
+    }
+}
+
+fn point(pubkey: &PublicKey) -> ProjectivePoint {
+    ProjectivePoint::from(*pubkey.0.as_affine())
+}
+
+fn nonce_point(k: &NonZeroScalar) -> PublicKey {
+    let point = (ProjectivePoint::GENERATOR * **k).to_affine();
+    PublicKey(VerifyingKey::from_affine(point).expect("nonce is not zero"))
+}
+
+// compressed encoding, all zeros for the identity
+fn encode_point(point: &ProjectivePoint) -> Vec<u8> {
+    if bool::from(point.is_identity()) {
+        return vec![0; 33];
+    }
+    point.to_affine().to_encoded_point(true).as_bytes().to_vec()
+}
+
+fn reduce(hash: [u8; 32]) -> Scalar {
+    <Scalar as Reduce<K256U256>>::reduce_bytes(&hash.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the signature of `message` by all of `keys`, checking every
+    // share on the way
+    fn sign(keys: &[PrivateKey], message: &Hash) -> (KeyAggregation, Signature) {
+        let pubkeys = keys.iter().map(PrivateKey::public_key).collect::<Vec<_>>();
+        let key_aggregation = KeyAggregation::new(&pubkeys).unwrap();
+        let (secret_nonces, public_nonces): (Vec<_>, Vec<_>) =
+            keys.iter().map(|_| generate_nonce()).unzip();
+        let session = SigningSession::new(&key_aggregation, &public_nonces, message);
+        let partials = secret_nonces
+            .into_iter()
+            .zip(keys)
+            .map(|(nonce, key)| session.sign(nonce, key).unwrap())
+            .collect::<Vec<_>>();
+        for (index, partial) in partials.iter().enumerate() {
+            assert!(session.verify_partial(partial, index));
+        }
+        let signature = session.aggregate(&partials).unwrap();
+        (key_aggregation, signature)
+    }
+
+    #[test]
+    fn aggregate_signature_verifies() {
+        let message = Hash::hash(&"message");
+        for count in [1, 2, 3, 5] {
+            let keys = (0..count)
+                .map(|_| PrivateKey::new_key())
+                .collect::<Vec<_>>();
+            let (key_aggregation, signature) = sign(&keys, &message);
+            let public_key = key_aggregation.public_key();
+            assert!(signature.is_schnorr());
+            assert!(signature.verify(&message, &public_key));
+            assert!(!signature.verify(&Hash::hash(&"other message"), &public_key));
+        }
+    }
+
+    #[test]
+    fn missing_or_wrong_shares_do_not_verify() {
+        let message = Hash::hash(&"message");
+        let keys = (0..3).map(|_| PrivateKey::new_key()).collect::<Vec<_>>();
+        let pubkeys = keys.iter().map(PrivateKey::public_key).collect::<Vec<_>>();
+        let key_aggregation = KeyAggregation::new(&pubkeys).unwrap();
+        let (secret_nonces, public_nonces): (Vec<_>, Vec<_>) =
+            keys.iter().map(|_| generate_nonce()).unzip();
+        let session = SigningSession::new(&key_aggregation, &public_nonces, &message);
+        let partials = secret_nonces
+            .into_iter()
+            .zip(&keys)
+            .map(|(nonce, key)| session.sign(nonce, key).unwrap())
+            .collect::<Vec<_>>();
+        assert!(!session.verify_partial(&partials[0], 1));
+        let public_key = key_aggregation.public_key();
+        let signature = session.aggregate(&partials[1..]).unwrap();
+        assert!(!signature.verify(&message, &public_key));
+        assert!(session
+            .sign(generate_nonce().0, &PrivateKey::new_key())
+            .is_none());
+    }
+}
@@ -1,13 +1,29 @@
 use {
     crate::{
-        crypto::PublicKey,
+        address::PubkeyHash,
+        error::RejectReason,
+        filter::BlockFilter,
+        sha256::Hash,
+        spv::{
+            FilteredBlock,
+            TransactionProof,
+        },
         types::{
             block::Block,
+            block_header::BlockHeader,
+            fee_rate::FeeRate,
             transaction::{
+                Lock,
                 Transaction,
                 TransactionOutput,
             },
+            utxo::Balance,
         },
+        U256,
+    },
+    chrono::{
+        DateTime,
+        Utc,
     },
     serde::{
         Deserialize,
@@ -28,18 +44,30 @@ use {
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub enum Message {
-    /// Fetch all UTXOs belonging to a public key
-    FetchUTXOs(PublicKey),
-    /// UTXOs belonging to a public key. Bool determines if marked
+    /// Fetch all UTXOs locked to the public key with the specified
+    /// hash, whether by the key or by its hash
+    FetchUTXOs(PubkeyHash),
+    /// UTXOs belonging to a public key hash. Bool determines if
+    /// marked.
+    /// Immature coinbase outputs are left out
     UTXOs(Vec<(TransactionOutput, bool)>),
+    /// Fetch the balance of a public key hash
+    FetchBalance(PubkeyHash),
+    /// This is the response to FetchBalance
+    Balance(Balance),
     /// Send a transaction to the network
     SubmitTransaction(Transaction),
+    /// The submitted transaction entered the mempool, replacing
+    /// the transactions with these hashes
+    TransactionAccepted(Vec<Hash>),
+    /// The submitted transaction was refused by the mempool
+    TransactionRejected(RejectReason),
     /// Broadcast a new transaction to other nodes
     NewTransaction(Transaction),
     /// Ask the node to prepare the optimal block template
-    /// with the coinbase transaction paying the specified
-    /// public key
-    FetchTemplate(PublicKey),
+    /// with the coinbase transaction paying to the specified
+    /// lock
+    FetchTemplate(Lock),
     /// The template
     Template(Block),
     /// Ask the node to validate a block template.
@@ -61,10 +89,58 @@ pub enum Message {
     AskDifference(u32),
     /// This is the response to AskDifference
     Difference(i32),
+    /// Ask a node for the time of its clock, for network-adjusted
+    /// time
+    AskTime,
+    /// This is the response to AskTime
+    Time(DateTime<Utc>),
+    /// Ask a node how much work its blockchain represents
+    AskChainwork,
+    /// This is the response to AskChainwork: the height of the
+    /// blockchain and its cumulative work
+    Chainwork(u64, U256),
     /// Ask a node to send a block with the specified height
     FetchBlock(usize),
     /// Broadcast a new block to other nodes
     NewBlock(Block),
+    /// Ask a node for the headers of the blocks starting at the
+    /// specified height, for light clients
+    FetchHeaders(u64),
+    /// This is the response to FetchHeaders, at most
+    /// `MAX_HEADERS_PER_MESSAGE` headers in chain order
+    Headers(Vec<BlockHeader>),
+    /// Ask a node to prove that the transaction with the specified
+    /// hash is in its blockchain
+    FetchTransactionProof(Hash),
+    /// This is the response to FetchTransactionProof, None if the
+    /// transaction is not in a block
+    TransactionProof(Option<TransactionProof>),
+    /// Ask a node for the transactions of the block at the specified
+    /// height that pay or spend from a public key hash, or spend one
+    /// of the specified outputs
+    FetchFilteredBlock(u64, PubkeyHash, Vec<Hash>),
+    /// This is the response to FetchFilteredBlock, None if there is
+    /// no block at that height
+    FilteredBlock(Option<FilteredBlock>),
+    /// Ask a node for the filters of the blocks starting at the
+    /// specified height, at most `MAX_CFILTERS_PER_REQUEST` of
+    /// the specified amount
+    GetCFilters(u64, u64),
+    /// One of the responses to GetCFilters, in chain order: the
+    /// hash of a block and its filter
+    CFilter(Hash, BlockFilter),
+    /// Ask a node for the filter headers of the blocks starting at
+    /// the specified height, at most `MAX_CFILTERS_PER_REQUEST` of
+    /// the specified amount
+    GetCFHeaders(u64, u64),
+    /// This is the response to GetCFHeaders
+    CFHeaders(Vec<Hash>),
+    /// Ask a node which fee rate a transaction should pay to
+    /// confirm within this many blocks
+    EstimateFee(u64),
+    /// This is the response to EstimateFee, None if the node has
+    /// not seen enough transactions confirm yet
+    FeeEstimate(Option<FeeRate>),
 }
 
 impl Message {
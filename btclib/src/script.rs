@@ -0,0 +1,752 @@
+use {
+    crate::{
+        address::PubkeyHash,
+        crypto::{
+            PublicKey,
+            Signature,
+        },
+        error::ScriptError,
+        sha256::Hash,
+        types::transaction::{
+            RelativeLock,
+            Transaction,
+            TransactionInput,
+        },
+        util::serialized_size,
+    },
+    std::result,
+};
+
+type Result<T> = result::Result<T, ScriptError>;
+
+/// An instruction of a script. Stack elements are byte strings,
+/// numbers are unsigned little endian without trailing zero bytes
+/// and anything with a non-zero byte is true
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Op {
+    /// Push the bytes. Up to 8 bytes that are empty or end in a
+    /// non-zero byte are a number, which only `Number` may push
+    Push(Vec<u8>),
+    /// Push the number
+    Number(u64),
+    /// Run the following operations if the popped element is true,
+    /// which has to be empty or a single 1
+    If,
+    /// Run the following operations if the popped element is false,
+    /// which has to be empty or a single 1
+    NotIf,
+    Else,
+    EndIf,
+    /// Fail unless the popped element is true
+    Verify,
+    /// Fail
+    Return,
+    Dup,
+    Drop,
+    Swap,
+    /// Push whether the two popped elements are equal
+    Equal,
+    EqualVerify,
+    /// Replace the top element by its SHA-256
+    Sha256,
+    /// Replace the top element by its `PubkeyHash::digest`
+    Hash160,
+    /// Pop a public key and a signature, push whether the signature
+    /// signs the spent output with that key
+    CheckSig,
+    CheckSigVerify,
+    /// Pop the key count, the keys, the threshold and that many
+    /// signatures, push whether each signature is by a different
+    /// key, in the order of the keys
+    CheckMultiSig,
+    CheckMultiSigVerify,
+    /// Fail unless the transaction's lock time is at least the
+    /// popped height or timestamp
+    CheckLockTimeVerify,
+    /// Fail unless the input's relative lock is at least the popped
+    /// one, encoded like sequence numbers
+    CheckSequenceVerify,
+}
+
+/// Conditions for spending an output (`Lock::Script`), or what an
+/// input provides to meet them
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Script(pub Vec<Op>);
+
+impl Script {
+    // spendable by revealing the key with `pubkey_hash` in the
+    // unlocking script, like `Lock::PubkeyHash`
+    pub fn pay_to_pubkey_hash(pubkey_hash: &PubkeyHash) -> Self {
+        Script(vec![
+            Op::Dup,
+            Op::Hash160,
+            Op::Push(pubkey_hash.0.to_vec()),
+            Op::EqualVerify,
+            Op::CheckSig,
+        ])
+    }
+
+    // spendable with signatures by `threshold` of the keys, the
+    // input's signature being the first of them
+    pub fn multisig(threshold: usize, pubkeys: &[PublicKey]) -> Self {
+        let mut ops = vec![Op::Number(threshold as u64)];
+        ops.extend(pubkeys.iter().map(|pubkey| Op::Push(pubkey.to_bytes())));
+        ops.push(Op::Number(pubkeys.len() as u64));
+        ops.push(Op::CheckMultiSig);
+        Script(ops)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn is_push_only(&self) -> bool {
+        self.0
+            .iter()
+            .all(|op| matches!(op, Op::Push(_) | Op::Number(_)))
+    }
+
+    // check that `input` may spend an output locked to this script:
+    // the input's signature goes on the stack first, then its
+    // unlocking script runs, then this script, which has to leave a
+    // true element on top. Signatures are checked against the
+    // input's `signature_hash`
+    pub fn verify(
+        &self,
+        transaction: &Transaction,
+        input: &TransactionInput,
+        signature_hash: &Hash,
+    ) -> Result<()> {
+        if serialized_size(self) > crate::MAX_SCRIPT_SIZE
+            || serialized_size(&input.unlock) > crate::MAX_SCRIPT_SIZE
+        {
+            return Err(ScriptError::ScriptTooLarge);
+        }
+        // anything else would let third parties change the input
+        if !input.unlock.is_push_only() {
+            return Err(ScriptError::NotPushOnly);
+        }
+        let mut interpreter = Interpreter {
+            transaction,
+            input,
+            signature_hash,
+            stack: vec![input.signature.to_bytes()],
+            ops: 0,
+        };
+        interpreter.run(&input.unlock)?;
+        // every script gets its own operation budget
+        interpreter.ops = 0;
+        interpreter.run(self)?;
+        match interpreter.stack.last() {
+            Some(top) if is_true(top) => Ok(()),
+            _ => Err(ScriptError::EvalFalse),
+        }
+    }
+}
+
+struct Interpreter<'a> {
+    transaction: &'a Transaction,
+    input: &'a TransactionInput,
+    signature_hash: &'a Hash,
+    stack: Vec<Vec<u8>>,
+    /// Non-push operations so far
+    ops: usize,
+}
+
+impl Interpreter<'_> {
+    fn run(&mut self, script: &Script) -> Result<()> {
+        // whether each enclosing branch runs
+        let mut conditions: Vec<bool> = vec![];
+        for op in script.0.iter() {
+            if !matches!(op, Op::Push(_) | Op::Number(_)) {
+                self.count_ops(1)?;
+            }
+            let executing = conditions.iter().all(|&condition| condition);
+            match op {
+                Op::If | Op::NotIf => {
+                    let condition = executing && self.pop_bool()? == matches!(op, Op::If);
+                    conditions.push(condition);
+                }
+                Op::Else => {
+                    let condition = conditions
+                        .last_mut()
+                        .ok_or(ScriptError::UnbalancedConditional)?;
+                    *condition = !*condition;
+                }
+                Op::EndIf => {
+                    conditions.pop().ok_or(ScriptError::UnbalancedConditional)?;
+                }
+                _ if !executing => {}
+                _ => self.execute(op)?,
+            }
+            if self.stack.len() > crate::MAX_SCRIPT_STACK_SIZE {
+                return Err(ScriptError::StackOverflow);
+            }
+        }
+        if !conditions.is_empty() {
+            return Err(ScriptError::UnbalancedConditional);
+        }
+        Ok(())
+    }
+
+    fn execute(&mut self, op: &Op) -> Result<()> {
+        match op {
+            Op::Push(data) => {
+                // anyone could swap one encoding of a number for the
+                // other without invalidating the input
+                if data.len() <= 8 && data.last() != Some(&0) {
+                    return Err(ScriptError::NonMinimal);
+                }
+                self.push(data.clone())?;
+            }
+            Op::Number(number) => self.push(encode_number(*number))?,
+            Op::Verify => self.verify()?,
+            Op::Return => return Err(ScriptError::Return),
+            Op::Dup => {
+                let top = self.stack.last().ok_or(ScriptError::StackUnderflow)?;
+                self.push(top.clone())?;
+            }
+            Op::Drop => {
+                self.pop()?;
+            }
+            Op::Swap => {
+                let (a, b) = (self.pop()?, self.pop()?);
+                self.stack.push(a);
+                self.stack.push(b);
+            }
+            Op::Equal | Op::EqualVerify => {
+                let equal = self.pop()? == self.pop()?;
+                self.push(encode_bool(equal))?;
+                if *op == Op::EqualVerify {
+                    self.verify()?;
+                }
+            }
+            Op::Sha256 => {
+                let data = self.pop()?;
+                self.push(hex::decode(sha256::digest(data)).unwrap())?;
+            }
+            Op::Hash160 => {
+                let data = self.pop()?;
+                self.push(PubkeyHash::digest(&data).0.to_vec())?;
+            }
+            Op::CheckSig | Op::CheckSigVerify => {
+                let pubkey = self.pop()?;
+                let signature = self.pop()?;
+                let valid = self.check_signature(&signature, &pubkey);
+                self.push(encode_bool(valid))?;
+                if *op == Op::CheckSigVerify {
+                    self.verify()?;
+                }
+            }
+            Op::CheckMultiSig | Op::CheckMultiSigVerify => {
+                let valid = self.check_multisig()?;
+                self.push(encode_bool(valid))?;
+                if *op == Op::CheckMultiSigVerify {
+                    self.verify()?;
+                }
+            }
+            Op::CheckLockTimeVerify => {
+                let lock_time = self.pop_number()?;
+                let lock_time_kind = lock_time < crate::LOCKTIME_THRESHOLD;
+                let transaction_kind = self.transaction.lock_time < crate::LOCKTIME_THRESHOLD;
+                // a final input would not enforce the lock time
+                if lock_time_kind != transaction_kind
+                    || lock_time > self.transaction.lock_time
+                    || self.input.sequence == crate::SEQUENCE_FINAL
+                {
+                    return Err(ScriptError::LockTime);
+                }
+            }
+            Op::CheckSequenceVerify => {
+                let sequence =
+                    u32::try_from(self.pop_number()?).map_err(|_| ScriptError::InvalidNumber)?;
+                let Some(required) = RelativeLock::from_sequence(sequence) else {
+                    return Ok(());
+                };
+                let satisfied = match (required, self.input.relative_lock()) {
+                    (RelativeLock::Blocks(required), Some(RelativeLock::Blocks(actual))) => {
+                        actual >= required
+                    }
+                    (RelativeLock::Seconds(required), Some(RelativeLock::Seconds(actual))) => {
+                        actual >= required
+                    }
+                    _ => false,
+                };
+                if !satisfied {
+                    return Err(ScriptError::Sequence);
+                }
+            }
+            Op::If | Op::NotIf | Op::Else | Op::EndIf => unreachable!(),
+        }
+        Ok(())
+    }
+
+    fn check_signature(&self, signature: &[u8], pubkey: &[u8]) -> bool {
+        match (
+            Signature::from_bytes(signature),
+            PublicKey::from_bytes(pubkey),
+        ) {
+            (Some(signature), Some(pubkey)) => signature.verify(self.signature_hash, &pubkey),
+            _ => false,
+        }
+    }
+
+    fn check_multisig(&mut self) -> Result<bool> {
+        let key_count = self.pop_number()? as usize;
+        if key_count > crate::MAX_MULTISIG_KEYS {
+            return Err(ScriptError::InvalidMultisig);
+        }
+        self.count_ops(key_count)?;
+        let mut pubkeys = (0..key_count)
+            .map(|_| self.pop())
+            .collect::<Result<Vec<_>>>()?;
+        pubkeys.reverse();
+        let threshold = self.pop_number()? as usize;
+        if threshold > key_count {
+            return Err(ScriptError::InvalidMultisig);
+        }
+        let mut signatures = (0..threshold)
+            .map(|_| self.pop())
+            .collect::<Result<Vec<_>>>()?;
+        signatures.reverse();
+
+        // every signature has to match one of the keys after the key
+        // of the previous signature
+        let mut pubkeys = pubkeys.iter();
+        Ok(signatures.iter().all(|signature| {
+            pubkeys
+                .by_ref()
+                .any(|pubkey| self.check_signature(signature, pubkey))
+        }))
+    }
+
+    fn count_ops(&mut self, count: usize) -> Result<()> {
+        self.ops += count;
+        if self.ops > crate::MAX_SCRIPT_OPS {
+            return Err(ScriptError::TooManyOps);
+        }
+        Ok(())
+    }
+
+    fn push(&mut self, element: Vec<u8>) -> Result<()> {
+        if element.len() > crate::MAX_SCRIPT_ELEMENT_SIZE {
+            return Err(ScriptError::ElementTooLarge);
+        }
+        self.stack.push(element);
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<Vec<u8>> {
+        self.stack.pop().ok_or(ScriptError::StackUnderflow)
+    }
+
+    fn pop_number(&mut self) -> Result<u64> {
+        let bytes = self.pop()?;
+        if bytes.len() > 8 {
+            return Err(ScriptError::InvalidNumber);
+        }
+        if bytes.last() == Some(&0) {
+            return Err(ScriptError::NonMinimal);
+        }
+        let mut number = [0u8; 8];
+        number[..bytes.len()].copy_from_slice(&bytes);
+        Ok(u64::from_le_bytes(number))
+    }
+
+    // conditions only take the encodings of false and true, so that
+    // they cannot be changed for another true element
+    fn pop_bool(&mut self) -> Result<bool> {
+        match self.pop()?.as_slice() {
+            [] => Ok(false),
+            [1] => Ok(true),
+            _ => Err(ScriptError::NonMinimal),
+        }
+    }
+
+    fn verify(&mut self) -> Result<()> {
+        if !is_true(&self.pop()?) {
+            return Err(ScriptError::VerifyFailed);
+        }
+        Ok(())
+    }
+}
+
+fn is_true(element: &[u8]) -> bool {
+    element.iter().any(|&byte| byte != 0)
+}
+
+fn encode_bool(value: bool) -> Vec<u8> {
+    if value {
+        vec![1]
+    } else {
+        vec![]
+    }
+}
+
+fn encode_number(number: u64) -> Vec<u8> {
+    let bytes = number.to_le_bytes();
+    let len = 8 - number.leading_zeros() as usize / 8;
+    bytes[..len].to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::crypto::PrivateKey,
+    };
+
+    // verify `lock` for an input signed by `signer` with the sequence
+    // number `sequence`, in a transaction with `lock_time`. `unlock`
+    // builds the unlocking script from the signature hash
+    fn spend(
+        lock: &Script,
+        sequence: u32,
+        lock_time: u64,
+        signer: &PrivateKey,
+        unlock: impl FnOnce(&Hash) -> Vec<Op>,
+    ) -> Result<()> {
+        let hash = Hash::hash(&"spent output");
+        let input = TransactionInput {
+            prev_tx_output_hash: hash,
+            signature: Signature::sign(&hash, signer),
+            sequence,
+            pubkey: None,
+            unlock: Script::default(),
+            extra_signatures: vec![],
+        };
+        let mut transaction = Transaction::new(vec![input], vec![]);
+        transaction.lock_time = lock_time;
+        let signature_hash = transaction.signature_hashes()[0];
+        transaction.inputs[0].signature = Signature::sign(&signature_hash, signer);
+        transaction.inputs[0].unlock = Script(unlock(&signature_hash));
+        lock.verify(&transaction, &transaction.inputs[0], &signature_hash)
+    }
+
+    // run `lock` for an input with the unlocking script `unlock` and
+    // the sequence number `sequence`
+    fn run(lock: Vec<Op>, unlock: Vec<Op>, sequence: u32) -> Result<()> {
+        spend(&Script(lock), sequence, 0, &PrivateKey::new_key(), |_| {
+            unlock
+        })
+    }
+
+    fn push_signature(signature_hash: &Hash, signer: &PrivateKey) -> Op {
+        Op::Push(Signature::sign(signature_hash, signer).to_bytes())
+    }
+
+    #[test]
+    fn pushes_of_numbers_are_rejected() {
+        let lock = vec![Op::Drop, Op::Number(1)];
+        assert_eq!(
+            run(lock.clone(), vec![Op::Push(vec![])], 0),
+            Err(ScriptError::NonMinimal)
+        );
+        assert_eq!(
+            run(lock.clone(), vec![Op::Push(vec![1])], 0),
+            Err(ScriptError::NonMinimal)
+        );
+        assert_eq!(
+            run(lock.clone(), vec![Op::Push(vec![1, 2, 3])], 0),
+            Err(ScriptError::NonMinimal)
+        );
+        assert_eq!(run(lock.clone(), vec![Op::Push(vec![0])], 0), Ok(()));
+        assert_eq!(run(lock.clone(), vec![Op::Push(vec![1; 9])], 0), Ok(()));
+        assert_eq!(run(lock, vec![Op::Number(1)], 0), Ok(()));
+    }
+
+    #[test]
+    fn numbers_have_to_be_minimal() {
+        let lock = vec![Op::CheckSequenceVerify, Op::Number(1)];
+        assert_eq!(run(lock.clone(), vec![Op::Number(10)], 10), Ok(()));
+        assert_eq!(
+            run(lock.clone(), vec![Op::Push(vec![10, 0])], 10),
+            Err(ScriptError::NonMinimal)
+        );
+        assert_eq!(
+            run(lock, vec![Op::Push(vec![0])], 10),
+            Err(ScriptError::NonMinimal)
+        );
+    }
+
+    #[test]
+    fn conditions_have_to_be_minimal() {
+        for op in [Op::If, Op::NotIf] {
+            let lock = vec![op, Op::Number(1), Op::Else, Op::Number(1), Op::EndIf];
+            assert_eq!(run(lock.clone(), vec![Op::Number(0)], 0), Ok(()));
+            assert_eq!(run(lock.clone(), vec![Op::Number(1)], 0), Ok(()));
+            assert_eq!(
+                run(lock.clone(), vec![Op::Number(2)], 0),
+                Err(ScriptError::NonMinimal)
+            );
+            assert_eq!(
+                run(lock.clone(), vec![Op::Push(vec![1, 0])], 0),
+                Err(ScriptError::NonMinimal)
+            );
+            assert_eq!(
+                run(lock, vec![Op::Push(vec![0])], 0),
+                Err(ScriptError::NonMinimal)
+            );
+        }
+    }
+
+    #[test]
+    fn check_sig_needs_a_signature_of_the_signature_hash() {
+        let key = PrivateKey::new_key();
+        let lock = Script(vec![Op::Push(key.public_key().to_bytes()), Op::CheckSig]);
+        assert_eq!(spend(&lock, 0, 0, &key, |_| vec![]), Ok(()));
+        assert_eq!(
+            spend(&lock, 0, 0, &PrivateKey::new_key(), |_| vec![]),
+            Err(ScriptError::EvalFalse)
+        );
+        // a signature of anything else than the signature hash
+        let other = Hash::hash(&"other");
+        let lock = Script(vec![
+            Op::Push(key.public_key().to_bytes()),
+            Op::CheckSigVerify,
+            Op::Number(1),
+        ]);
+        assert_eq!(
+            spend(&lock, 0, 0, &key, |hash| vec![push_signature(hash, &key)]),
+            Ok(())
+        );
+        assert_eq!(
+            spend(&lock, 0, 0, &key, |_| vec![push_signature(&other, &key)]),
+            Err(ScriptError::VerifyFailed)
+        );
+        assert_eq!(
+            spend(&lock, 0, 0, &key, |_| vec![Op::Push(vec![1; 9])]),
+            Err(ScriptError::VerifyFailed)
+        );
+    }
+
+    #[test]
+    fn pay_to_pubkey_hash_needs_the_key_and_its_signature() {
+        let key = PrivateKey::new_key();
+        let other = PrivateKey::new_key();
+        let lock = Script::pay_to_pubkey_hash(&key.public_key().pubkey_hash());
+        let reveal = |key: &PrivateKey| vec![Op::Push(key.public_key().to_bytes())];
+        assert_eq!(spend(&lock, 0, 0, &key, |_| reveal(&key)), Ok(()));
+        assert_eq!(
+            spend(&lock, 0, 0, &other, |_| reveal(&other)),
+            Err(ScriptError::VerifyFailed)
+        );
+        assert_eq!(
+            spend(&lock, 0, 0, &other, |_| reveal(&key)),
+            Err(ScriptError::EvalFalse)
+        );
+        assert_eq!(
+            spend(&lock, 0, 0, &key, |_| vec![]),
+            Err(ScriptError::VerifyFailed)
+        );
+    }
+
+    #[test]
+    fn hash_locks_need_the_preimage() {
+        let preimage = b"the secret preimage".to_vec();
+        let sha256 = hex::decode(sha256::digest(&preimage)).unwrap();
+        let hash160 = PubkeyHash::digest(&preimage).0.to_vec();
+        for (op, digest) in [(Op::Sha256, sha256), (Op::Hash160, hash160)] {
+            let lock = vec![op, Op::Push(digest), Op::Equal];
+            assert_eq!(
+                run(lock.clone(), vec![Op::Push(preimage.clone())], 0),
+                Ok(())
+            );
+            assert_eq!(
+                run(
+                    lock.clone(),
+                    vec![Op::Push(b"another preimage".to_vec())],
+                    0
+                ),
+                Err(ScriptError::EvalFalse)
+            );
+            // the input's signature is not the preimage
+            assert_eq!(run(lock, vec![], 0), Err(ScriptError::EvalFalse));
+        }
+    }
+
+    #[test]
+    fn check_lock_time_verify_needs_a_later_lock_time_of_the_same_kind() {
+        let check = |required: u64, sequence: u32, lock_time: u64| {
+            let lock = Script(vec![
+                Op::Number(required),
+                Op::CheckLockTimeVerify,
+                Op::Number(1),
+            ]);
+            spend(
+                &lock,
+                sequence,
+                lock_time,
+                &PrivateKey::new_key(),
+                |_| vec![],
+            )
+        };
+        let time = crate::LOCKTIME_THRESHOLD + 1000;
+        assert_eq!(check(100, 0, 100), Ok(()));
+        assert_eq!(check(100, 0, 101), Ok(()));
+        assert_eq!(check(100, 0, 99), Err(ScriptError::LockTime));
+        assert_eq!(check(time, 0, time), Ok(()));
+        assert_eq!(check(time, 0, time - 1), Err(ScriptError::LockTime));
+        // heights and times do not compare
+        assert_eq!(check(100, 0, time), Err(ScriptError::LockTime));
+        assert_eq!(check(time, 0, 100), Err(ScriptError::LockTime));
+        // the lock time of a final input is not enforced
+        assert_eq!(
+            check(100, crate::SEQUENCE_FINAL, 100),
+            Err(ScriptError::LockTime)
+        );
+    }
+
+    #[test]
+    fn check_sequence_verify_needs_a_longer_relative_lock_of_the_same_kind() {
+        let check = |required: u32, sequence: u32| {
+            let lock = vec![
+                Op::Number(required as u64),
+                Op::CheckSequenceVerify,
+                Op::Number(1),
+            ];
+            run(lock, vec![], sequence)
+        };
+        let seconds = |units: u32| crate::SEQUENCE_LOCKTIME_TYPE_FLAG | units;
+        assert_eq!(check(10, 10), Ok(()));
+        assert_eq!(check(10, 11), Ok(()));
+        assert_eq!(check(10, 9), Err(ScriptError::Sequence));
+        assert_eq!(check(seconds(10), seconds(10)), Ok(()));
+        assert_eq!(check(seconds(10), seconds(9)), Err(ScriptError::Sequence));
+        assert_eq!(check(10, seconds(10)), Err(ScriptError::Sequence));
+        assert_eq!(check(seconds(10), 10), Err(ScriptError::Sequence));
+        // the input has to hold a relative lock, unless the script
+        // does not require one
+        let disabled = crate::SEQUENCE_LOCKTIME_DISABLE_FLAG;
+        assert_eq!(check(10, disabled | 10), Err(ScriptError::Sequence));
+        assert_eq!(check(10, crate::SEQUENCE_FINAL), Err(ScriptError::Sequence));
+        assert_eq!(check(disabled | 10, 0), Ok(()));
+    }
+
+    #[test]
+    fn multisig_needs_the_threshold_of_signatures_in_key_order() {
+        let keys = [
+            PrivateKey::new_key(),
+            PrivateKey::new_key(),
+            PrivateKey::new_key(),
+        ];
+        let pubkeys = keys.iter().map(PrivateKey::public_key).collect::<Vec<_>>();
+        let lock = Script::multisig(2, &pubkeys);
+        // the input's signature is the first one
+        let check = |first: usize, second: usize| {
+            spend(&lock, 0, 0, &keys[first], |hash| {
+                vec![push_signature(hash, &keys[second])]
+            })
+        };
+        assert_eq!(check(0, 1), Ok(()));
+        assert_eq!(check(0, 2), Ok(()));
+        assert_eq!(check(1, 2), Ok(()));
+        assert_eq!(check(2, 0), Err(ScriptError::EvalFalse));
+        assert_eq!(check(1, 1), Err(ScriptError::EvalFalse));
+        assert_eq!(
+            spend(&lock, 0, 0, &keys[0], |_| vec![]),
+            Err(ScriptError::StackUnderflow)
+        );
+        let stranger = PrivateKey::new_key();
+        assert_eq!(
+            spend(&lock, 0, 0, &keys[0], |hash| {
+                vec![push_signature(hash, &stranger)]
+            }),
+            Err(ScriptError::EvalFalse)
+        );
+
+        let verify = Script(vec![
+            Op::Number(1),
+            Op::Push(pubkeys[0].to_bytes()),
+            Op::Number(1),
+            Op::CheckMultiSigVerify,
+            Op::Number(1),
+        ]);
+        assert_eq!(spend(&verify, 0, 0, &keys[0], |_| vec![]), Ok(()));
+        assert_eq!(
+            spend(&verify, 0, 0, &keys[1], |_| vec![]),
+            Err(ScriptError::VerifyFailed)
+        );
+    }
+
+    #[test]
+    fn multisig_needs_a_valid_key_count_and_threshold() {
+        let key = PrivateKey::new_key();
+        let pubkeys = vec![key.public_key(); crate::MAX_MULTISIG_KEYS + 1];
+        let lock = Script::multisig(1, &pubkeys);
+        assert_eq!(
+            spend(&lock, 0, 0, &key, |_| vec![]),
+            Err(ScriptError::InvalidMultisig)
+        );
+        let lock = Script::multisig(1, &pubkeys[..crate::MAX_MULTISIG_KEYS]);
+        assert_eq!(spend(&lock, 0, 0, &key, |_| vec![]), Ok(()));
+        let lock = Script::multisig(2, &pubkeys[..1]);
+        assert_eq!(
+            spend(&lock, 0, 0, &key, |_| vec![]),
+            Err(ScriptError::InvalidMultisig)
+        );
+    }
+
+    #[test]
+    fn operations_are_limited() {
+        // `ops` operations that leave the stack as it is
+        let padding = |ops: usize| (0..ops / 2).flat_map(|_| [Op::Dup, Op::Drop]);
+        let mut lock = vec![Op::Number(1), Op::Verify];
+        lock.extend(padding(crate::MAX_SCRIPT_OPS - 1));
+        assert_eq!(run(lock.clone(), vec![], 0), Ok(()));
+        lock.push(Op::Dup);
+        assert_eq!(run(lock, vec![], 0), Err(ScriptError::TooManyOps));
+
+        // every key of a multisig counts as an operation
+        let key = PrivateKey::new_key();
+        let multisig = Script::multisig(1, &vec![key.public_key(); crate::MAX_MULTISIG_KEYS]);
+        let ops = crate::MAX_SCRIPT_OPS - crate::MAX_MULTISIG_KEYS - 1;
+        let mut lock = padding(ops).collect::<Vec<_>>();
+        lock.extend(multisig.0.clone());
+        assert_eq!(spend(&Script(lock), 0, 0, &key, |_| vec![]), Ok(()));
+        let mut lock = padding(ops + 2).collect::<Vec<_>>();
+        lock.extend(multisig.0);
+        assert_eq!(
+            spend(&Script(lock), 0, 0, &key, |_| vec![]),
+            Err(ScriptError::TooManyOps)
+        );
+    }
+
+    #[test]
+    fn the_stack_is_limited() {
+        // the input's signature is the first element
+        let unlock = vec![Op::Number(1); crate::MAX_SCRIPT_STACK_SIZE - 1];
+        assert_eq!(run(vec![], unlock.clone(), 0), Ok(()));
+        assert_eq!(
+            run(vec![Op::Dup], unlock, 0),
+            Err(ScriptError::StackOverflow)
+        );
+        assert_eq!(run(vec![Op::Drop], vec![], 0), Err(ScriptError::EvalFalse));
+        assert_eq!(
+            run(vec![Op::Drop, Op::Drop], vec![], 0),
+            Err(ScriptError::StackUnderflow)
+        );
+    }
+
+    #[test]
+    fn elements_and_scripts_are_limited() {
+        let element = vec![1; crate::MAX_SCRIPT_ELEMENT_SIZE];
+        assert_eq!(run(vec![], vec![Op::Push(element.clone())], 0), Ok(()));
+        let mut too_large = element.clone();
+        too_large.push(1);
+        assert_eq!(
+            run(vec![], vec![Op::Push(too_large)], 0),
+            Err(ScriptError::ElementTooLarge)
+        );
+        let pushes = crate::MAX_SCRIPT_SIZE / crate::MAX_SCRIPT_ELEMENT_SIZE + 1;
+        assert_eq!(
+            run(vec![], vec![Op::Push(element.clone()); pushes], 0),
+            Err(ScriptError::ScriptTooLarge)
+        );
+        assert_eq!(
+            run(vec![Op::Push(element); pushes], vec![], 0),
+            Err(ScriptError::ScriptTooLarge)
+        );
+    }
+}
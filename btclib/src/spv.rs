@@ -0,0 +1,528 @@
+use {
+    crate::{
+        address::PubkeyHash,
+        clock::{
+            Clock,
+            SystemClock,
+        },
+        difficulty::DifficultyAdjustment,
+        error::{
+            BtcError,
+            Result,
+        },
+        sha256::Hash,
+        types::{
+            block::Block,
+            block_header::BlockHeader,
+            transaction::{
+                Transaction,
+                TransactionOutput,
+            },
+        },
+        util::{
+            MerkleProof,
+            MerkleRoot,
+            PartialMerkleTree,
+            Saveable,
+        },
+        U256,
+    },
+    chrono::{
+        DateTime,
+        Utc,
+    },
+    std::{
+        collections::HashSet,
+        io::{
+            Error as IoError,
+            ErrorKind as IoErrorKind,
+            Read,
+            Result as IoResult,
+            Write,
+        },
+        sync::Arc,
+    },
+};
+
+/// The block headers of a chain without the transactions, which is
+/// all a light client keeps. Headers are checked like full nodes
+/// check them, except for the transactions behind the merkle root
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HeaderChain {
+    headers: Vec<BlockHeader>,
+    /// How the target changes over time, has to match the one of
+    /// the full nodes
+    #[serde(skip)]
+    difficulty: DifficultyAdjustment,
+    /// What header timestamps are checked against
+    #[serde(skip, default = "system_clock")]
+    clock: Arc<dyn Clock>,
+}
+
+fn system_clock() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
+
+impl HeaderChain {
+    pub fn new(difficulty: DifficultyAdjustment) -> Self {
+        HeaderChain {
+            headers: vec![],
+            difficulty,
+            clock: system_clock(),
+        }
+    }
+
+    pub fn height(&self) -> u64 {
+        self.headers.len() as u64
+    }
+
+    pub fn headers(&self) -> impl Iterator<Item = &BlockHeader> {
+        self.headers.iter()
+    }
+
+    pub fn header(&self, height: u64) -> Option<&BlockHeader> {
+        self.headers.get(height as usize)
+    }
+
+    pub fn difficulty(&self) -> DifficultyAdjustment {
+        self.difficulty
+    }
+
+    pub fn set_difficulty(&mut self, difficulty: DifficultyAdjustment) {
+        self.difficulty = difficulty;
+    }
+
+    // replace the clock header timestamps are checked against
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    // total work of the headers
+    pub fn chainwork(&self) -> U256 {
+        self.headers.iter().fold(U256::zero(), |chainwork, header| {
+            chainwork.saturating_add(header.work())
+        })
+    }
+
+    // median timestamp of the last MEDIAN_TIME_SPAN headers
+    pub fn median_time_past(&self) -> DateTime<Utc> {
+        let start = self.headers.len().saturating_sub(crate::MEDIAN_TIME_SPAN);
+        let mut timestamps = self.headers[start..]
+            .iter()
+            .map(|header| header.timestamp)
+            .collect::<Vec<_>>();
+        timestamps.sort();
+        timestamps
+            .get(timestamps.len() / 2)
+            .copied()
+            .unwrap_or(DateTime::<Utc>::MIN_UTC)
+    }
+
+    // the target the next header has to be mined at
+    pub fn next_target(&self) -> U256 {
+        let headers = self.headers.iter().collect::<Vec<_>>();
+        self.difficulty.algorithm().next_target(&headers)
+    }
+
+    pub fn add_header(&mut self, header: BlockHeader) -> Result<()> {
+        // check if the timestamp is not too far in the future
+        let max_timestamp =
+            self.clock.now() + chrono::Duration::seconds(crate::MAX_FUTURE_BLOCK_TIME as i64);
+        if header.timestamp > max_timestamp {
+            return Err(BtcError::InvalidBlockHeader);
+        }
+        match self.headers.last() {
+            None => {
+                // the genesis block is not mined, but has to carry the
+                // initial target
+                if header.prev_block_hash != Hash::zero()
+                    || header.bits != crate::MIN_TARGET.to_compact()
+                {
+                    return Err(BtcError::InvalidBlockHeader);
+                }
+            }
+            Some(last_header) => {
+                if header.prev_block_hash != last_header.hash() {
+                    return Err(BtcError::InvalidBlockHeader);
+                }
+                // check the proof of work at the expected difficulty
+                if header.bits != self.next_target().to_compact()
+                    || !header.hash().matches_target(header.target())
+                {
+                    return Err(BtcError::InvalidBlockHeader);
+                }
+                if header.timestamp <= self.median_time_past() {
+                    return Err(BtcError::InvalidBlockHeader);
+                }
+            }
+        }
+        self.headers.push(header);
+        Ok(())
+    }
+
+    // whether the proven transaction is in the block at the proof's
+    // height
+    pub fn verify_transaction_proof(&self, proof: &TransactionProof) -> bool {
+        self.header(proof.height).is_some_and(|header| {
+            proof
+                .proof
+                .verify(&proof.transaction.hash(), &header.merkle_root)
+        })
+    }
+
+    // check that the block is the one of the chain at `height`, and
+    // that its transactions are the ones the header commits to
+    pub fn verify_block(&self, height: u64, block: &Block) -> Result<()> {
+        let header = self.header(height).ok_or(BtcError::InvalidBlockHeader)?;
+        if header.hash() != block.hash() {
+            return Err(BtcError::InvalidBlockHeader);
+        }
+        let (merkle_root, mutated) = MerkleRoot::calculate_checked(&block.transactions);
+        if merkle_root != header.merkle_root || mutated {
+            return Err(BtcError::InvalidMerkleRoot);
+        }
+        Ok(())
+    }
+
+    // check that the filtered block is one of the chain and that its
+    // transactions are the ones its merkle tree matches
+    pub fn verify_filtered_block(&self, block: &FilteredBlock) -> Result<()> {
+        let header = self
+            .header(block.height)
+            .ok_or(BtcError::InvalidBlockHeader)?;
+        if header.hash() != block.header.hash() {
+            return Err(BtcError::InvalidBlockHeader);
+        }
+        let (merkle_root, matches) = block
+            .tree
+            .extract_matches()
+            .ok_or(BtcError::InvalidMerkleRoot)?;
+        if merkle_root != header.merkle_root {
+            return Err(BtcError::InvalidMerkleRoot);
+        }
+        if matches.len() != block.transactions.len()
+            || matches
+                .iter()
+                .zip(block.transactions.iter())
+                .any(|((_, hash), transaction)| *hash != transaction.hash())
+        {
+            return Err(BtcError::InvalidTransaction);
+        }
+        Ok(())
+    }
+}
+
+impl Saveable for HeaderChain {
+    fn load<I: Read>(reader: I) -> IoResult<Self> {
+        ciborium::de::from_reader(reader).map_err(|_| {
+            IoError::new(
+                IoErrorKind::InvalidData,
+                "Failed to deserialize HeaderChain",
+            )
+        })
+    }
+
+    fn save<O: Write>(&self, writer: O) -> IoResult<()> {
+        ciborium::ser::into_writer(self, writer)
+            .map_err(|_| IoError::new(IoErrorKind::InvalidData, "Failed to serialize HeaderChain"))
+    }
+}
+
+/// A transaction with a proof that it is in the block at `height`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransactionProof {
+    pub transaction: Transaction,
+    pub height: u64,
+    pub proof: MerkleProof,
+}
+
+impl TransactionProof {
+    // proof for the transaction with `hash`, None if it is not in
+    // the block
+    pub fn new(block: &Block, height: u64, hash: &Hash) -> Option<Self> {
+        let proof = block.merkle_proof(hash)?;
+        Some(TransactionProof {
+            transaction: block.transactions[proof.index].clone(),
+            height,
+            proof,
+        })
+    }
+}
+
+/// The header of a block with the transactions relevant to a public
+/// key hash and a partial merkle tree proving them (see
+/// `is_relevant`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FilteredBlock {
+    pub height: u64,
+    pub header: BlockHeader,
+    pub tree: PartialMerkleTree,
+    /// Matched transactions in block order
+    pub transactions: Vec<Transaction>,
+}
+
+impl FilteredBlock {
+    // `outpoints` are the hashes of the key's outputs from earlier
+    // blocks. Outputs paying the key in this block are added to
+    // them, so that transactions spending them later in the block
+    // are matched too
+    pub fn new(
+        block: &Block,
+        height: u64,
+        pubkey_hash: &PubkeyHash,
+        outpoints: &HashSet<Hash>,
+    ) -> Self {
+        let mut outpoints = outpoints.clone();
+        let mut transactions = vec![];
+        for transaction in block.transactions.iter() {
+            if !is_relevant(transaction, pubkey_hash, &outpoints) {
+                continue;
+            }
+            outpoints.extend(
+                transaction
+                    .outputs
+                    .iter()
+                    .filter(|output| output.lock.pubkey_hash() == Some(*pubkey_hash))
+                    .map(TransactionOutput::hash),
+            );
+            transactions.push(transaction.clone());
+        }
+        let matched = transactions
+            .iter()
+            .map(Transaction::hash)
+            .collect::<HashSet<_>>();
+        FilteredBlock {
+            height,
+            header: block.header.clone(),
+            tree: PartialMerkleTree::new(&block.transactions, |transaction| {
+                matched.contains(&transaction.hash())
+            }),
+            transactions,
+        }
+    }
+}
+
+// whether the transaction pays the key with `pubkey_hash`, or
+// spends one of its outputs. Inputs spending outputs locked to the
+// key itself do not reveal the key, so they are recognized by the
+// hashes of the spent outputs in `outpoints`
+pub fn is_relevant(
+    transaction: &Transaction,
+    pubkey_hash: &PubkeyHash,
+    outpoints: &HashSet<Hash>,
+) -> bool {
+    transaction
+        .outputs
+        .iter()
+        .any(|output| output.lock.pubkey_hash() == Some(*pubkey_hash))
+        || transaction.inputs.iter().any(|input| {
+            outpoints.contains(&input.prev_tx_output_hash)
+                || input
+                    .pubkey
+                    .as_ref()
+                    .is_some_and(|pubkey| pubkey.pubkey_hash() == *pubkey_hash)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            clock::FixedClock,
+            crypto::{
+                PrivateKey,
+                Signature,
+            },
+            types::{
+                amount::Amount,
+                transaction::{
+                    Lock,
+                    TransactionInput,
+                },
+            },
+        },
+        uuid::Uuid,
+    };
+
+    fn output(lock: Lock) -> TransactionOutput {
+        TransactionOutput {
+            value: Amount::from_sat(1000),
+            unique_id: Uuid::new_v4(),
+            lock,
+        }
+    }
+
+    // a transaction spending `spent` to a new key, the signature
+    // does not matter to light clients
+    fn spend(spent: &TransactionOutput, private_key: &PrivateKey, reveal: bool) -> Transaction {
+        let input = TransactionInput {
+            prev_tx_output_hash: spent.hash(),
+            signature: Signature::sign(&spent.hash(), private_key),
+            sequence: crate::SEQUENCE_FINAL,
+            pubkey: reveal.then(|| private_key.public_key()),
+            unlock: Default::default(),
+            extra_signatures: vec![],
+        };
+        Transaction::new(
+            vec![input],
+            vec![output(PrivateKey::new_key().public_key().into())],
+        )
+    }
+
+    fn block(transactions: Vec<Transaction>, prev: Option<&BlockHeader>, target: U256) -> Block {
+        let (timestamp, prev_block_hash) = match prev {
+            Some(prev) => (prev.timestamp + chrono::Duration::seconds(10), prev.hash()),
+            None => (Utc::now() - chrono::Duration::minutes(1), Hash::zero()),
+        };
+        let mut header = BlockHeader::new(
+            timestamp,
+            0,
+            prev_block_hash,
+            MerkleRoot::calculate(&transactions),
+            target,
+        );
+        if prev.is_some() {
+            assert!(header.mine(usize::MAX));
+        }
+        Block::new(header, transactions)
+    }
+
+    // a genesis block paying the key, and a block spending that
+    // output without revealing the key, paying the key again and
+    // spending that within the same block
+    fn blocks(private_key: &PrivateKey) -> (Block, Block) {
+        let genesis_output = output(private_key.public_key().into());
+        let coinbase = Transaction::new_coinbase(0, vec![], vec![genesis_output]);
+        let genesis = block(vec![coinbase], None, crate::MIN_TARGET);
+
+        let stranger = PrivateKey::new_key();
+        let coinbase =
+            Transaction::new_coinbase(1, vec![], vec![output(stranger.public_key().into())]);
+        let spend_genesis = spend(&genesis.transactions[0].outputs[0], private_key, false);
+        let mut pay = spend(&output(stranger.public_key().into()), &stranger, true);
+        pay.outputs.push(output(private_key.public_key().into()));
+        let spend_pay = spend(&pay.outputs[1], private_key, false);
+        let unrelated = spend(&output(stranger.public_key().into()), &stranger, true);
+        let transactions = vec![coinbase, spend_genesis, pay, spend_pay, unrelated];
+        let chain = chain(&[&genesis]);
+        let next = block(transactions, Some(&genesis.header), chain.next_target());
+        (genesis, next)
+    }
+
+    fn chain(blocks: &[&Block]) -> HeaderChain {
+        let mut chain = HeaderChain::new(DifficultyAdjustment::default());
+        for block in blocks {
+            chain.add_header(block.header.clone()).unwrap();
+        }
+        chain
+    }
+
+    #[test]
+    fn headers_are_checked_like_blocks() {
+        let (genesis, next) = blocks(&PrivateKey::new_key());
+        let mut empty = HeaderChain::new(DifficultyAdjustment::default());
+        let mut header = genesis.header.clone();
+        header.bits = (crate::MIN_TARGET / 2).to_compact();
+        assert!(empty.add_header(header).is_err());
+        let mut header = genesis.header.clone();
+        header.prev_block_hash = next.hash();
+        assert!(empty.add_header(header).is_err());
+
+        let chain = chain(&[&genesis]);
+        let mut header = next.header.clone();
+        header.prev_block_hash = Hash::zero();
+        assert!(chain.clone().add_header(header).is_err());
+        // mined at an easier target than the expected one
+        let mut header = next.header.clone();
+        header.bits = U256::MAX.to_compact();
+        assert!(header.mine(usize::MAX));
+        assert!(chain.clone().add_header(header).is_err());
+        // not mined at all
+        let mut header = next.header.clone();
+        while header.hash().matches_target(header.target()) {
+            header.nonce += 1;
+        }
+        assert!(chain.clone().add_header(header).is_err());
+        // not after the median time past
+        let mut header = next.header.clone();
+        header.timestamp = genesis.header.timestamp;
+        assert!(header.mine(usize::MAX));
+        assert!(chain.clone().add_header(header).is_err());
+
+        // too far ahead of the clock
+        let max_future = chrono::Duration::seconds(crate::MAX_FUTURE_BLOCK_TIME as i64);
+        let mut ahead = chain.clone();
+        ahead.set_clock(Arc::new(FixedClock(
+            next.header.timestamp - max_future - chrono::Duration::seconds(1),
+        )));
+        assert!(ahead.add_header(next.header.clone()).is_err());
+        let mut chain = chain;
+        chain.set_clock(Arc::new(FixedClock(next.header.timestamp - max_future)));
+        chain.add_header(next.header.clone()).unwrap();
+        assert_eq!(chain.height(), 2);
+        assert_eq!(
+            chain.chainwork(),
+            genesis.header.work() + next.header.work()
+        );
+    }
+
+    #[test]
+    fn transaction_proofs_hold_for_their_block_only() {
+        let (genesis, next) = blocks(&PrivateKey::new_key());
+        let chain = chain(&[&genesis, &next]);
+        for transaction in next.transactions.iter() {
+            let proof = TransactionProof::new(&next, 1, &transaction.hash()).unwrap();
+            assert!(chain.verify_transaction_proof(&proof));
+            let mut wrong = proof.clone();
+            wrong.height = 0;
+            assert!(!chain.verify_transaction_proof(&wrong));
+            wrong.height = 2;
+            assert!(!chain.verify_transaction_proof(&wrong));
+        }
+        let mut proof = TransactionProof::new(&next, 1, &next.transactions[1].hash()).unwrap();
+        proof.transaction = next.transactions[2].clone();
+        assert!(!chain.verify_transaction_proof(&proof));
+        assert!(TransactionProof::new(&next, 1, &genesis.transactions[0].hash()).is_none());
+    }
+
+    #[test]
+    fn filtered_blocks_match_payments_and_spends() {
+        let private_key = PrivateKey::new_key();
+        let pubkey_hash = private_key.public_key().pubkey_hash();
+        let (genesis, next) = blocks(&private_key);
+        let chain = chain(&[&genesis, &next]);
+        let hashes = |block: &FilteredBlock| {
+            block
+                .transactions
+                .iter()
+                .map(Transaction::hash)
+                .collect::<Vec<_>>()
+        };
+
+        // the spend of the genesis output is only found by its
+        // outpoint, the spend of the output paid in the same block
+        // is found either way
+        let outpoints = HashSet::from([genesis.transactions[0].outputs[0].hash()]);
+        let filtered = FilteredBlock::new(&next, 1, &pubkey_hash, &outpoints);
+        chain.verify_filtered_block(&filtered).unwrap();
+        let expected = next.transactions[1..4]
+            .iter()
+            .map(Transaction::hash)
+            .collect::<Vec<_>>();
+        assert_eq!(hashes(&filtered), expected);
+        let filtered = FilteredBlock::new(&next, 1, &pubkey_hash, &HashSet::new());
+        chain.verify_filtered_block(&filtered).unwrap();
+        assert_eq!(hashes(&filtered), expected[1..]);
+
+        let mut wrong = filtered.clone();
+        wrong.transactions.pop();
+        assert!(chain.verify_filtered_block(&wrong).is_err());
+        let mut wrong = filtered.clone();
+        wrong.transactions.push(next.transactions[4].clone());
+        assert!(chain.verify_filtered_block(&wrong).is_err());
+        let mut wrong = filtered.clone();
+        wrong.height = 0;
+        assert!(chain.verify_filtered_block(&wrong).is_err());
+    }
+}
@@ -0,0 +1,468 @@
+use {
+    crate::{
+        sha256::Hash,
+        types::{
+            amount::Amount,
+            block::Block,
+            block_header::BlockHeader,
+            blockchain::Blockchain,
+            mempool::Mempool,
+            transaction::{
+                Lock,
+                Transaction,
+                TransactionOutput,
+            },
+            utxo::Utxo,
+        },
+        util::{
+            serialized_size,
+            MerkleRoot,
+        },
+        U256,
+    },
+    chrono::{
+        DateTime,
+        Utc,
+    },
+    std::{
+        cmp::Ordering,
+        collections::{
+            BinaryHeap,
+            HashMap,
+            HashSet,
+        },
+    },
+    uuid::Uuid,
+};
+
+/// A block ready to be mined, together with the fees
+/// its transactions pay to the miner
+#[derive(Debug, Clone)]
+pub struct BlockTemplate {
+    pub block: Block,
+    pub fees: Amount,
+}
+
+impl BlockTemplate {
+    /// Build a template on top of the blockchain's tip, picking
+    /// the mempool transactions that pay the highest fee rate and
+    /// paying the block reward and fees to `lock`
+    pub fn new(blockchain: &Blockchain, lock: Lock) -> Self {
+        let mut coinbase = Transaction::new_coinbase(
+            blockchain.block_height(),
+            vec![],
+            vec![TransactionOutput {
+                lock,
+                value: Amount::ZERO,
+                unique_id: Uuid::new_v4(),
+            }],
+        );
+        // leave room for the header and the coinbase at their
+        // largest. CBOR encodes numbers and timestamps with variable
+        // length, mining changes the nonce, the timestamp, the extra
+        // nonce and with it the merkle root, and the coinbase value
+        // is only known once the transactions are picked
+        let header = BlockHeader {
+            timestamp: DateTime::<Utc>::MAX_UTC,
+            nonce: u64::MAX,
+            prev_block_hash: Hash(U256::MAX),
+            merkle_root: MerkleRoot::calculate(std::slice::from_ref(&coinbase)),
+            bits: u32::MAX,
+        };
+        let header_size = serialized_size(&header) - serialized_size(&header.merkle_root)
+            + serialized_size(&Hash(U256::MAX));
+        let mut largest_coinbase = coinbase.clone();
+        largest_coinbase.outputs[0].value = Amount::MAX_MONEY;
+        if let Some(input) = largest_coinbase.coinbase.as_mut() {
+            input.extra_nonce = u64::MAX;
+        }
+        let max_weight = crate::MAX_BLOCK_WEIGHT
+            .saturating_sub(header_size * crate::WITNESS_SCALE_FACTOR)
+            .saturating_sub(largest_coinbase.weight());
+        let (transactions, fees) =
+            select_transactions(blockchain.mempool(), blockchain.utxos(), max_weight);
+
+        coinbase.outputs[0].value = blockchain.calculate_block_reward() + fees;
+        let mut txs = vec![coinbase];
+        txs.extend(transactions);
+
+        let merkle_root = MerkleRoot::calculate(&txs);
+        // the timestamp has to exceed the median time past
+        let timestamp =
+            Utc::now().max(blockchain.median_time_past() + chrono::Duration::seconds(1));
+        let block = Block::new(
+            BlockHeader::new(
+                timestamp,
+                0,
+                blockchain
+                    .blocks()
+                    .last()
+                    .map(|last_block| last_block.hash())
+                    .unwrap_or(Hash::zero()),
+                merkle_root,
+                blockchain.next_target(),
+            ),
+            txs,
+        );
+
+        BlockTemplate { block, fees }
+    }
+}
+
+// a mempool transaction with everything needed to rank it
+struct Candidate<'a> {
+    transaction: &'a Transaction,
+    fee: Amount,
+    weight: usize,
+    // mempool transactions whose outputs this one spends
+    parents: Vec<usize>,
+}
+
+// the fee and weight of a candidate together with its unselected
+// ancestors, best fee rate first
+struct Score {
+    fee: Amount,
+    weight: usize,
+    idx: usize,
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.fee.to_sat() as u128 * other.weight as u128)
+            .cmp(&(other.fee.to_sat() as u128 * self.weight as u128))
+            .then(other.idx.cmp(&self.idx))
+    }
+}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Score {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Score {}
+
+/// Pick mempool transactions by fee rate, so that the selection
+/// weighs at most `max_weight`. A transaction spending the output of another
+/// mempool transaction is only picked together with its
+/// ancestors, ranked by the fee rate of the whole package, and
+/// always placed after them.
+/// Returns the transactions in block order and their total fee.
+pub fn select_transactions(
+    mempool: &Mempool,
+    utxos: &HashMap<Hash, Utxo>,
+    max_weight: usize,
+) -> (Vec<Transaction>, Amount) {
+    let candidates = candidates(mempool, utxos);
+    let mut children = vec![vec![]; candidates.len()];
+    for (idx, candidate) in candidates.iter().enumerate() {
+        for &parent in candidate.iter().flat_map(|candidate| &candidate.parents) {
+            children[parent].push(idx);
+        }
+    }
+    let mut selected = vec![false; candidates.len()];
+    let mut excluded = candidates.iter().map(Option::is_none).collect::<Vec<_>>();
+    // package fee and weight of every candidate, updated as its
+    // ancestors get picked. Queued scores that no longer match are
+    // outdated
+    let mut package_fees = vec![Amount::ZERO; candidates.len()];
+    let mut package_weights = vec![0; candidates.len()];
+    let mut queue = BinaryHeap::new();
+    for idx in 0..candidates.len() {
+        let package = package(idx, &candidates, &selected);
+        // a package is only as good as its worst member
+        if package.iter().any(|&member| excluded[member]) {
+            excluded[idx] = true;
+            continue;
+        }
+        for &member in package.iter() {
+            let candidate = candidates[member].as_ref().expect("Bug: impossible");
+            package_fees[idx] += candidate.fee;
+            package_weights[idx] += candidate.weight;
+        }
+        queue.push(Score {
+            fee: package_fees[idx],
+            weight: package_weights[idx],
+            idx,
+        });
+    }
+
+    let mut order = vec![];
+    let mut weight = 0;
+    let mut fees = Amount::ZERO;
+    while let Some(Score {
+        fee: package_fee,
+        weight: package_weight,
+        idx,
+    }) = queue.pop()
+    {
+        if selected[idx]
+            || excluded[idx]
+            || package_fees[idx] != package_fee
+            || package_weights[idx] != package_weight
+        {
+            continue;
+        }
+        let package = package(idx, &candidates, &selected);
+        if package.iter().any(|&member| excluded[member]) {
+            excluded[idx] = true;
+            continue;
+        }
+        if weight + package_weight > max_weight {
+            // the package does not fit, but its ancestors alone might
+            excluded[idx] = true;
+            continue;
+        }
+        for &member in package.iter() {
+            selected[member] = true;
+            order.push(member);
+        }
+        weight += package_weight;
+        fees += package_fee;
+
+        // the descendants of the picked transactions no longer
+        // need to pay for them
+        for &member in package.iter() {
+            let candidate = candidates[member].as_ref().expect("Bug: impossible");
+            for descendant in descendants(member, &children) {
+                if selected[descendant] || excluded[descendant] {
+                    continue;
+                }
+                package_fees[descendant] = package_fees[descendant] - candidate.fee;
+                package_weights[descendant] -= candidate.weight;
+                queue.push(Score {
+                    fee: package_fees[descendant],
+                    weight: package_weights[descendant],
+                    idx: descendant,
+                });
+            }
+        }
+    }
+
+    let transactions = order
+        .into_iter()
+        .map(|idx| {
+            candidates[idx]
+                .as_ref()
+                .expect("Bug: impossible")
+                .transaction
+                .clone()
+        })
+        .collect();
+    (transactions, fees)
+}
+
+// resolve the mempool parents of every transaction, transactions
+// spending outputs that are neither unspent nor in the mempool
+// anymore become None
+fn candidates<'a>(mempool: &'a Mempool, utxos: &HashMap<Hash, Utxo>) -> Vec<Option<Candidate<'a>>> {
+    let entries = mempool.iter().collect::<Vec<_>>();
+    let indices = entries
+        .iter()
+        .enumerate()
+        .map(|(idx, entry)| (entry.transaction.hash(), idx))
+        .collect::<HashMap<_, _>>();
+
+    entries
+        .iter()
+        .map(|entry| {
+            let inputs_known = entry.transaction.inputs.iter().all(|input| {
+                utxos.contains_key(&input.prev_tx_output_hash)
+                    || mempool.output(&input.prev_tx_output_hash).is_some()
+            });
+            if !inputs_known {
+                return None;
+            }
+            Some(Candidate {
+                transaction: &entry.transaction,
+                fee: entry.fee,
+                weight: entry.weight,
+                parents: entry
+                    .parents
+                    .iter()
+                    .map(|parent| indices.get(parent).copied())
+                    .collect::<Option<_>>()?,
+            })
+        })
+        .collect()
+}
+
+// the transaction at `idx` preceded by all of its unselected
+// ancestors, parents always before their children
+fn package(idx: usize, candidates: &[Option<Candidate>], selected: &[bool]) -> Vec<usize> {
+    let mut package = vec![];
+    let mut visited = HashSet::new();
+    let mut stack = vec![(idx, false)];
+    while let Some((current, parents_done)) = stack.pop() {
+        if parents_done {
+            package.push(current);
+            continue;
+        }
+        if selected[current] || !visited.insert(current) {
+            continue;
+        }
+        stack.push((current, true));
+        if let Some(candidate) = &candidates[current] {
+            stack.extend(candidate.parents.iter().map(|&parent| (parent, false)));
+        }
+    }
+    package
+}
+
+// every candidate depending on the one at `idx`, directly or not
+fn descendants(idx: usize, children: &[Vec<usize>]) -> HashSet<usize> {
+    let mut found = HashSet::new();
+    let mut stack = vec![idx];
+    while let Some(current) = stack.pop() {
+        for &child in children[current].iter() {
+            if found.insert(child) {
+                stack.push(child);
+            }
+        }
+    }
+    found
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            crypto::{
+                PrivateKey,
+                Signature,
+            },
+            types::transaction::TransactionInput,
+        },
+    };
+
+    // a mempool, the confirmed outputs its transactions spend, and
+    // the key all outputs are locked to
+    struct Pool {
+        mempool: Mempool,
+        utxos: HashMap<Hash, Utxo>,
+        private_key: PrivateKey,
+    }
+
+    impl Pool {
+        fn new() -> Self {
+            Pool {
+                mempool: Mempool::default(),
+                utxos: HashMap::new(),
+                private_key: PrivateKey::new_key(),
+            }
+        }
+
+        fn output(&self, value: u64) -> TransactionOutput {
+            TransactionOutput {
+                value: Amount::from_sat(value),
+                unique_id: Uuid::new_v4(),
+                lock: self.private_key.public_key().into(),
+            }
+        }
+
+        // a confirmed output worth 100000 sats
+        fn confirmed(&mut self) -> TransactionOutput {
+            let output = self.output(100_000);
+            self.utxos.insert(
+                output.hash(),
+                Utxo::new(output.clone(), 0, Utc::now(), false),
+            );
+            output
+        }
+
+        // add a transaction spending `spent` that pays `fee`
+        fn spend(&mut self, spent: &TransactionOutput, fee: u64) -> Transaction {
+            let input = TransactionInput {
+                prev_tx_output_hash: spent.hash(),
+                signature: Signature::sign(&spent.hash(), &self.private_key),
+                sequence: crate::SEQUENCE_FINAL,
+                pubkey: None,
+                unlock: Default::default(),
+                extra_signatures: vec![],
+            };
+            let paid = self.output(spent.value.to_sat() - fee);
+            let mut transaction = Transaction::new(vec![input], vec![paid]);
+            let signature_hash = transaction.signature_hashes()[0];
+            transaction.inputs[0].signature = Signature::sign(&signature_hash, &self.private_key);
+            self.mempool
+                .insert(transaction.clone(), &self.utxos, 1, Utc::now())
+                .unwrap();
+            transaction
+        }
+
+        fn select(&self, max_weight: usize) -> (Vec<Hash>, Amount) {
+            let (transactions, fees) = select_transactions(&self.mempool, &self.utxos, max_weight);
+            // the fees are the ones of the picked transactions
+            let paid = transactions
+                .iter()
+                .map(|transaction| self.mempool.get(&transaction.hash()).unwrap().fee)
+                .sum::<Amount>();
+            assert_eq!(fees, paid);
+            (transactions.iter().map(Transaction::hash).collect(), fees)
+        }
+    }
+
+    #[test]
+    fn children_pay_for_their_ancestors() {
+        let mut pool = Pool::new();
+        let confirmed = pool.confirmed();
+        let parent = pool.spend(&confirmed, 1_000);
+        let child = pool.spend(&parent.outputs[0], 2_000);
+        let grandchild = pool.spend(&child.outputs[0], 40_000);
+        let confirmed = pool.confirmed();
+        let other = pool.spend(&confirmed, 10_000);
+
+        let (picked, fees) = pool.select(crate::MAX_BLOCK_WEIGHT);
+        assert_eq!(
+            picked,
+            vec![parent.hash(), child.hash(), grandchild.hash(), other.hash()]
+        );
+        assert_eq!(fees, Amount::from_sat(53_000));
+    }
+
+    #[test]
+    fn parents_come_before_children() {
+        let mut pool = Pool::new();
+        let confirmed = pool.confirmed();
+        let parent = pool.spend(&confirmed, 1_000);
+        let child = pool.spend(&parent.outputs[0], 1_000);
+        // parent and child pay the same fee rate, whichever ranks
+        // first the parent has to be placed first
+        let confirmed = pool.confirmed();
+        let other = pool.spend(&confirmed, 1_500);
+
+        let (picked, _) = pool.select(crate::MAX_BLOCK_WEIGHT);
+        let position = |hash: Hash| picked.iter().position(|picked| *picked == hash);
+        assert_eq!(picked.len(), 3);
+        assert!(position(parent.hash()) < position(child.hash()));
+        assert!(position(other.hash()).is_some());
+    }
+
+    #[test]
+    fn packages_that_do_not_fit_are_skipped() {
+        let mut pool = Pool::new();
+        let confirmed = pool.confirmed();
+        let parent = pool.spend(&confirmed, 1_000);
+        let child = pool.spend(&parent.outputs[0], 40_000);
+        let confirmed = pool.confirmed();
+        let other = pool.spend(&confirmed, 10_000);
+
+        // room for one transaction, the package of two does not fit
+        let max_weight = parent.weight().max(other.weight());
+        let (picked, fees) = pool.select(max_weight);
+        assert_eq!(picked, vec![other.hash()]);
+        assert_eq!(fees, Amount::from_sat(10_000));
+        assert!(!picked.contains(&child.hash()));
+
+        let (picked, fees) = pool.select(0);
+        assert!(picked.is_empty());
+        assert_eq!(fees, Amount::ZERO);
+    }
+}
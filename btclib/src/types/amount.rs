@@ -0,0 +1,188 @@
+use std::{
+    fmt,
+    iter::Sum,
+    ops::{
+        Add,
+        AddAssign,
+        Sub,
+    },
+};
+
+/// An amount of satoshis. Consensus code sticks to the checked
+/// operations, which also fail beyond `MAX_MONEY`; the operators
+/// panic on overflow and are meant for amounts known to be valid
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(transparent)]
+pub struct Amount(u64);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+    pub const MAX_MONEY: Amount = Amount(crate::MAX_MONEY);
+
+    pub const fn from_sat(sat: u64) -> Self {
+        Amount(sat)
+    }
+
+    pub const fn from_btc(btc: u64) -> Self {
+        Amount(btc * crate::SATS_PER_BTC)
+    }
+
+    pub const fn to_sat(self) -> u64 {
+        self.0
+    }
+
+    // whether the amount could exist at all
+    pub fn is_valid(self) -> bool {
+        self.0 <= crate::MAX_MONEY
+    }
+
+    // None if the sum overflows or exceeds MAX_MONEY
+    pub fn checked_add(self, other: Amount) -> Option<Amount> {
+        let sum = Amount(self.0.checked_add(other.0)?);
+        sum.is_valid().then_some(sum)
+    }
+
+    // None if `other` is larger
+    pub fn checked_sub(self, other: Amount) -> Option<Amount> {
+        self.0.checked_sub(other.0).map(Amount)
+    }
+
+    // None if any amount or partial sum exceeds MAX_MONEY
+    pub fn checked_sum(amounts: impl IntoIterator<Item = Amount>) -> Option<Amount> {
+        amounts.into_iter().try_fold(Amount::ZERO, |sum, amount| {
+            amount.is_valid().then_some(())?;
+            sum.checked_add(amount)
+        })
+    }
+}
+
+impl Add for Amount {
+    type Output = Amount;
+
+    fn add(self, other: Amount) -> Amount {
+        self.checked_add(other).expect("Amount overflow")
+    }
+}
+
+impl AddAssign for Amount {
+    fn add_assign(&mut self, other: Amount) {
+        *self = *self + other;
+    }
+}
+
+impl Sub for Amount {
+    type Output = Amount;
+
+    fn sub(self, other: Amount) -> Amount {
+        self.checked_sub(other).expect("Amount underflow")
+    }
+}
+
+impl Sum for Amount {
+    fn sum<I: Iterator<Item = Amount>>(iter: I) -> Amount {
+        iter.fold(Amount::ZERO, Add::add)
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}.{:08} BTC",
+            self.0 / crate::SATS_PER_BTC,
+            self.0 % crate::SATS_PER_BTC
+        )
+    }
+}
+
+// reward for mining the block at `height`, halving every
+// HALVING_INTERVAL blocks until nothing is left
+pub fn block_subsidy(height: u64) -> Amount {
+    let halvings = height / crate::HALVING_INTERVAL;
+    if halvings >= 64 {
+        return Amount::ZERO;
+    }
+    Amount(Amount::from_btc(crate::INITIAL_REWARD).0 >> halvings)
+}
+
+// amount of coins in existence once the block at `height` has been
+// mined, which is never more than MAX_MONEY
+pub fn total_supply(height: u64) -> Amount {
+    let blocks = height.saturating_add(1);
+    let mut supply = Amount::ZERO;
+    let mut era_start = 0;
+    while era_start < blocks {
+        let subsidy = block_subsidy(era_start);
+        if subsidy == Amount::ZERO {
+            break;
+        }
+        let era_blocks = (blocks - era_start).min(crate::HALVING_INTERVAL);
+        supply += Amount(subsidy.0 * era_blocks);
+        era_start += crate::HALVING_INTERVAL;
+    }
+    supply
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checked_operations_stop_at_the_edges() {
+        let one = Amount::from_sat(1);
+        assert_eq!(
+            Amount::from_sat(crate::MAX_MONEY - 1).checked_add(one),
+            Some(Amount::MAX_MONEY)
+        );
+        assert_eq!(Amount::MAX_MONEY.checked_add(one), None);
+        assert_eq!(Amount::from_sat(u64::MAX).checked_add(one), None);
+        assert_eq!(Amount::ZERO.checked_sub(one), None);
+        assert_eq!(one.checked_sub(one), Some(Amount::ZERO));
+        assert_eq!(
+            Amount::MAX_MONEY.checked_sub(Amount::MAX_MONEY),
+            Some(Amount::ZERO)
+        );
+
+        assert_eq!(
+            Amount::checked_sum([Amount::MAX_MONEY, Amount::ZERO]),
+            Some(Amount::MAX_MONEY)
+        );
+        assert_eq!(Amount::checked_sum([Amount::MAX_MONEY, one]), None);
+        // amounts beyond MAX_MONEY fail even if the sum would wrap
+        // around to something small
+        assert_eq!(Amount::checked_sum([Amount::from_sat(u64::MAX), one]), None);
+        assert_eq!(Amount::checked_sum([]), Some(Amount::ZERO));
+    }
+
+    #[test]
+    fn subsidy_halves_until_nothing_is_left() {
+        let initial = Amount::from_btc(crate::INITIAL_REWARD);
+        assert_eq!(block_subsidy(0), initial);
+        assert_eq!(block_subsidy(crate::HALVING_INTERVAL - 1), initial);
+        assert_eq!(
+            block_subsidy(crate::HALVING_INTERVAL),
+            Amount::from_sat(initial.to_sat() / 2)
+        );
+        assert_eq!(block_subsidy(33 * crate::HALVING_INTERVAL), Amount::ZERO);
+        // shifting by 64 or more would overflow
+        assert_eq!(block_subsidy(64 * crate::HALVING_INTERVAL), Amount::ZERO);
+        assert_eq!(block_subsidy(100 * crate::HALVING_INTERVAL), Amount::ZERO);
+        assert_eq!(block_subsidy(u64::MAX), Amount::ZERO);
+    }
+
+    #[test]
+    fn supply_never_exceeds_max_money() {
+        assert_eq!(total_supply(0), Amount::from_btc(crate::INITIAL_REWARD));
+        let mut previous = Amount::ZERO;
+        for halvings in 0..70 {
+            let supply = total_supply(halvings * crate::HALVING_INTERVAL);
+            assert!(supply >= previous);
+            assert!(supply <= Amount::MAX_MONEY);
+            previous = supply;
+        }
+        assert_eq!(total_supply(u64::MAX), previous);
+        assert!(total_supply(u64::MAX) <= Amount::MAX_MONEY);
+    }
+}
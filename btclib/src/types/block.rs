@@ -1,15 +1,30 @@
 use {
     super::{
+        amount::{
+            block_subsidy,
+            Amount,
+        },
         block_header::BlockHeader,
         transaction::{
             Transaction,
             TransactionOutput,
         },
+        utxo::Utxo,
     },
     crate::{
+        crypto::SignatureBatch,
         error::*,
         sha256::Hash,
-        util::Saveable,
+        util::{
+            serialized_size,
+            MerkleProof,
+            MerkleRoot,
+            Saveable,
+        },
+    },
+    chrono::{
+        DateTime,
+        Utc,
     },
     std::{
         collections::HashMap,
@@ -37,16 +52,60 @@ impl Block {
         }
     }
 
+    // the hash of the header, which commits to the transactions
+    // through the merkle root. Chaining blocks by it lets light
+    // clients follow the chain without the transactions
     pub fn hash(&self) -> Hash {
-        Hash::hash(self)
+        self.header.hash()
+    }
+
+    // bump the coinbase extra nonce and recalculate the merkle root,
+    // giving the miner a fresh range of header nonces
+    pub fn increment_extra_nonce(&mut self) {
+        if let Some(coinbase) = self
+            .transactions
+            .first_mut()
+            .and_then(|transaction| transaction.coinbase.as_mut())
+        {
+            coinbase.extra_nonce = coinbase.extra_nonce.wrapping_add(1);
+            self.header.merkle_root = MerkleRoot::calculate(&self.transactions);
+        }
+    }
+
+    // proof that the transaction with `hash` is in the block
+    pub fn merkle_proof(&self, hash: &Hash) -> Option<MerkleProof> {
+        let index = self
+            .transactions
+            .iter()
+            .position(|transaction| transaction.hash() == *hash)?;
+        MerkleProof::new(&self.transactions, index)
     }
 
+    // weight of the header and all transactions, the header counting
+    // as non-signature data
+    pub fn weight(&self) -> usize {
+        serialized_size(&self.header) * crate::WITNESS_SCALE_FACTOR
+            + self
+                .transactions
+                .iter()
+                .map(|transaction| transaction.weight())
+                .sum::<usize>()
+    }
+
+    // `median_time_past` is the one of the blocks before this one,
+    // which time locks are checked against
     pub fn verify_transactions(
         &self,
         predicted_block_height: u64,
-        utxos: &HashMap<Hash, (bool, TransactionOutput)>,
+        median_time_past: DateTime<Utc>,
+        utxos: &HashMap<Hash, Utxo>,
     ) -> Result<()> {
         let mut inputs: HashMap<Hash, TransactionOutput> = HashMap::new();
+        // outputs of earlier transactions in the block can be spent
+        // by later ones
+        let mut block_outputs: HashMap<Hash, TransactionOutput> = HashMap::new();
+        // Schnorr signatures of the whole block are verified at once
+        let mut signatures = SignatureBatch::new();
 
         // reject completely empty blocks
         if self.transactions.is_empty() {
@@ -56,79 +115,137 @@ impl Block {
         self.verify_coinbase_transaction(predicted_block_height, utxos)?;
 
         for transaction in self.transactions.iter().skip(1) {
-            let mut input_value = 0;
-            let mut output_value = 0;
-            for input in transaction.inputs.iter() {
-                let prev_output = utxos
-                    .get(&input.prev_tx_output_hash)
-                    .map(|(_, output)| output);
+            // only the first transaction may be a coinbase
+            if transaction.is_coinbase() {
+                return Err(BtcError::InvalidTransaction);
+            }
+            if !transaction.is_final(predicted_block_height, median_time_past) {
+                return Err(BtcError::InvalidTransaction);
+            }
+            // high s signatures would let anyone change the hash, and
+            // fail verification below as well
+            if !transaction.has_low_s_signatures() {
+                return Err(BtcError::InvalidSignature);
+            }
+            let mut input_value = Amount::ZERO;
+            let signature_hashes = transaction.signature_hashes();
+            for (input, signature_hash) in transaction.inputs.iter().zip(signature_hashes.iter()) {
+                // outputs created in this block count as confirmed
+                // at its height
+                let (prev_output, prev_height, prev_time) =
+                    match utxos.get(&input.prev_tx_output_hash) {
+                        // coinbase outputs can only be spent once mature
+                        Some(utxo) if !utxo.is_mature(predicted_block_height) => {
+                            return Err(BtcError::InvalidTransaction);
+                        }
+                        Some(utxo) => (Some(&utxo.output), utxo.height, utxo.median_time_past),
+                        None => {
+                            (
+                                block_outputs.get(&input.prev_tx_output_hash),
+                                predicted_block_height,
+                                median_time_past,
+                            )
+                        }
+                    };
                 if prev_output.is_none() {
                     return Err(BtcError::InvalidTransaction);
                 }
                 let prev_output = prev_output.unwrap();
+                // check the input's relative lock
+                if let Some(lock) = input.relative_lock() {
+                    if !lock.is_satisfied(
+                        prev_height,
+                        prev_time,
+                        predicted_block_height,
+                        median_time_past,
+                    ) {
+                        return Err(BtcError::InvalidTransaction);
+                    }
+                }
                 //prevent same-block double spending
                 if inputs.contains_key(&input.prev_tx_output_hash) {
                     return Err(BtcError::InvalidTransaction);
                 }
-                // check if the signature is valid
-                if !input
-                    .signature
-                    .verify(&input.prev_tx_output_hash, &prev_output.pubkey)
-                {
+                // check if the input satisfies the output's lock
+                if !prev_output.lock.is_unlocked_by_batched(
+                    transaction,
+                    input,
+                    signature_hash,
+                    &mut signatures,
+                ) {
                     return Err(BtcError::InvalidSignature);
                 }
-                input_value += prev_output.value;
+                // every amount has to stay within MAX_MONEY
+                input_value = input_value
+                    .checked_add(prev_output.value)
+                    .ok_or(BtcError::InvalidTransaction)?;
                 inputs.insert(input.prev_tx_output_hash, prev_output.clone());
             }
-            for output in transaction.outputs.iter() {
-                output_value += output.value;
-            }
+            let output_value =
+                Amount::checked_sum(transaction.outputs.iter().map(|output| output.value))
+                    .ok_or(BtcError::InvalidTransaction)?;
 
             // It is fine for output value to be less than input value
             // as the difference is the fee for the miner
             if input_value < output_value {
                 return Err(BtcError::InvalidTransaction);
             }
+
+            for output in transaction.outputs.iter() {
+                block_outputs.insert(output.hash(), output.clone());
+            }
         }
 
+        if !signatures.verify() {
+            return Err(BtcError::InvalidSignature);
+        }
         Ok(())
     }
 
     pub fn verify_coinbase_transaction(
         &self,
         predicted_block_height: u64,
-        utxos: &HashMap<Hash, (bool, TransactionOutput)>,
+        utxos: &HashMap<Hash, Utxo>,
     ) -> Result<()> {
         // coinbase transaction is the first transaction in the block
         let coinbase_transaction = &self.transactions[0];
-        if coinbase_transaction.inputs.is_empty() {
+        let Some(coinbase) = &coinbase_transaction.coinbase else {
+            return Err(BtcError::InvalidTransaction);
+        };
+        // the coinbase creates coins, it does not spend any
+        if !coinbase_transaction.inputs.is_empty() {
             return Err(BtcError::InvalidTransaction);
         }
         if coinbase_transaction.outputs.is_empty() {
             return Err(BtcError::InvalidTransaction);
         }
+        // committing to the height makes every coinbase unique
+        if coinbase.height != predicted_block_height {
+            return Err(BtcError::InvalidTransaction);
+        }
+        if coinbase.extra_data.len() > crate::MAX_COINBASE_EXTRA_DATA {
+            return Err(BtcError::InvalidTransaction);
+        }
 
         let miner_fees = self.calculate_miner_fees(utxos)?;
-        let block_reward = crate::INITIAL_REWARD * 10u64.pow(8)
-            / 2u64.pow((predicted_block_height / crate::HALVING_INTERVAL) as u32);
+        let block_reward = block_subsidy(predicted_block_height);
 
-        let total_coinbase_outputs = coinbase_transaction
-            .outputs
-            .iter()
-            .map(|output| output.value)
-            .sum::<u64>();
+        let total_coinbase_outputs = Amount::checked_sum(
+            coinbase_transaction
+                .outputs
+                .iter()
+                .map(|output| output.value),
+        )
+        .ok_or(BtcError::InvalidTransaction)?;
 
-        if total_coinbase_outputs != block_reward + miner_fees {
+        if Some(total_coinbase_outputs) != block_reward.checked_add(miner_fees) {
             return Err(BtcError::InvalidTransaction);
         }
 
         Ok(())
     }
 
-    pub fn calculate_miner_fees(
-        &self,
-        utxos: &HashMap<Hash, (bool, TransactionOutput)>,
-    ) -> Result<u64> {
+    pub fn calculate_miner_fees(&self, utxos: &HashMap<Hash, Utxo>) -> Result<Amount> {
         let mut inputs: HashMap<Hash, TransactionOutput> = HashMap::new();
         let mut outputs: HashMap<Hash, TransactionOutput> = HashMap::new();
 
@@ -136,10 +253,11 @@ impl Block {
         for transaction in self.transactions.iter().skip(1) {
             for input in transaction.inputs.iter() {
                 // input do not contain the values of the outputs so we need to match inputs to
-                // outputs
+                // outputs, which may have been created earlier in the block
                 let prev_output = utxos
                     .get(&input.prev_tx_output_hash)
-                    .map(|(_, output)| output);
+                    .map(|utxo| &utxo.output)
+                    .or_else(|| outputs.get(&input.prev_tx_output_hash));
                 if prev_output.is_none() {
                     return Err(BtcError::InvalidTransaction);
                 }
@@ -157,10 +275,14 @@ impl Block {
                 outputs.insert(output.hash(), output.clone());
             }
         }
-        let input_value = inputs.values().map(|output| output.value).sum::<u64>();
-        let output_value = outputs.values().map(|output| output.value).sum::<u64>();
+        let input_value = Amount::checked_sum(inputs.values().map(|output| output.value))
+            .ok_or(BtcError::InvalidTransaction)?;
+        let output_value = Amount::checked_sum(outputs.values().map(|output| output.value))
+            .ok_or(BtcError::InvalidTransaction)?;
 
-        Ok(input_value - output_value)
+        input_value
+            .checked_sub(output_value)
+            .ok_or(BtcError::InvalidTransaction)
     }
 }
 
@@ -175,3 +297,218 @@ impl Saveable for Block {
             .map_err(|_| IoError::new(IoErrorKind::InvalidData, "Failed to serialize Block"))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            crypto::{
+                PrivateKey,
+                Signature,
+            },
+            types::transaction::TransactionInput,
+        },
+        uuid::Uuid,
+    };
+
+    fn output(value: u64, private_key: &PrivateKey) -> TransactionOutput {
+        TransactionOutput {
+            value: Amount::from_sat(value),
+            unique_id: Uuid::new_v4(),
+            lock: private_key.public_key().into(),
+        }
+    }
+
+    // a transaction spending `spent` with a fee of 1000 satoshis
+    fn spend(
+        spent: &TransactionOutput,
+        sequence: u32,
+        lock_time: u64,
+        private_key: &PrivateKey,
+    ) -> Transaction {
+        let mut transaction = Transaction::new(
+            vec![TransactionInput {
+                prev_tx_output_hash: spent.hash(),
+                signature: Signature::sign(&spent.hash(), private_key),
+                sequence,
+                pubkey: None,
+                unlock: Default::default(),
+                extra_signatures: vec![],
+            }],
+            vec![output(spent.value.to_sat() - 1000, private_key)],
+        );
+        transaction.lock_time = lock_time;
+        let signature_hash = transaction.signature_hashes()[0];
+        transaction.inputs[0].signature = Signature::sign(&signature_hash, private_key);
+        transaction
+    }
+
+    // a block at `height` holding `transactions` after a coinbase
+    // paying the subsidy and their fees
+    fn block(height: u64, transactions: Vec<Transaction>) -> Block {
+        let fees = Amount::from_sat(1000 * transactions.len() as u64);
+        let reward = block_subsidy(height).checked_add(fees).unwrap();
+        let coinbase = Transaction::new_coinbase(
+            height,
+            vec![],
+            vec![output(reward.to_sat(), &PrivateKey::new_key())],
+        );
+        let mut transactions = transactions;
+        transactions.insert(0, coinbase);
+        let header = BlockHeader::new(
+            Utc::now(),
+            0,
+            Hash::zero(),
+            MerkleRoot::calculate(&transactions),
+            crate::MIN_TARGET,
+        );
+        Block::new(header, transactions)
+    }
+
+    fn confirmed(
+        output: &TransactionOutput,
+        height: u64,
+        time: DateTime<Utc>,
+    ) -> HashMap<Hash, Utxo> {
+        HashMap::from([(
+            output.hash(),
+            Utxo::new(output.clone(), height, time, false),
+        )])
+    }
+
+    #[test]
+    fn transactions_have_to_be_final() {
+        let private_key = PrivateKey::new_key();
+        let spent = output(1_000_000, &private_key);
+        let time = Utc::now();
+        let utxos = confirmed(&spent, 0, time);
+        let verify = |transaction: &Transaction, height: u64, time: DateTime<Utc>| {
+            block(height, vec![transaction.clone()]).verify_transactions(height, time, &utxos)
+        };
+
+        let locked = spend(&spent, 0, 10, &private_key);
+        assert!(verify(&locked, 10, time).is_err());
+        verify(&locked, 11, time).unwrap();
+        let final_input = spend(&spent, crate::SEQUENCE_FINAL, 10, &private_key);
+        verify(&final_input, 10, time).unwrap();
+
+        let locked = spend(&spent, 0, time.timestamp() as u64, &private_key);
+        assert!(verify(&locked, 1, time).is_err());
+        verify(&locked, 1, time + chrono::Duration::seconds(1)).unwrap();
+    }
+
+    #[test]
+    fn relative_locks_count_from_the_confirmation() {
+        let private_key = PrivateKey::new_key();
+        let spent = output(1_000_000, &private_key);
+        let time = Utc::now();
+        let utxos = confirmed(&spent, 5, time);
+        let verify = |transactions: Vec<Transaction>, height: u64, time: DateTime<Utc>| {
+            block(height, transactions).verify_transactions(height, time, &utxos)
+        };
+
+        let locked = spend(&spent, 3, 0, &private_key);
+        assert!(verify(vec![locked.clone()], 7, time).is_err());
+        verify(vec![locked], 8, time).unwrap();
+
+        let sequence = crate::SEQUENCE_LOCKTIME_TYPE_FLAG | 1;
+        let seconds = 1 << crate::SEQUENCE_LOCKTIME_GRANULARITY;
+        let after = |seconds: i64| time + chrono::Duration::seconds(seconds);
+        let locked = spend(&spent, sequence, 0, &private_key);
+        assert!(verify(vec![locked.clone()], 6, after(seconds - 1)).is_err());
+        verify(vec![locked], 6, after(seconds)).unwrap();
+
+        // outputs of earlier transactions in the block are
+        // confirmed at its height
+        let parent = spend(&spent, 0, 0, &private_key);
+        let child = spend(&parent.outputs[0], 0, 0, &private_key);
+        verify(vec![parent.clone(), child], 6, time).unwrap();
+        let locked = spend(&parent.outputs[0], 1, 0, &private_key);
+        assert!(verify(vec![parent, locked], 6, time).is_err());
+    }
+
+    #[test]
+    fn coinbase_outputs_are_spent_after_100_blocks() {
+        let private_key = PrivateKey::new_key();
+        let spent = output(1_000_000, &private_key);
+        let time = Utc::now();
+        let utxos = HashMap::from([(spent.hash(), Utxo::new(spent.clone(), 5, time, true))]);
+        let transaction = spend(&spent, crate::SEQUENCE_FINAL, 0, &private_key);
+        let verify = |height: u64| {
+            block(height, vec![transaction.clone()]).verify_transactions(height, time, &utxos)
+        };
+        assert!(verify(5 + crate::COINBASE_MATURITY - 1).is_err());
+        verify(5 + crate::COINBASE_MATURITY).unwrap();
+    }
+
+    #[test]
+    fn coinbases_commit_to_the_height() {
+        let utxos = HashMap::new();
+        block(7, vec![])
+            .verify_coinbase_transaction(7, &utxos)
+            .unwrap();
+        assert!(block(7, vec![])
+            .verify_coinbase_transaction(8, &utxos)
+            .is_err());
+
+        let mut block = block(7, vec![]);
+        let coinbase = block.transactions[0].coinbase.as_mut().unwrap();
+        coinbase.extra_data = vec![0; crate::MAX_COINBASE_EXTRA_DATA];
+        block.verify_coinbase_transaction(7, &utxos).unwrap();
+        let coinbase = block.transactions[0].coinbase.as_mut().unwrap();
+        coinbase.extra_data.push(0);
+        assert!(block.verify_coinbase_transaction(7, &utxos).is_err());
+    }
+
+    #[test]
+    fn extra_nonces_wrap_around() {
+        let mut block = block(7, vec![]);
+        block.transactions[0].coinbase.as_mut().unwrap().extra_nonce = u64::MAX - 1;
+        let merkle_root = block.header.merkle_root.clone();
+        block.increment_extra_nonce();
+        let coinbase = block.transactions[0].coinbase.as_ref().unwrap();
+        assert_eq!(coinbase.extra_nonce, u64::MAX);
+        assert_ne!(block.header.merkle_root, merkle_root);
+        assert_eq!(
+            block.header.merkle_root,
+            MerkleRoot::calculate(&block.transactions)
+        );
+        block.increment_extra_nonce();
+        let coinbase = block.transactions[0].coinbase.as_ref().unwrap();
+        assert_eq!(coinbase.extra_nonce, 0);
+        assert_eq!(
+            block.header.merkle_root,
+            MerkleRoot::calculate(&block.transactions)
+        );
+    }
+
+    // `signature` with the high s of the same signature
+    fn high_s(signature: &Signature) -> Signature {
+        let Signature::Ecdsa(signature) = signature else {
+            unreachable!();
+        };
+        let (r, s) = signature.split_scalars();
+        Signature::Ecdsa(ecdsa::Signature::from_scalars(r, -*s).unwrap())
+    }
+
+    #[test]
+    fn high_s_signatures_are_rejected() {
+        let private_key = PrivateKey::new_key();
+        let spent = output(1_000_000, &private_key);
+        let time = Utc::now();
+        let utxos = confirmed(&spent, 0, time);
+        let mut transaction = spend(&spent, crate::SEQUENCE_FINAL, 0, &private_key);
+        transaction.inputs[0].signature = high_s(&transaction.inputs[0].signature);
+        assert!(!transaction.has_low_s_signatures());
+        assert!(matches!(
+            block(1, vec![transaction.clone()]).verify_transactions(1, time, &utxos),
+            Err(BtcError::InvalidSignature)
+        ));
+        transaction.normalize_signatures();
+        assert!(transaction.has_low_s_signatures());
+        block(1, vec![transaction])
+            .verify_transactions(1, time, &utxos)
+            .unwrap();
+    }
+}
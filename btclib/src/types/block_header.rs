@@ -20,8 +20,8 @@ pub struct BlockHeader {
     pub prev_block_hash: Hash,
     /// Merkle root of the block's transactions
     pub merkle_root: MerkleRoot,
-    /// target
-    pub target: U256,
+    /// Target in compact form (see `U256::to_compact`)
+    pub bits: u32,
 }
 
 impl BlockHeader {
@@ -37,7 +37,7 @@ impl BlockHeader {
             nonce,
             prev_block_hash,
             merkle_root,
-            target,
+            bits: target.to_compact(),
         }
     }
 
@@ -45,9 +45,21 @@ impl BlockHeader {
         Hash::hash(self)
     }
 
+    // the target decoded from the compact bits, zero if they are
+    // invalid so that no hash can match it
+    pub fn target(&self) -> U256 {
+        U256::from_compact(self.bits).unwrap_or_default()
+    }
+
+    // amount of work needed to mine the block
+    pub fn work(&self) -> U256 {
+        self.target().work()
+    }
+
     pub fn mine(&mut self, steps: usize) -> bool {
+        let target = self.target();
         // if the block already matches target, return early
-        if self.hash().matches_target(self.target) {
+        if self.hash().matches_target(target) {
             return true;
         }
         for _ in 0..steps {
@@ -57,7 +69,7 @@ impl BlockHeader {
                 self.nonce = 0;
                 self.timestamp = Utc::now();
             }
-            if self.hash().matches_target(self.target) {
+            if self.hash().matches_target(target) {
                 return true;
             }
         }
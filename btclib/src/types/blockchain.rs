@@ -1,16 +1,36 @@
 use {
     super::{
+        amount::{
+            block_subsidy,
+            total_supply,
+            Amount,
+        },
         block::Block,
+        mempool::{
+            Mempool,
+            MempoolEntry,
+        },
         transaction::{
             Transaction,
             TransactionOutput,
         },
+        utxo::{
+            Balance,
+            Utxo,
+        },
     },
     crate::{
+        address::PubkeyHash,
+        clock::{
+            Clock,
+            SystemClock,
+        },
+        difficulty::DifficultyAdjustment,
         error::{
             BtcError,
             Result,
         },
+        filter::BlockFilter,
         sha256::Hash,
         util::{
             MerkleRoot,
@@ -18,16 +38,12 @@ use {
         },
         U256,
     },
-    bigdecimal::BigDecimal,
     chrono::{
         DateTime,
         Utc,
     },
     std::{
-        collections::{
-            HashMap,
-            HashSet,
-        },
+        collections::HashMap,
         io::{
             Error as IoError,
             ErrorKind as IoErrorKind,
@@ -35,16 +51,43 @@ use {
             Result as IoResult,
             Write,
         },
+        sync::Arc,
     },
 };
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Blockchain {
-    pub utxos: HashMap<Hash, (bool, TransactionOutput)>,
+    pub utxos: HashMap<Hash, Utxo>,
     pub target: U256,
     pub blocks: Vec<Block>,
-    #[serde(default, skip_serializing)]
-    pub mempool: Vec<(DateTime<Utc>, Transaction)>,
+    /// Compact filter of every block, for light clients
+    #[serde(default)]
+    pub filters: Vec<BlockFilter>,
+    /// Header of every block's filter, chaining it to the filters
+    /// of the blocks before
+    #[serde(default)]
+    pub filter_headers: Vec<Hash>,
+    #[serde(skip)]
+    pub mempool: Mempool,
+    /// Total work of the blocks up to and including each one,
+    /// recomputed when loading
+    #[serde(skip)]
+    chainwork: Vec<U256>,
+    /// Height of the block of every transaction, recomputed when
+    /// loading
+    #[serde(skip)]
+    transaction_heights: HashMap<Hash, u64>,
+    /// How the target changes over time, a consensus rule that is
+    /// not stored with the blocks
+    #[serde(skip)]
+    difficulty: DifficultyAdjustment,
+    /// What block timestamps are checked against
+    #[serde(skip, default = "system_clock")]
+    clock: Arc<dyn Clock>,
+}
+
+fn system_clock() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
 }
 
 impl Blockchain {
@@ -52,7 +95,7 @@ impl Blockchain {
         self.blocks.len() as u64
     }
 
-    pub fn utxos(&self) -> &HashMap<Hash, (bool, TransactionOutput)> {
+    pub fn utxos(&self) -> &HashMap<Hash, Utxo> {
         &self.utxos
     }
 
@@ -64,9 +107,58 @@ impl Blockchain {
         self.blocks.iter()
     }
 
-    pub fn mempool(&self) -> &[(DateTime<Utc>, Transaction)] {
+    pub fn mempool(&self) -> &Mempool {
         &self.mempool
     }
+
+    pub fn filters(&self) -> impl Iterator<Item = &BlockFilter> {
+        self.filters.iter()
+    }
+
+    pub fn filter_headers(&self) -> impl Iterator<Item = &Hash> {
+        self.filter_headers.iter()
+    }
+
+    // total work of the blocks below `height`, which tells apart
+    // competing chains better than their length
+    pub fn chainwork_at(&self, height: u64) -> U256 {
+        let blocks = (height as usize).min(self.chainwork.len());
+        match blocks.checked_sub(1) {
+            Some(last) => self.chainwork[last],
+            None => U256::zero(),
+        }
+    }
+
+    // total work of the whole chain
+    pub fn chainwork(&self) -> U256 {
+        self.chainwork_at(self.block_height())
+    }
+
+    // height of the block holding the transaction with `hash`
+    pub fn transaction_height(&self, hash: &Hash) -> Option<u64> {
+        self.transaction_heights.get(hash).copied()
+    }
+
+    // median timestamp of the MEDIAN_TIME_SPAN blocks before the
+    // one at `height`, which time locks are checked against
+    pub fn median_time_past_at(&self, height: u64) -> DateTime<Utc> {
+        let end = (height as usize).min(self.blocks.len());
+        let start = end.saturating_sub(crate::MEDIAN_TIME_SPAN);
+        let mut timestamps = self.blocks[start..end]
+            .iter()
+            .map(|block| block.header.timestamp)
+            .collect::<Vec<_>>();
+        timestamps.sort();
+        timestamps
+            .get(timestamps.len() / 2)
+            .copied()
+            .unwrap_or(DateTime::<Utc>::MIN_UTC)
+    }
+
+    // median time past for the next block
+    pub fn median_time_past(&self) -> DateTime<Utc> {
+        self.median_time_past_at(self.block_height())
+    }
 }
 
 impl Blockchain {
@@ -75,15 +167,51 @@ impl Blockchain {
             utxos: HashMap::new(),
             target: crate::MIN_TARGET,
             blocks: Vec::new(),
-            mempool: Vec::new(),
+            filters: Vec::new(),
+            filter_headers: Vec::new(),
+            mempool: Mempool::default(),
+            chainwork: Vec::new(),
+            transaction_heights: HashMap::new(),
+            difficulty: DifficultyAdjustment::default(),
+            clock: system_clock(),
         }
     }
+
+    pub fn difficulty(&self) -> DifficultyAdjustment {
+        self.difficulty
+    }
+
+    // switch to another difficulty adjustment algorithm, and the
+    // target it expects next
+    pub fn set_difficulty(&mut self, difficulty: DifficultyAdjustment) {
+        self.difficulty = difficulty;
+        self.try_adjust_target();
+    }
+
+    // replace the clock block timestamps are checked against
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
     pub fn add_block(&mut self, block: Block) -> Result<()> {
+        // check if the block's timestamp is not too far in the future
+        let max_timestamp =
+            self.clock.now() + chrono::Duration::seconds(crate::MAX_FUTURE_BLOCK_TIME as i64);
+        if block.header.timestamp > max_timestamp {
+            println!("block timestamp is too far in the future");
+            return Err(BtcError::InvalidBlock);
+        }
         if self.blocks.is_empty() {
             if block.header.prev_block_hash != Hash::zero() {
                 println!("zero hash");
                 return Err(BtcError::InvalidBlock);
             }
+            // the genesis block is not mined, but has to carry the
+            // initial target
+            if block.header.bits != crate::MIN_TARGET.to_compact() {
+                println!("unexpected target");
+                return Err(BtcError::InvalidBlock);
+            }
         } else {
             // if this is not the first block,
             // check if the previous block hash is the hash of the last block
@@ -92,37 +220,47 @@ impl Blockchain {
                 println!("prev hash is wrong");
                 return Err(BtcError::InvalidBlock);
             }
+            // check if the block was mined at the expected difficulty
+            if block.header.bits != self.next_target().to_compact() {
+                println!("unexpected target");
+                return Err(BtcError::InvalidBlock);
+            }
             // check if the block's hash is lesss than the target
-            if !block.header.hash().matches_target(block.header.target) {
+            if !block.header.hash().matches_target(block.header.target()) {
                 println!("does not match target");
                 return Err(BtcError::InvalidBlock);
             }
-            // check if the block's merkle root is correct
-            let calculated_merkle_root = MerkleRoot::calculate(&block.transactions);
-            if block.header.merkle_root != calculated_merkle_root {
+            // check if the block fits within the weight limit
+            if block.weight() > crate::MAX_BLOCK_WEIGHT {
+                println!("block is too heavy");
+                return Err(BtcError::InvalidBlock);
+            }
+            // check if the block's merkle root is correct, and that no
+            // other transaction list shares it
+            let (calculated_merkle_root, mutated) =
+                MerkleRoot::calculate_checked(&block.transactions);
+            if block.header.merkle_root != calculated_merkle_root || mutated {
                 println!("invalid merkle root");
                 return Err(BtcError::InvalidMerkleRoot);
             }
 
-            // check if the block's timestamp is after the last block's timestamp
-            if block.header.timestamp <= last_block.header.timestamp {
-                println!("invalid block timestamp");
+            // check if the block's timestamp is after the median of the
+            // last blocks' timestamps
+            if block.header.timestamp <= self.median_time_past() {
+                println!("block timestamp is too old");
                 return Err(BtcError::InvalidBlock);
             }
 
             // Verify all transactions in the block
-            block.verify_transactions(self.block_height(), &self.utxos)?;
+            block.verify_transactions(self.block_height(), self.median_time_past(), &self.utxos)?;
         }
-        // Remove transactions from mempool that are now in the block
-        let block_transactions = block
-            .transactions
-            .iter()
-            .map(|tx| tx.hash())
-            .collect::<HashSet<_>>();
-
-        self.mempool
-            .retain(|(_, tx)| !block_transactions.contains(&tx.hash()));
+        // Remove transactions from mempool that are now in the block,
+        // and the ones double spending them
+        let conflicts = self.mempool.remove_confirmed(&block.transactions);
+        self.unmark_inputs(&conflicts);
 
+        self.push_filter(&block);
+        self.index_block(&block);
         self.blocks.push(block);
 
         self.try_adjust_target();
@@ -131,211 +269,204 @@ impl Blockchain {
 
     // try to adjust the target of the blockchain
     pub fn try_adjust_target(&mut self) {
-        if self.blocks.is_empty() {
-            return;
-        }
-        if self.block_height() % crate::DIFFICULTY_UPDATE_INTERVAL != 0 {
-            return;
+        self.target = self.next_target();
+    }
+
+    // the target the next block has to be mined at, derived from
+    // the chain's history by its difficulty adjustment algorithm
+    pub fn next_target(&self) -> U256 {
+        let headers = self
+            .blocks
+            .iter()
+            .map(|block| &block.header)
+            .collect::<Vec<_>>();
+        self.difficulty.algorithm().next_target(&headers)
+    }
+
+    // add the next block to the chainwork and transaction index
+    fn index_block(&mut self, block: &Block) {
+        let chainwork = self.chainwork().saturating_add(block.header.work());
+        self.chainwork.push(chainwork);
+        let height = self.chainwork.len() as u64 - 1;
+        for transaction in block.transactions.iter() {
+            self.transaction_heights.insert(transaction.hash(), height);
         }
+    }
 
-        //measure the time it took to mine the last crate::DIFFICULTY_UPDATE_INTERVAL
-        // blocks with chrono
-        let start_time = self.blocks
-            [self.blocks.len() - crate::DIFFICULTY_UPDATE_INTERVAL as usize]
-            .header
-            .timestamp;
-        let end_time = self.blocks.last().unwrap().header.timestamp;
-        let time_diff = end_time - start_time;
-
-        //convert time_diff to seconds
-        let time_diff_seconds = time_diff.num_seconds();
-        // calcualte the ideal number of seconds
-        let target_seconds = crate::IDEAL_BLOCK_TIME * crate::DIFFICULTY_UPDATE_INTERVAL;
-        // multiply the current target by actual time didvided by ideal time
-        let new_target = BigDecimal::parse_bytes(&self.target.to_string().as_bytes(), 10)
-            .expect("Bug: impossible")
-            * (BigDecimal::from(time_diff_seconds) / BigDecimal::from(target_seconds));
-
-        let new_target_str = new_target
-            .to_string()
-            .split('.')
-            .next()
-            .expect("Bug: Expected a decimal point")
-            .to_owned();
-        let new_target = U256::from_str_radix(&new_target_str, 10).expect("Bug: Impossible");
-
-        // clamp new_target to be within the range of 4 * self.target and self.target /
-        // 4
-        let new_target = if new_target < self.target / 4 {
-            self.target / 4
-        } else if new_target > self.target * 4 {
-            self.target * 4
-        } else {
-            new_target
-        };
+    fn push_filter(&mut self, block: &Block) {
+        let filter = BlockFilter::new(block);
+        let prev_header = self.filter_headers.last().copied().unwrap_or(Hash::zero());
+        self.filter_headers.push(filter.header(&prev_header));
+        self.filters.push(filter);
+    }
 
-        // if the new target is more than the minimum target, set it to the minimum
-        // target
-        self.target = new_target.min(crate::MIN_TARGET);
+    // recompute the block filters, e.g. for a blockchain saved
+    // without them or with filters of an older encoding
+    pub fn rebuild_filters(&mut self) {
+        self.filters = self.blocks.iter().map(BlockFilter::new).collect();
+        let mut prev_header = Hash::zero();
+        self.filter_headers = self
+            .filters
+            .iter()
+            .map(|filter| {
+                prev_header = filter.header(&prev_header);
+                prev_header
+            })
+            .collect();
     }
 
     pub fn rebuild_utxos(&mut self) {
-        for block in self.blocks.iter() {
+        self.utxos.clear();
+        for (height, block) in self.blocks.iter().enumerate() {
+            let median_time_past = self.median_time_past_at(height as u64);
             for transaction in block.transactions.iter() {
                 for input in transaction.inputs.iter() {
                     self.utxos.remove(&input.prev_tx_output_hash);
                 }
                 for output in transaction.outputs.iter() {
-                    self.utxos
-                        .insert(transaction.hash(), (false, output.clone()));
+                    self.utxos.insert(
+                        output.hash(),
+                        Utxo::new(
+                            output.clone(),
+                            height as u64,
+                            median_time_past,
+                            transaction.is_coinbase(),
+                        ),
+                    );
                 }
             }
         }
-    }
-
-    pub fn add_to_mempool(&mut self, transaction: Transaction) -> Result<()> {
-        // validate transaction before insertion
-        // all input must match known UTXOs, and must be unique
-        let mut known_inputs = HashSet::new();
-        for input in transaction.inputs.iter() {
-            if !self.utxos.contains_key(&input.prev_tx_output_hash) {
-                return Err(BtcError::InvalidTransaction);
-            }
-            if known_inputs.contains(&input.prev_tx_output_hash) {
-                return Err(BtcError::InvalidTransaction);
-            }
-            known_inputs.insert(input.prev_tx_output_hash);
-        }
-
-        // check if any of the utxos have the bool mark set to true and if so, find the
-        // transaction that references them in mempool, remove it, and set all the utxos
-        // it references to false
-        for input in transaction.inputs.iter() {
-            if let Some((true, _)) = self.utxos.get(&input.prev_tx_output_hash) {
-                // find the references the UTXO
-                // we are trying to reference
-                let referencing_tx = self.mempool.iter().enumerate().find(|(_, (_, tx))| {
-                    tx.outputs
-                        .iter()
-                        .any(|output| output.hash() == input.prev_tx_output_hash)
-                });
-
-                // If we have found one, unmark all of its UTXOs
-                if let Some((idx, (_, referencing_tx))) = referencing_tx {
-                    for input in referencing_tx.inputs.iter() {
-                        // set all utxos from this tx to false
-                        self.utxos
-                            .entry(input.prev_tx_output_hash)
-                            .and_modify(|(marked, _)| {
-                                *marked = false;
-                            });
-                    }
-                    self.mempool.remove(idx);
-                } else {
-                    // if, somehow, there is no matching transaction, set this utxo to false
-                    self.utxos
-                        .entry(input.prev_tx_output_hash)
-                        .and_modify(|(marked, _)| {
-                            *marked = false;
-                        });
-                }
+        // keep the outputs spent by the mempool marked
+        for hash in self.mempool.spent_outputs() {
+            if let Some(utxo) = self.utxos.get_mut(hash) {
+                utxo.marked = true;
             }
         }
+    }
 
-        // all inputs must be lower than all outputs
-        let all_inputs = transaction
+    // add a transaction to the mempool, returning the hashes of
+    // the mempool transactions it replaced
+    pub fn add_to_mempool(&mut self, transaction: Transaction) -> Result<Vec<Hash>> {
+        let inputs = transaction
             .inputs
             .iter()
-            .map(|input| {
-                self.utxos()
-                    .get(&input.prev_tx_output_hash)
-                    .expect("Bug: impossible")
-                    .1
-                    .value
-            })
-            .sum::<u64>();
-        let all_outputs = transaction
-            .outputs
+            .map(|input| input.prev_tx_output_hash)
+            .collect::<Vec<_>>();
+        let displaced = self.mempool.insert(
+            transaction,
+            &self.utxos,
+            self.block_height(),
+            self.median_time_past(),
+        )?;
+        // unmark the UTXOs of replaced and evicted transactions
+        // before marking the ones used by the new transaction
+        self.unmark_inputs(&displaced.replaced);
+        self.unmark_inputs(&displaced.evicted);
+        for hash in inputs.iter() {
+            self.utxos.entry(*hash).and_modify(|utxo| {
+                utxo.marked = true;
+            });
+        }
+
+        Ok(displaced
+            .replaced
             .iter()
-            .map(|output| output.value)
-            .sum::<u64>();
+            .map(|entry| entry.transaction.hash())
+            .collect())
+    }
 
-        if all_inputs < all_outputs {
-            println!("inputs are lower than outputs");
-            return Err(BtcError::InvalidTransaction);
-        }
+    pub fn clean_up_mempool(&mut self) {
+        let cutoff =
+            Utc::now() - chrono::Duration::seconds(crate::MAX_MEMPOOL_TRANSACTION_AGE as i64);
+        let expired = self.mempool.expire(cutoff);
+        self.unmark_inputs(&expired);
+    }
 
-        // Mark the UTXOs as used
-        for input in transaction.inputs.iter() {
+    fn unmark_inputs(&mut self, entries: &[MempoolEntry]) {
+        for input in entries
+            .iter()
+            .flat_map(|entry| entry.transaction.inputs.iter())
+        {
             self.utxos
                 .entry(input.prev_tx_output_hash)
-                .and_modify(|(marked, _)| {
-                    *marked = true;
+                .and_modify(|utxo| {
+                    utxo.marked = false;
                 });
         }
-
-        // push the tx to the mempool
-        self.mempool.push((Utc::now(), transaction));
-        // sort by miner fee
-        self.mempool.sort_by_key(|(_, tx)| {
-            let all_inputs = tx
-                .inputs
-                .iter()
-                .map(|input| {
-                    self.utxos
-                        .get(&input.prev_tx_output_hash)
-                        .expect("Bug: Impossible")
-                        .1
-                        .value
-                })
-                .sum::<u64>();
-            let all_outputs = tx.outputs.iter().map(|output| output.value).sum::<u64>();
-            let miner_fee = all_inputs - all_outputs;
-            miner_fee
-        });
-
-        Ok(())
     }
 
-    pub fn clean_up_mempool(&mut self) {
-        let now = Utc::now();
-        let mut utxo_hashes_to_unmark: Vec<Hash> = Vec::new();
-        self.mempool.retain(|(timestamp, transaction)| {
-            if now - *timestamp
-                > chrono::Duration::seconds(crate::MAX_MEMPOOL_TRANSACTION_AGE as i64)
-            {
-                // push all utxos to unmark to the vector so we can unmark them later
-                utxo_hashes_to_unmark.extend(
-                    transaction
-                        .inputs
-                        .iter()
-                        .map(|input| input.prev_tx_output_hash),
-                );
-                false
+    // balance of the key with `pubkey_hash`, whichever way outputs
+    // are locked to it. Coinbase outputs that cannot be spent in the
+    // next block count as immature
+    pub fn balance(&self, pubkey_hash: &PubkeyHash) -> Balance {
+        let mut balance = Balance::default();
+        for utxo in self.utxos.values() {
+            if utxo.output.lock.pubkey_hash() != Some(*pubkey_hash) {
+                continue;
+            }
+            if utxo.is_mature(self.block_height()) {
+                balance.spendable += utxo.output.value;
             } else {
-                true
+                balance.immature += utxo.output.value;
             }
-        });
-
-        // unmark all of the UTXOs
-        for hash in utxo_hashes_to_unmark.into_iter() {
-            self.utxos.entry(hash).and_modify(|(marked, _)| {
-                *marked = false;
-            });
         }
+        balance.unconfirmed = self
+            .mempool
+            .unspent_outputs()
+            .filter(|output| output.lock.pubkey_hash() == Some(*pubkey_hash))
+            .map(|output| output.value)
+            .sum();
+        balance
+    }
+
+    // outputs the key with `pubkey_hash` can spend, whichever way
+    // they are locked to it, and whether a mempool transaction
+    // spends them already. Unconfirmed outputs can be spent too,
+    // e.g. change, immature coinbase outputs cannot
+    pub fn spendable_outputs(&self, pubkey_hash: &PubkeyHash) -> Vec<(TransactionOutput, bool)> {
+        let mut outputs = self
+            .utxos
+            .values()
+            .filter(|utxo| {
+                utxo.output.lock.pubkey_hash() == Some(*pubkey_hash)
+                    && utxo.is_mature(self.block_height())
+            })
+            .map(|utxo| (utxo.output.clone(), utxo.marked))
+            .collect::<Vec<_>>();
+        outputs.extend(
+            self.mempool
+                .unspent_outputs()
+                .filter(|output| output.lock.pubkey_hash() == Some(*pubkey_hash))
+                .map(|output| (output.clone(), false)),
+        );
+        outputs
     }
 
-    pub fn calculate_block_reward(&self) -> u64 {
-        let block_height = self.block_height();
-        let halvings = block_height / crate::HALVING_INTERVAL;
-        (crate::INITIAL_REWARD * 10u64.pow(8)) >> halvings
+    // subsidy of the next block
+    pub fn calculate_block_reward(&self) -> Amount {
+        block_subsidy(self.block_height())
+    }
+
+    // coins created by the subsidies of the blocks so far
+    pub fn total_supply(&self) -> Amount {
+        match self.block_height() {
+            0 => Amount::ZERO,
+            height => total_supply(height - 1),
+        }
     }
 }
 
 impl Saveable for Blockchain {
     fn load<I: Read>(reader: I) -> IoResult<Self> {
-        ciborium::de::from_reader(reader)
-            .map_err(|_| IoError::new(IoErrorKind::InvalidData, "Failed to deserialize Blockchain"))
+        let mut blockchain: Blockchain = ciborium::de::from_reader(reader).map_err(|_| {
+            IoError::new(IoErrorKind::InvalidData, "Failed to deserialize Blockchain")
+        })?;
+        let blocks = std::mem::take(&mut blockchain.blocks);
+        for block in blocks.iter() {
+            blockchain.index_block(block);
+        }
+        blockchain.blocks = blocks;
+        Ok(blockchain)
     }
 
     fn save<O: Write>(&self, writer: O) -> IoResult<()> {
@@ -343,3 +474,234 @@ impl Saveable for Blockchain {
             .map_err(|_| IoError::new(IoErrorKind::InvalidData, "Failed to serialize Blockchain"))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            clock::FixedClock,
+            crypto::{
+                PrivateKey,
+                Signature,
+            },
+            types::{
+                block_header::BlockHeader,
+                transaction::{
+                    Lock,
+                    TransactionInput,
+                },
+            },
+        },
+        uuid::Uuid,
+    };
+
+    // a blockchain holding a genesis block mined a minute ago
+    fn with_genesis() -> Blockchain {
+        let mut blockchain = Blockchain::new();
+        let coinbase = Transaction::new_coinbase(0, vec![], vec![]);
+        let header = BlockHeader::new(
+            Utc::now() - chrono::Duration::minutes(1),
+            0,
+            Hash::zero(),
+            MerkleRoot::calculate(std::slice::from_ref(&coinbase)),
+            crate::MIN_TARGET,
+        );
+        blockchain
+            .add_block(Block::new(header, vec![coinbase]))
+            .unwrap();
+        blockchain
+    }
+
+    // the next block, paying the block reward, mined at `target`
+    fn next_block(blockchain: &Blockchain, target: U256, timestamp: DateTime<Utc>) -> Block {
+        let coinbase = Transaction::new_coinbase(
+            blockchain.block_height(),
+            vec![],
+            vec![TransactionOutput {
+                value: blockchain.calculate_block_reward(),
+                unique_id: Uuid::new_v4(),
+                lock: PrivateKey::new_key().public_key().into(),
+            }],
+        );
+        let mut header = BlockHeader::new(
+            timestamp,
+            0,
+            blockchain.blocks.last().unwrap().hash(),
+            MerkleRoot::calculate(std::slice::from_ref(&coinbase)),
+            target,
+        );
+        assert!(header.mine(usize::MAX));
+        Block::new(header, vec![coinbase])
+    }
+
+    #[test]
+    fn blocks_have_to_use_the_expected_target() {
+        let blockchain = with_genesis();
+        let block = next_block(&blockchain, blockchain.next_target(), Utc::now());
+        blockchain.clone().add_block(block).unwrap();
+
+        // an easier target than the expected one, and a harder one
+        for target in [U256::MAX, blockchain.next_target() / 2] {
+            let block = next_block(&blockchain, target, Utc::now());
+            assert!(matches!(
+                blockchain.clone().add_block(block),
+                Err(BtcError::InvalidBlock)
+            ));
+        }
+    }
+
+    #[test]
+    fn timestamps_have_to_pass_the_median_time_past() {
+        let blockchain = with_genesis();
+        let median_time_past = blockchain.median_time_past();
+        assert_eq!(median_time_past, blockchain.blocks[0].header.timestamp);
+        let block = next_block(&blockchain, blockchain.next_target(), median_time_past);
+        assert!(matches!(
+            blockchain.clone().add_block(block),
+            Err(BtcError::InvalidBlock)
+        ));
+        let timestamp = median_time_past + chrono::Duration::seconds(1);
+        let block = next_block(&blockchain, blockchain.next_target(), timestamp);
+        blockchain.clone().add_block(block).unwrap();
+    }
+
+    #[test]
+    fn timestamps_may_not_run_too_far_ahead_of_the_clock() {
+        let mut blockchain = with_genesis();
+        let now = Utc::now() + chrono::Duration::days(1);
+        blockchain.set_clock(Arc::new(FixedClock(now)));
+        let max_timestamp = now + chrono::Duration::seconds(crate::MAX_FUTURE_BLOCK_TIME as i64);
+        let block = next_block(&blockchain, blockchain.next_target(), max_timestamp);
+        blockchain.clone().add_block(block).unwrap();
+        let timestamp = max_timestamp + chrono::Duration::seconds(1);
+        let block = next_block(&blockchain, blockchain.next_target(), timestamp);
+        assert!(matches!(
+            blockchain.clone().add_block(block),
+            Err(BtcError::InvalidBlock)
+        ));
+    }
+
+    #[test]
+    fn indexes_are_kept_across_saving() {
+        assert_eq!(Blockchain::new().chainwork(), U256::zero());
+        let blockchain = with_genesis();
+        assert_eq!(blockchain.chainwork(), blockchain.blocks[0].header.work());
+        assert_eq!(blockchain.chainwork_at(0), U256::zero());
+        assert_eq!(blockchain.chainwork_at(10), blockchain.chainwork());
+
+        let mut saved = vec![];
+        blockchain.save(&mut saved).unwrap();
+        let loaded = Blockchain::load(saved.as_slice()).unwrap();
+        assert_eq!(loaded.chainwork(), blockchain.chainwork());
+        let coinbase = blockchain.blocks[0].transactions[0].hash();
+        assert_eq!(blockchain.transaction_height(&coinbase), Some(0));
+        assert_eq!(loaded.transaction_height(&coinbase), Some(0));
+        assert_eq!(loaded.transaction_height(&Hash::zero()), None);
+    }
+
+    #[test]
+    fn spendable_outputs_are_found_by_pubkey_hash() {
+        let mut blockchain = with_genesis();
+        let private_key = PrivateKey::new_key();
+        let pubkey_hash = private_key.public_key().pubkey_hash();
+        let output = |value: u64, lock: Lock| {
+            TransactionOutput {
+                value: Amount::from_sat(value),
+                unique_id: Uuid::new_v4(),
+                lock,
+            }
+        };
+        let by_key = output(1_000_000, private_key.public_key().into());
+        let by_hash = output(2_000_000, Lock::PubkeyHash(pubkey_hash));
+        let immature = output(3_000_000, Lock::PubkeyHash(pubkey_hash));
+        let stranger = output(4_000_000, PrivateKey::new_key().public_key().into());
+        for (output, coinbase) in [
+            (&by_key, false),
+            (&by_hash, false),
+            (&immature, true),
+            (&stranger, false),
+        ] {
+            let utxo = Utxo::new(output.clone(), 0, Utc::now(), coinbase);
+            blockchain.utxos.insert(output.hash(), utxo);
+        }
+
+        // spending the output locked by hash pays change back to it
+        let change = output(1_990_000, Lock::PubkeyHash(pubkey_hash));
+        let mut transaction = Transaction::new(
+            vec![TransactionInput {
+                prev_tx_output_hash: by_hash.hash(),
+                signature: Signature::sign(&by_hash.hash(), &private_key),
+                sequence: crate::SEQUENCE_FINAL,
+                pubkey: Some(private_key.public_key()),
+                unlock: Default::default(),
+                extra_signatures: vec![],
+            }],
+            vec![change.clone()],
+        );
+        let signature_hash = transaction.signature_hashes()[0];
+        transaction.inputs[0].signature = Signature::sign(&signature_hash, &private_key);
+        blockchain.add_to_mempool(transaction).unwrap();
+
+        let mut outputs = blockchain
+            .spendable_outputs(&pubkey_hash)
+            .into_iter()
+            .map(|(output, marked)| (output.value, marked))
+            .collect::<Vec<_>>();
+        outputs.sort();
+        assert_eq!(
+            outputs,
+            [
+                (by_key.value, false),
+                (change.value, false),
+                (by_hash.value, true),
+            ]
+        );
+    }
+
+    #[test]
+    fn immature_coinbase_outputs_are_not_spendable_yet() {
+        let mut blockchain = with_genesis();
+        let private_key = PrivateKey::new_key();
+        let pubkey_hash = private_key.public_key().pubkey_hash();
+        let output = |value: u64| {
+            TransactionOutput {
+                value: Amount::from_sat(value),
+                unique_id: Uuid::new_v4(),
+                lock: private_key.public_key().into(),
+            }
+        };
+        // the next block is at height 1, where coinbase outputs of
+        // the genesis block are still immature
+        for (output, coinbase) in [(output(1000), false), (output(2000), true)] {
+            let utxo = Utxo::new(output.clone(), 0, Utc::now(), coinbase);
+            blockchain.utxos.insert(output.hash(), utxo);
+        }
+        let balance = blockchain.balance(&pubkey_hash);
+        assert_eq!(balance.spendable, Amount::from_sat(1000));
+        assert_eq!(balance.immature, Amount::from_sat(2000));
+        assert_eq!(balance.unconfirmed, Amount::ZERO);
+        assert_eq!(blockchain.spendable_outputs(&pubkey_hash).len(), 1);
+    }
+
+    #[test]
+    fn blocks_have_to_fit_the_weight_limit() {
+        let blockchain = with_genesis();
+        let extra_data = vec![1; crate::MAX_BLOCK_WEIGHT / crate::WITNESS_SCALE_FACTOR];
+        let coinbase = Transaction::new_coinbase(1, extra_data, vec![]);
+        let mut header = BlockHeader::new(
+            Utc::now(),
+            0,
+            blockchain.blocks[0].hash(),
+            MerkleRoot::calculate(std::slice::from_ref(&coinbase)),
+            blockchain.next_target(),
+        );
+        assert!(header.mine(usize::MAX));
+        let block = Block::new(header, vec![coinbase]);
+        assert!(block.weight() > crate::MAX_BLOCK_WEIGHT);
+        assert!(matches!(
+            blockchain.clone().add_block(block),
+            Err(BtcError::InvalidBlock)
+        ));
+    }
+}
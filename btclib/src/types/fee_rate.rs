@@ -0,0 +1,105 @@
+use {
+    super::amount::Amount,
+    std::fmt,
+};
+
+/// Fee rate in satoshis per 1000 weight units
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+pub struct FeeRate(u64);
+
+impl FeeRate {
+    pub const ZERO: FeeRate = FeeRate(0);
+
+    pub const fn from_sat_per_kwu(sat_per_kwu: u64) -> Self {
+        FeeRate(sat_per_kwu)
+    }
+
+    // a virtual byte is WITNESS_SCALE_FACTOR weight units
+    pub const fn from_sat_per_vbyte(sat_per_vbyte: u64) -> Self {
+        FeeRate(sat_per_vbyte * 1000 / crate::WITNESS_SCALE_FACTOR as u64)
+    }
+
+    // fee rate paid by `fee` for `weight` units, rounded down
+    pub fn from_fee_and_weight(fee: Amount, weight: usize) -> Self {
+        let rate = fee.to_sat() as u128 * 1000 / weight.max(1) as u128;
+        FeeRate(rate.min(u64::MAX as u128) as u64)
+    }
+
+    pub fn to_sat_per_kwu(self) -> u64 {
+        self.0
+    }
+
+    pub fn to_sat_per_vbyte(self) -> f64 {
+        self.0 as f64 * crate::WITNESS_SCALE_FACTOR as f64 / 1000.0
+    }
+
+    // fee needed for `weight` units to pay this rate, rounded up
+    pub fn fee_for_weight(self, weight: usize) -> Amount {
+        let fee = (self.0 as u128 * weight as u128).div_ceil(1000);
+        Amount::from_sat(fee.min(u64::MAX as u128) as u64)
+    }
+}
+
+impl fmt::Display for FeeRate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.2} sat/vB", self.to_sat_per_vbyte())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fees_are_rounded_up() {
+        let rate = FeeRate::from_sat_per_kwu(1000);
+        assert_eq!(rate.fee_for_weight(0), Amount::ZERO);
+        assert_eq!(rate.fee_for_weight(1), Amount::from_sat(1));
+        assert_eq!(rate.fee_for_weight(400), Amount::from_sat(400));
+        let rate = FeeRate::from_sat_per_kwu(1);
+        assert_eq!(rate.fee_for_weight(1), Amount::from_sat(1));
+        assert_eq!(rate.fee_for_weight(1000), Amount::from_sat(1));
+        assert_eq!(rate.fee_for_weight(1001), Amount::from_sat(2));
+        assert_eq!(FeeRate::ZERO.fee_for_weight(1000), Amount::ZERO);
+        assert_eq!(
+            FeeRate::from_sat_per_kwu(u64::MAX).fee_for_weight(usize::MAX),
+            Amount::from_sat(u64::MAX)
+        );
+    }
+
+    #[test]
+    fn rates_are_rounded_down() {
+        assert_eq!(
+            FeeRate::from_fee_and_weight(Amount::from_sat(1), 1001),
+            FeeRate::ZERO
+        );
+        assert_eq!(
+            FeeRate::from_fee_and_weight(Amount::from_sat(1999), 1000),
+            FeeRate::from_sat_per_kwu(1999)
+        );
+        assert_eq!(
+            FeeRate::from_fee_and_weight(Amount::from_sat(10), 3),
+            FeeRate::from_sat_per_kwu(3333)
+        );
+        // a fee for no weight counts as paid for one unit
+        assert_eq!(
+            FeeRate::from_fee_and_weight(Amount::from_sat(1), 0),
+            FeeRate::from_sat_per_kwu(1000)
+        );
+        // paying the rate for a weight pays at least that rate
+        let rate = FeeRate::from_sat_per_kwu(1234);
+        for weight in [1, 7, 999, 1001, 123_456] {
+            assert!(FeeRate::from_fee_and_weight(rate.fee_for_weight(weight), weight) >= rate);
+        }
+    }
+
+    #[test]
+    fn virtual_bytes_are_4_weight_units() {
+        let rate = FeeRate::from_sat_per_vbyte(1);
+        assert_eq!(rate, FeeRate::from_sat_per_kwu(250));
+        assert_eq!(rate.to_sat_per_vbyte(), 1.0);
+        assert_eq!(rate.fee_for_weight(400), Amount::from_sat(100));
+    }
+}
@@ -0,0 +1,1010 @@
+use {
+    super::{
+        amount::Amount,
+        fee_rate::FeeRate,
+        transaction::{
+            Transaction,
+            TransactionOutput,
+        },
+        utxo::Utxo,
+    },
+    crate::{
+        error::RejectReason,
+        sha256::Hash,
+    },
+    chrono::{
+        DateTime,
+        Utc,
+    },
+    std::{
+        cmp::Ordering,
+        collections::{
+            HashMap,
+            HashSet,
+        },
+    },
+};
+
+#[derive(Debug, Clone)]
+pub struct MempoolEntry {
+    pub transaction: Transaction,
+    /// When the transaction entered the mempool
+    pub received: DateTime<Utc>,
+    /// Fee paid to the miner in satoshis
+    pub fee: Amount,
+    /// Size of the serialized transaction in bytes
+    pub size: usize,
+    /// Weight of the transaction, fee rates are based on it
+    pub weight: usize,
+    /// Mempool transactions whose outputs this one spends
+    pub parents: HashSet<Hash>,
+    /// Mempool transactions spending this one's outputs
+    pub children: HashSet<Hash>,
+}
+
+impl MempoolEntry {
+    pub fn fee_rate(&self) -> FeeRate {
+        FeeRate::from_fee_and_weight(self.fee, self.weight)
+    }
+
+    // compare fee rates without losing precision to division
+    pub fn cmp_fee_rate(&self, other: &MempoolEntry) -> Ordering {
+        (self.fee.to_sat() as u128 * other.weight as u128)
+            .cmp(&(other.fee.to_sat() as u128 * self.weight as u128))
+    }
+}
+
+/// Mempool transactions removed to make way for a new one
+#[derive(Debug, Default)]
+pub struct Displaced {
+    /// Transactions double spent by the new one, and their
+    /// descendants
+    pub replaced: Vec<MempoolEntry>,
+    /// Transactions evicted because the mempool was full
+    pub evicted: Vec<MempoolEntry>,
+}
+
+/// Unconfirmed transactions waiting to be mined, indexed by
+/// transaction hash, by the outputs they spend and by the
+/// outputs they create
+#[derive(Debug, Clone)]
+pub struct Mempool {
+    entries: HashMap<Hash, MempoolEntry>,
+    // spent output hash -> hash of the spending transaction
+    spent: HashMap<Hash, Hash>,
+    // created output hash -> hash of the creating transaction
+    outputs: HashMap<Hash, (Hash, TransactionOutput)>,
+    size: usize,
+    max_size: usize,
+}
+
+impl Default for Mempool {
+    fn default() -> Self {
+        Mempool::new(crate::MAX_MEMPOOL_SIZE)
+    }
+}
+
+impl Mempool {
+    pub fn new(max_size: usize) -> Self {
+        Mempool {
+            entries: HashMap::new(),
+            spent: HashMap::new(),
+            outputs: HashMap::new(),
+            size: 0,
+            max_size,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Total size of all transactions in bytes
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn get(&self, hash: &Hash) -> Option<&MempoolEntry> {
+        self.entries.get(hash)
+    }
+
+    pub fn contains(&self, hash: &Hash) -> bool {
+        self.entries.contains_key(hash)
+    }
+
+    /// Hash of the mempool transaction spending an output, if any
+    pub fn spender(&self, output_hash: &Hash) -> Option<&Hash> {
+        self.spent.get(output_hash)
+    }
+
+    /// Hashes of all outputs spent by mempool transactions
+    pub fn spent_outputs(&self) -> impl Iterator<Item = &Hash> {
+        self.spent.keys()
+    }
+
+    /// An output created by a mempool transaction
+    pub fn output(&self, output_hash: &Hash) -> Option<&TransactionOutput> {
+        self.outputs.get(output_hash).map(|(_, output)| output)
+    }
+
+    /// Outputs created by mempool transactions that no other
+    /// mempool transaction spends yet
+    pub fn unspent_outputs(&self) -> impl Iterator<Item = &TransactionOutput> {
+        self.outputs
+            .iter()
+            .filter(|(hash, _)| !self.spent.contains_key(hash))
+            .map(|(_, (_, output))| output)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &MempoolEntry> {
+        self.entries.values()
+    }
+
+    /// All mempool transactions `hash` depends on, directly or not
+    pub fn ancestors(&self, hash: &Hash) -> HashSet<Hash> {
+        self.walk(hash, |entry| &entry.parents)
+    }
+
+    /// All mempool transactions depending on `hash`, directly or not
+    pub fn descendants(&self, hash: &Hash) -> HashSet<Hash> {
+        self.walk(hash, |entry| &entry.children)
+    }
+
+    fn walk(&self, hash: &Hash, next: impl Fn(&MempoolEntry) -> &HashSet<Hash>) -> HashSet<Hash> {
+        let mut found = HashSet::new();
+        let mut stack = vec![*hash];
+        while let Some(current) = stack.pop() {
+            let Some(entry) = self.entries.get(&current) else {
+                continue;
+            };
+            for linked in next(entry).iter() {
+                if found.insert(*linked) {
+                    stack.push(*linked);
+                }
+            }
+        }
+        found
+    }
+
+    /// Check a transaction against the mempool policy and add it.
+    /// Inputs may spend confirmed outputs or outputs of other
+    /// mempool transactions, within the ancestor and descendant
+    /// limits. Transactions spending the same outputs are only
+    /// replaced if they signal it and the newcomer pays more (see
+    /// `check_replacement`), and the lowest fee rate transactions
+    /// are evicted to make room if the mempool is full.
+    /// `height` and `median_time_past` are the ones of the next
+    /// block, which decide whether coinbase outputs are mature and
+    /// whether time locks have expired.
+    pub fn insert(
+        &mut self,
+        transaction: Transaction,
+        utxos: &HashMap<Hash, Utxo>,
+        height: u64,
+        median_time_past: DateTime<Utc>,
+    ) -> Result<Displaced, RejectReason> {
+        let hash = transaction.hash();
+        if self.entries.contains_key(&hash) {
+            return Err(RejectReason::AlreadyKnown);
+        }
+        if transaction.is_coinbase() {
+            return Err(RejectReason::Coinbase);
+        }
+        if transaction.inputs.is_empty() {
+            return Err(RejectReason::NoInputs);
+        }
+        if !transaction.has_low_s_signatures() {
+            return Err(RejectReason::HighS);
+        }
+        // only transactions that could be mined in the next block
+        if !transaction.is_final(height, median_time_past) {
+            return Err(RejectReason::NonFinal);
+        }
+
+        // all inputs must match known UTXOs or mempool outputs, and
+        // must be unique
+        let mut known_inputs = HashSet::new();
+        let mut parents = HashSet::new();
+        let mut input_value = Amount::ZERO;
+        let signature_hashes = transaction.signature_hashes();
+        for (input, signature_hash) in transaction.inputs.iter().zip(signature_hashes.iter()) {
+            // unconfirmed outputs count as confirmed in the next block
+            let (prev_output, prev_height, prev_time) = if let Some(utxo) =
+                utxos.get(&input.prev_tx_output_hash)
+            {
+                if !utxo.is_mature(height) {
+                    return Err(RejectReason::ImmatureCoinbase(input.prev_tx_output_hash));
+                }
+                (&utxo.output, utxo.height, utxo.median_time_past)
+            } else if let Some((parent, output)) = self.outputs.get(&input.prev_tx_output_hash) {
+                parents.insert(*parent);
+                (output, height, median_time_past)
+            } else {
+                return Err(RejectReason::UnknownInput(input.prev_tx_output_hash));
+            };
+            // the same check blocks make, so that the mempool
+            // only holds transactions that can be mined
+            if !prev_output
+                .lock
+                .is_unlocked_by(&transaction, input, signature_hash)
+            {
+                return Err(RejectReason::InvalidSignature(input.prev_tx_output_hash));
+            }
+            if let Some(lock) = input.relative_lock() {
+                if !lock.is_satisfied(prev_height, prev_time, height, median_time_past) {
+                    return Err(RejectReason::SequenceLocked(input.prev_tx_output_hash));
+                }
+            }
+            if !known_inputs.insert(input.prev_tx_output_hash) {
+                return Err(RejectReason::DuplicateInput(input.prev_tx_output_hash));
+            }
+            input_value = input_value
+                .checked_add(prev_output.value)
+                .ok_or(RejectReason::AmountOutOfRange)?;
+        }
+
+        let mut ancestors = parents.clone();
+        for parent in parents.iter() {
+            ancestors.extend(self.ancestors(parent));
+        }
+        if ancestors.len() + 1 > crate::MAX_MEMPOOL_ANCESTORS {
+            return Err(RejectReason::TooManyAncestors);
+        }
+        // the ancestor and the new transaction count on top of the
+        // ancestor's current descendants
+        for ancestor in ancestors.iter() {
+            if self.descendants(ancestor).len() + 2 > crate::MAX_MEMPOOL_DESCENDANTS {
+                return Err(RejectReason::TooManyDescendants);
+            }
+        }
+
+        let mut output_value = Amount::ZERO;
+        for output in transaction.outputs.iter() {
+            if output.value < crate::DUST_THRESHOLD {
+                return Err(RejectReason::Dust(output.value));
+            }
+            output_value = output_value
+                .checked_add(output.value)
+                .ok_or(RejectReason::AmountOutOfRange)?;
+        }
+
+        let fee = input_value
+            .checked_sub(output_value)
+            .ok_or(RejectReason::OutputsExceedInputs)?;
+        let size = transaction.size();
+        let weight = transaction.weight();
+        let min_fee = crate::MIN_RELAY_FEE_RATE.fee_for_weight(weight);
+        if fee < min_fee {
+            return Err(RejectReason::FeeTooLow { fee, min_fee });
+        }
+
+        let entry = MempoolEntry {
+            transaction,
+            received: Utc::now(),
+            fee,
+            size,
+            weight,
+            parents,
+            children: HashSet::new(),
+        };
+
+        // transactions spending the same outputs are replaced,
+        // together with their descendants
+        let direct_conflicts = entry
+            .transaction
+            .inputs
+            .iter()
+            .filter_map(|input| self.spent.get(&input.prev_tx_output_hash))
+            .copied()
+            .collect::<HashSet<_>>();
+        // a transaction can not replace one it depends on
+        if let Some(input) = entry.transaction.inputs.iter().find(|input| {
+            self.spent
+                .get(&input.prev_tx_output_hash)
+                .is_some_and(|spender| ancestors.contains(spender))
+        }) {
+            return Err(RejectReason::DuplicateInput(input.prev_tx_output_hash));
+        }
+        let mut conflicts = direct_conflicts.clone();
+        for conflict in direct_conflicts.iter() {
+            conflicts.extend(self.descendants(conflict));
+        }
+        self.check_replacement(&entry, &direct_conflicts, &conflicts)?;
+        let evictions = self.plan_evictions(&entry, &ancestors, &conflicts)?;
+
+        let mut displaced = Displaced::default();
+        for hash in direct_conflicts.iter() {
+            displaced.replaced.extend(self.remove(hash));
+        }
+        for hash in evictions.iter() {
+            displaced.evicted.extend(self.remove(hash));
+        }
+        self.add_entry(hash, entry);
+
+        Ok(displaced)
+    }
+
+    // replace-by-fee rules: every directly conflicting transaction
+    // must signal replaceability and pay a lower fee rate, the
+    // replacement must pay for everything it evicts plus its own
+    // relay fee, and it can not evict too many transactions.
+    // Signatures commit to the outputs (see
+    // `Transaction::signature_hashes`), so only the owners of the
+    // spent outputs can make a replacement that gets this far
+    fn check_replacement(
+        &self,
+        entry: &MempoolEntry,
+        direct_conflicts: &HashSet<Hash>,
+        conflicts: &HashSet<Hash>,
+    ) -> Result<(), RejectReason> {
+        if conflicts.is_empty() {
+            return Ok(());
+        }
+        for hash in direct_conflicts.iter() {
+            let conflict = &self.entries[hash];
+            if !conflict.transaction.signals_replacement() {
+                return Err(RejectReason::NotReplaceable(*hash));
+            }
+            if entry.cmp_fee_rate(conflict) != Ordering::Greater {
+                return Err(RejectReason::ReplacementFeeRateTooLow(*hash));
+            }
+        }
+        if conflicts.len() > crate::MAX_REPLACEMENT_EVICTIONS {
+            return Err(RejectReason::TooManyReplacements(conflicts.len()));
+        }
+        let required = Amount::checked_sum(conflicts.iter().map(|hash| self.entries[hash].fee))
+            .and_then(|fees| {
+                fees.checked_add(crate::MIN_RELAY_FEE_RATE.fee_for_weight(entry.weight))
+            })
+            .ok_or(RejectReason::AmountOutOfRange)?;
+        if entry.fee < required {
+            return Err(RejectReason::InsufficientReplacementFee {
+                fee: entry.fee,
+                required,
+            });
+        }
+        Ok(())
+    }
+
+    fn add_entry(&mut self, hash: Hash, entry: MempoolEntry) {
+        for input in entry.transaction.inputs.iter() {
+            self.spent.insert(input.prev_tx_output_hash, hash);
+        }
+        for output in entry.transaction.outputs.iter() {
+            self.outputs.insert(output.hash(), (hash, output.clone()));
+        }
+        for parent in entry.parents.iter() {
+            if let Some(parent) = self.entries.get_mut(parent) {
+                parent.children.insert(hash);
+            }
+        }
+        self.size += entry.size;
+        self.entries.insert(hash, entry);
+    }
+
+    // pick the cheapest transactions to evict until `entry` fits,
+    // as long as they pay a lower fee rate than it does. Evicting a
+    // transaction also evicts its descendants. The ancestors of
+    // `entry` are never evicted, and they are no descendants of any
+    // other transaction, which would be an ancestor of `entry` too.
+    fn plan_evictions(
+        &self,
+        entry: &MempoolEntry,
+        ancestors: &HashSet<Hash>,
+        conflicts: &HashSet<Hash>,
+    ) -> Result<Vec<Hash>, RejectReason> {
+        let mut evicted = conflicts.clone();
+        let evicted_size = |evicted: &HashSet<Hash>| {
+            evicted
+                .iter()
+                .filter_map(|hash| self.entries.get(hash))
+                .map(|evicted| evicted.size)
+                .sum::<usize>()
+        };
+        if self.size - evicted_size(&evicted) + entry.size <= self.max_size {
+            return Ok(vec![]);
+        }
+
+        let mut candidates = self
+            .entries
+            .iter()
+            .filter(|(hash, _)| !conflicts.contains(hash) && !ancestors.contains(hash))
+            .collect::<Vec<_>>();
+        candidates.sort_by(|(_, a), (_, b)| a.cmp_fee_rate(b));
+
+        let mut evictions = vec![];
+        for (hash, candidate) in candidates {
+            if self.size - evicted_size(&evicted) + entry.size <= self.max_size {
+                break;
+            }
+            if evicted.contains(hash) {
+                continue;
+            }
+            if candidate.cmp_fee_rate(entry) != Ordering::Less {
+                return Err(RejectReason::MempoolFull);
+            }
+            evicted.insert(*hash);
+            evicted.extend(self.descendants(hash));
+            evictions.push(*hash);
+        }
+        if self.size - evicted_size(&evicted) + entry.size > self.max_size {
+            return Err(RejectReason::MempoolFull);
+        }
+        Ok(evictions)
+    }
+
+    /// Remove a transaction and everything depending on it
+    pub fn remove(&mut self, hash: &Hash) -> Vec<MempoolEntry> {
+        if !self.entries.contains_key(hash) {
+            return vec![];
+        }
+        let mut removed = self.descendants(hash).into_iter().collect::<Vec<_>>();
+        removed.push(*hash);
+        removed
+            .iter()
+            .filter_map(|hash| self.remove_entry(hash))
+            .collect()
+    }
+
+    // remove a single transaction, its children are only unlinked
+    fn remove_entry(&mut self, hash: &Hash) -> Option<MempoolEntry> {
+        let entry = self.entries.remove(hash)?;
+        for input in entry.transaction.inputs.iter() {
+            self.spent.remove(&input.prev_tx_output_hash);
+        }
+        for output in entry.transaction.outputs.iter() {
+            self.outputs.remove(&output.hash());
+        }
+        for parent in entry.parents.iter() {
+            if let Some(parent) = self.entries.get_mut(parent) {
+                parent.children.remove(hash);
+            }
+        }
+        for child in entry.children.iter() {
+            if let Some(child) = self.entries.get_mut(child) {
+                child.parents.remove(hash);
+            }
+        }
+        self.size -= entry.size;
+        Some(entry)
+    }
+
+    /// Remove transactions that were included in a block, along
+    /// with any that spend the same outputs and their descendants.
+    /// Children of confirmed transactions stay in the mempool.
+    /// Returns the removed conflicting entries.
+    pub fn remove_confirmed(&mut self, transactions: &[Transaction]) -> Vec<MempoolEntry> {
+        let mut conflicts = vec![];
+        for transaction in transactions.iter() {
+            let hash = transaction.hash();
+            if self.remove_entry(&hash).is_some() {
+                continue;
+            }
+            for input in transaction.inputs.iter() {
+                if let Some(spender) = self.spent.get(&input.prev_tx_output_hash).copied() {
+                    conflicts.extend(self.remove(&spender));
+                }
+            }
+        }
+        conflicts
+    }
+
+    /// Remove transactions received before `cutoff`, along with
+    /// their descendants
+    pub fn expire(&mut self, cutoff: DateTime<Utc>) -> Vec<MempoolEntry> {
+        let expired = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| entry.received < cutoff)
+            .map(|(hash, _)| *hash)
+            .collect::<Vec<_>>();
+        expired.iter().flat_map(|hash| self.remove(hash)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            crypto::{
+                PrivateKey,
+                Signature,
+            },
+            types::transaction::TransactionInput,
+        },
+        uuid::Uuid,
+    };
+
+    fn output(value: u64, private_key: &PrivateKey) -> TransactionOutput {
+        TransactionOutput {
+            value: Amount::from_sat(value),
+            unique_id: Uuid::new_v4(),
+            lock: private_key.public_key().into(),
+        }
+    }
+
+    // a transaction spending `spent`, signed by `private_key` and
+    // signalling replace-by-fee
+    fn spend(
+        spent: &TransactionOutput,
+        paid: TransactionOutput,
+        private_key: &PrivateKey,
+    ) -> Transaction {
+        spend_to(spent, vec![paid], private_key)
+    }
+
+    fn spend_to(
+        spent: &TransactionOutput,
+        paid: Vec<TransactionOutput>,
+        private_key: &PrivateKey,
+    ) -> Transaction {
+        let mut transaction = Transaction::new(
+            vec![TransactionInput {
+                prev_tx_output_hash: spent.hash(),
+                signature: Signature::sign(&spent.hash(), private_key),
+                sequence: 0,
+                pubkey: None,
+                unlock: Default::default(),
+                extra_signatures: vec![],
+            }],
+            paid,
+        );
+        let signature_hash = transaction.signature_hashes()[0];
+        transaction.inputs[0].signature = Signature::sign(&signature_hash, private_key);
+        transaction
+    }
+
+    // `transaction` with a new sequence number and lock time, signed
+    // again by `private_key`
+    fn with_locks(
+        mut transaction: Transaction,
+        sequence: u32,
+        lock_time: u64,
+        private_key: &PrivateKey,
+    ) -> Transaction {
+        transaction.inputs[0].sequence = sequence;
+        transaction.lock_time = lock_time;
+        let signature_hash = transaction.signature_hashes()[0];
+        transaction.inputs[0].signature = Signature::sign(&signature_hash, private_key);
+        transaction
+    }
+
+    fn confirmed(outputs: &[&TransactionOutput]) -> HashMap<Hash, Utxo> {
+        outputs
+            .iter()
+            .map(|output| {
+                (
+                    output.hash(),
+                    Utxo::new((*output).clone(), 0, Utc::now(), false),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn only_the_owner_can_replace() {
+        let owner = PrivateKey::new_key();
+        let thief = PrivateKey::new_key();
+        let spent = output(1_000_000, &owner);
+        let utxos = HashMap::from([(spent.hash(), Utxo::new(spent.clone(), 0, Utc::now(), false))]);
+        let mut mempool = Mempool::default();
+        let original = spend(&spent, output(990_000, &owner), &owner);
+        mempool
+            .insert(original.clone(), &utxos, 1, Utc::now())
+            .unwrap();
+
+        // the original signature with outputs paying the thief
+        let mut stolen = original.clone();
+        stolen.outputs = vec![output(900_000, &thief)];
+        assert_eq!(
+            mempool.insert(stolen, &utxos, 1, Utc::now()).unwrap_err(),
+            RejectReason::InvalidSignature(spent.hash())
+        );
+        assert!(mempool.contains(&original.hash()));
+
+        let replacement = spend(&spent, output(900_000, &owner), &owner);
+        let displaced = mempool
+            .insert(replacement.clone(), &utxos, 1, Utc::now())
+            .unwrap();
+        assert_eq!(displaced.replaced.len(), 1);
+        assert!(mempool.contains(&replacement.hash()));
+        assert!(!mempool.contains(&original.hash()));
+    }
+
+    #[test]
+    fn rejects_with_the_reason() {
+        let private_key = PrivateKey::new_key();
+        let spent = output(1_000_000, &private_key);
+        let utxos = confirmed(&[&spent]);
+        let mut mempool = Mempool::default();
+        let insert = |mempool: &mut Mempool, transaction: Transaction| {
+            mempool.insert(transaction, &utxos, 1, Utc::now())
+        };
+
+        let coinbase = Transaction::new_coinbase(1, vec![], vec![output(1_000, &private_key)]);
+        assert_eq!(
+            insert(&mut mempool, coinbase).unwrap_err(),
+            RejectReason::Coinbase
+        );
+        let no_inputs = Transaction::new(vec![], vec![output(1_000, &private_key)]);
+        assert_eq!(
+            insert(&mut mempool, no_inputs).unwrap_err(),
+            RejectReason::NoInputs
+        );
+        let unknown = output(1_000_000, &private_key);
+        let transaction = spend(&unknown, output(990_000, &private_key), &private_key);
+        assert_eq!(
+            insert(&mut mempool, transaction).unwrap_err(),
+            RejectReason::UnknownInput(unknown.hash())
+        );
+        let transaction = spend(&spent, output(1_000_001, &private_key), &private_key);
+        assert_eq!(
+            insert(&mut mempool, transaction).unwrap_err(),
+            RejectReason::OutputsExceedInputs
+        );
+        let mut transaction = spend(&spent, output(990_000, &private_key), &private_key);
+        transaction.inputs.push(transaction.inputs[0].clone());
+        let signature_hashes = transaction.signature_hashes();
+        for (input, signature_hash) in transaction.inputs.iter_mut().zip(signature_hashes) {
+            input.signature = Signature::sign(&signature_hash, &private_key);
+        }
+        assert_eq!(
+            insert(&mut mempool, transaction).unwrap_err(),
+            RejectReason::DuplicateInput(spent.hash())
+        );
+
+        let transaction = spend(&spent, output(990_000, &private_key), &private_key);
+        insert(&mut mempool, transaction.clone()).unwrap();
+        assert_eq!(
+            insert(&mut mempool, transaction).unwrap_err(),
+            RejectReason::AlreadyKnown
+        );
+    }
+
+    #[test]
+    fn rejects_dust_outputs() {
+        let private_key = PrivateKey::new_key();
+        let spent = output(1_000_000, &private_key);
+        let utxos = confirmed(&[&spent]);
+        let mut mempool = Mempool::default();
+        let dust = crate::DUST_THRESHOLD.to_sat() - 1;
+        let mut transaction = spend(&spent, output(990_000, &private_key), &private_key);
+        transaction.outputs.push(output(dust, &private_key));
+        let signature_hash = transaction.signature_hashes()[0];
+        transaction.inputs[0].signature = Signature::sign(&signature_hash, &private_key);
+        assert_eq!(
+            mempool
+                .insert(transaction.clone(), &utxos, 1, Utc::now())
+                .unwrap_err(),
+            RejectReason::Dust(Amount::from_sat(dust))
+        );
+
+        transaction.outputs[1] = output(dust + 1, &private_key);
+        let signature_hash = transaction.signature_hashes()[0];
+        transaction.inputs[0].signature = Signature::sign(&signature_hash, &private_key);
+        mempool.insert(transaction, &utxos, 1, Utc::now()).unwrap();
+    }
+
+    #[test]
+    fn needs_the_min_relay_fee() {
+        let private_key = PrivateKey::new_key();
+        let spent = output(1_000_000, &private_key);
+        let utxos = confirmed(&[&spent]);
+        let mut mempool = Mempool::default();
+        let transaction = spend(&spent, output(999_999, &private_key), &private_key);
+        let min_fee = crate::MIN_RELAY_FEE_RATE.fee_for_weight(transaction.weight());
+        assert_eq!(
+            mempool
+                .insert(transaction, &utxos, 1, Utc::now())
+                .unwrap_err(),
+            RejectReason::FeeTooLow {
+                fee: Amount::from_sat(1),
+                min_fee,
+            }
+        );
+
+        // signatures vary in size by a few bytes
+        let fee = min_fee + Amount::from_sat(10);
+        let transaction = spend(
+            &spent,
+            output(1_000_000 - fee.to_sat(), &private_key),
+            &private_key,
+        );
+        let displaced = mempool
+            .insert(transaction.clone(), &utxos, 1, Utc::now())
+            .unwrap();
+        assert!(displaced.replaced.is_empty() && displaced.evicted.is_empty());
+        assert_eq!(mempool.get(&transaction.hash()).unwrap().fee, fee);
+    }
+
+    #[test]
+    fn evicts_the_lowest_fee_rate_when_full() {
+        let private_key = PrivateKey::new_key();
+        let spent = [
+            output(1_000_000, &private_key),
+            output(1_000_000, &private_key),
+            output(1_000_000, &private_key),
+            output(1_000_000, &private_key),
+        ];
+        let utxos = confirmed(&spent.iter().collect::<Vec<_>>());
+        let [cheap, rich, richer, cheaper] = [1_000, 5_000, 10_000, 500]
+            .map(|fee| 1_000_000 - fee)
+            .map(|paid| output(paid, &private_key));
+        let cheap = spend(&spent[0], cheap, &private_key);
+        let rich = spend(&spent[1], rich, &private_key);
+        let richer = spend(&spent[2], richer, &private_key);
+        let cheaper = spend(&spent[3], cheaper, &private_key);
+
+        // room for two of the transactions, which are about as big
+        let mut mempool = Mempool::new(cheap.size() + rich.size() + 10);
+        mempool
+            .insert(cheap.clone(), &utxos, 1, Utc::now())
+            .unwrap();
+        mempool.insert(rich.clone(), &utxos, 1, Utc::now()).unwrap();
+        let displaced = mempool
+            .insert(richer.clone(), &utxos, 1, Utc::now())
+            .unwrap();
+        let evicted = displaced
+            .evicted
+            .iter()
+            .map(|entry| entry.transaction.hash())
+            .collect::<Vec<_>>();
+        assert_eq!(evicted, vec![cheap.hash()]);
+        assert!(displaced.replaced.is_empty());
+        assert!(mempool.contains(&rich.hash()) && mempool.contains(&richer.hash()));
+        assert!(mempool.size() <= cheap.size() + rich.size() + 10);
+
+        assert_eq!(
+            mempool.insert(cheaper, &utxos, 1, Utc::now()).unwrap_err(),
+            RejectReason::MempoolFull
+        );
+        assert_eq!(mempool.len(), 2);
+    }
+
+    #[test]
+    fn accepts_children_of_unconfirmed_parents() {
+        let private_key = PrivateKey::new_key();
+        let spent = output(1_000_000, &private_key);
+        let utxos = confirmed(&[&spent]);
+        let mut mempool = Mempool::default();
+        let parent = spend(&spent, output(990_000, &private_key), &private_key);
+        let child = spend(
+            &parent.outputs[0],
+            output(980_000, &private_key),
+            &private_key,
+        );
+        // the parent has to come first
+        assert_eq!(
+            mempool
+                .insert(child.clone(), &utxos, 1, Utc::now())
+                .unwrap_err(),
+            RejectReason::UnknownInput(parent.outputs[0].hash())
+        );
+        mempool
+            .insert(parent.clone(), &utxos, 1, Utc::now())
+            .unwrap();
+        mempool
+            .insert(child.clone(), &utxos, 1, Utc::now())
+            .unwrap();
+
+        let entry = mempool.get(&child.hash()).unwrap();
+        assert_eq!(entry.parents, HashSet::from([parent.hash()]));
+        assert_eq!(entry.fee, Amount::from_sat(10_000));
+        let entry = mempool.get(&parent.hash()).unwrap();
+        assert_eq!(entry.children, HashSet::from([child.hash()]));
+        assert_eq!(
+            mempool.spender(&parent.outputs[0].hash()),
+            Some(&child.hash())
+        );
+    }
+
+    #[test]
+    fn limits_unconfirmed_ancestors() {
+        let private_key = PrivateKey::new_key();
+        let spent = output(1_000_000, &private_key);
+        let utxos = confirmed(&[&spent]);
+        let mut mempool = Mempool::default();
+        let mut spent = spent;
+        for _ in 0..crate::MAX_MEMPOOL_ANCESTORS {
+            let paid = output(spent.value.to_sat() - 1_000, &private_key);
+            let transaction = spend(&spent, paid, &private_key);
+            mempool
+                .insert(transaction.clone(), &utxos, 1, Utc::now())
+                .unwrap();
+            spent = transaction.outputs[0].clone();
+        }
+        let paid = output(spent.value.to_sat() - 1_000, &private_key);
+        let transaction = spend(&spent, paid, &private_key);
+        assert_eq!(
+            mempool
+                .insert(transaction, &utxos, 1, Utc::now())
+                .unwrap_err(),
+            RejectReason::TooManyAncestors
+        );
+    }
+
+    #[test]
+    fn limits_unconfirmed_descendants() {
+        let private_key = PrivateKey::new_key();
+        let spent = output(10_000_000, &private_key);
+        let utxos = confirmed(&[&spent]);
+        let mut mempool = Mempool::default();
+        let outputs = (0..crate::MAX_MEMPOOL_DESCENDANTS)
+            .map(|_| output(100_000, &private_key))
+            .collect::<Vec<_>>();
+        let parent = spend_to(&spent, outputs, &private_key);
+        mempool
+            .insert(parent.clone(), &utxos, 1, Utc::now())
+            .unwrap();
+        // the parent counts as one of its own descendants
+        for spent in parent.outputs[1..].iter() {
+            let child = spend(spent, output(99_000, &private_key), &private_key);
+            mempool.insert(child, &utxos, 1, Utc::now()).unwrap();
+        }
+        let child = spend(
+            &parent.outputs[0],
+            output(99_000, &private_key),
+            &private_key,
+        );
+        assert_eq!(
+            mempool.insert(child, &utxos, 1, Utc::now()).unwrap_err(),
+            RejectReason::TooManyDescendants
+        );
+        assert_eq!(mempool.len(), crate::MAX_MEMPOOL_DESCENDANTS);
+    }
+
+    #[test]
+    fn evicts_descendants_with_their_parent() {
+        let private_key = PrivateKey::new_key();
+        let spent = [
+            output(1_000_000, &private_key),
+            output(1_000_000, &private_key),
+        ];
+        let utxos = confirmed(&[&spent[0], &spent[1]]);
+        let parent = spend(&spent[0], output(999_000, &private_key), &private_key);
+        // the child alone pays a better fee rate than the newcomer
+        let child = spend(
+            &parent.outputs[0],
+            output(900_000, &private_key),
+            &private_key,
+        );
+        let newcomer = spend(&spent[1], output(990_000, &private_key), &private_key);
+
+        let mut mempool = Mempool::new(parent.size() + child.size() + 10);
+        mempool
+            .insert(parent.clone(), &utxos, 1, Utc::now())
+            .unwrap();
+        mempool
+            .insert(child.clone(), &utxos, 1, Utc::now())
+            .unwrap();
+        let displaced = mempool
+            .insert(newcomer.clone(), &utxos, 1, Utc::now())
+            .unwrap();
+        let evicted = displaced
+            .evicted
+            .iter()
+            .map(|entry| entry.transaction.hash())
+            .collect::<HashSet<_>>();
+        assert_eq!(evicted, HashSet::from([parent.hash(), child.hash()]));
+        assert_eq!(mempool.len(), 1);
+        assert!(mempool.contains(&newcomer.hash()));
+        assert_eq!(mempool.spender(&parent.outputs[0].hash()), None);
+    }
+
+    #[test]
+    fn lock_times_have_to_pass_by_the_next_block() {
+        let private_key = PrivateKey::new_key();
+        let spent = output(1_000_000, &private_key);
+        let utxos = confirmed(&[&spent]);
+        let transaction = spend(&spent, output(990_000, &private_key), &private_key);
+        let insert = |transaction: &Transaction, height: u64, time: DateTime<Utc>| {
+            Mempool::default().insert(transaction.clone(), &utxos, height, time)
+        };
+
+        let locked = with_locks(transaction.clone(), 0, 10, &private_key);
+        assert_eq!(
+            insert(&locked, 10, Utc::now()).unwrap_err(),
+            RejectReason::NonFinal
+        );
+        insert(&locked, 11, Utc::now()).unwrap();
+
+        let time = Utc::now();
+        let locked = with_locks(transaction, 0, time.timestamp() as u64, &private_key);
+        assert_eq!(
+            insert(&locked, 1, time).unwrap_err(),
+            RejectReason::NonFinal
+        );
+        insert(&locked, 1, time + chrono::Duration::seconds(1)).unwrap();
+    }
+
+    #[test]
+    fn relative_locks_count_from_the_parent() {
+        let private_key = PrivateKey::new_key();
+        let spent = output(1_000_000, &private_key);
+        let time = Utc::now();
+        let utxos = HashMap::from([(spent.hash(), Utxo::new(spent.clone(), 5, time, false))]);
+        let transaction = spend(&spent, output(990_000, &private_key), &private_key);
+
+        let locked = with_locks(transaction.clone(), 3, 0, &private_key);
+        assert_eq!(
+            Mempool::default()
+                .insert(locked.clone(), &utxos, 7, time)
+                .unwrap_err(),
+            RejectReason::SequenceLocked(spent.hash())
+        );
+        Mempool::default().insert(locked, &utxos, 8, time).unwrap();
+
+        let sequence = crate::SEQUENCE_LOCKTIME_TYPE_FLAG | 1;
+        let seconds = 1 << crate::SEQUENCE_LOCKTIME_GRANULARITY;
+        let locked = with_locks(transaction.clone(), sequence, 0, &private_key);
+        let after = |seconds: i64| time + chrono::Duration::seconds(seconds);
+        assert_eq!(
+            Mempool::default()
+                .insert(locked.clone(), &utxos, 6, after(seconds - 1))
+                .unwrap_err(),
+            RejectReason::SequenceLocked(spent.hash())
+        );
+        Mempool::default()
+            .insert(locked, &utxos, 6, after(seconds))
+            .unwrap();
+
+        // unconfirmed parents count as confirmed in the next block
+        let mut mempool = Mempool::default();
+        mempool
+            .insert(transaction.clone(), &utxos, 6, time)
+            .unwrap();
+        let parent = &transaction.outputs[0];
+        let child = spend(parent, output(980_000, &private_key), &private_key);
+        let locked = with_locks(child.clone(), 1, 0, &private_key);
+        assert_eq!(
+            mempool.clone().insert(locked, &utxos, 6, time).unwrap_err(),
+            RejectReason::SequenceLocked(parent.hash())
+        );
+        mempool.insert(child, &utxos, 6, time).unwrap();
+    }
+
+    #[test]
+    fn coinbase_outputs_are_spent_after_100_blocks() {
+        let private_key = PrivateKey::new_key();
+        let spent = output(1_000_000, &private_key);
+        let utxos = HashMap::from([(spent.hash(), Utxo::new(spent.clone(), 5, Utc::now(), true))]);
+        let transaction = spend(&spent, output(990_000, &private_key), &private_key);
+        let insert = |height: u64| {
+            Mempool::default().insert(transaction.clone(), &utxos, height, Utc::now())
+        };
+        assert_eq!(
+            insert(5 + crate::COINBASE_MATURITY - 1).unwrap_err(),
+            RejectReason::ImmatureCoinbase(spent.hash())
+        );
+        insert(5 + crate::COINBASE_MATURITY).unwrap();
+    }
+
+    #[test]
+    fn high_s_signatures_have_their_own_reason() {
+        let private_key = PrivateKey::new_key();
+        let spent = output(1_000_000, &private_key);
+        let utxos = confirmed(&[&spent]);
+        let mut transaction = spend(&spent, output(990_000, &private_key), &private_key);
+        let Signature::Ecdsa(signature) = &transaction.inputs[0].signature else {
+            unreachable!();
+        };
+        let (r, s) = signature.split_scalars();
+        let high = Signature::Ecdsa(ecdsa::Signature::from_scalars(r, -*s).unwrap());
+        transaction.inputs[0].signature = high;
+        let mut mempool = Mempool::default();
+        assert_eq!(
+            mempool
+                .insert(transaction.clone(), &utxos, 1, Utc::now())
+                .unwrap_err(),
+            RejectReason::HighS
+        );
+        // the low s form of the same signature is valid
+        transaction.normalize_signatures();
+        mempool.insert(transaction, &utxos, 1, Utc::now()).unwrap();
+    }
+}
@@ -1,4 +1,9 @@
+pub mod amount;
 pub mod blockchain;
 pub mod block;
 pub mod block_header;
-pub mod transaction;
\ No newline at end of file
+pub mod fee_rate;
+pub mod mempool;
+pub mod partial_transaction;
+pub mod transaction;
+pub mod utxo;
\ No newline at end of file
@@ -0,0 +1,334 @@
+use {
+    super::transaction::{
+        signature_hashes,
+        Lock,
+        Transaction,
+        TransactionInput,
+        TransactionOutput,
+    },
+    crate::{
+        crypto::{
+            PrivateKey,
+            Signature,
+        },
+        error::{
+            BtcError,
+            Result,
+        },
+        sha256::Hash,
+        util::Saveable,
+    },
+    std::{
+        collections::BTreeMap,
+        io::{
+            Error as IoError,
+            ErrorKind as IoErrorKind,
+            Read,
+            Result as IoResult,
+            Write,
+        },
+    },
+};
+
+/// A transaction spending multisig outputs while its signatures are
+/// collected. The key holders sign it one after another, or sign
+/// copies that are combined afterwards, until every input has enough
+/// signatures to be finalized
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PartialTransaction {
+    pub inputs: Vec<PartialInput>,
+    pub outputs: Vec<TransactionOutput>,
+    pub lock_time: u64,
+}
+
+/// An input of a partial transaction with the signatures collected
+/// so far
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PartialInput {
+    pub prev_tx_output_hash: Hash,
+    pub sequence: u32,
+    /// Lock of the spent output, which has to be a valid
+    /// `Lock::Multisig`
+    pub lock: Lock,
+    /// Signatures by the index of their key in the lock
+    pub signatures: BTreeMap<usize, Signature>,
+}
+
+impl PartialInput {
+    // signatures still needed to spend the output
+    pub fn missing_signatures(&self) -> usize {
+        match &self.lock {
+            Lock::Multisig { threshold, .. } => threshold.saturating_sub(self.signatures.len()),
+            _ => 0,
+        }
+    }
+}
+
+impl PartialTransaction {
+    // spend the multisig outputs `spent` to `outputs`
+    pub fn new(spent: &[TransactionOutput], outputs: Vec<TransactionOutput>) -> Result<Self> {
+        let inputs = spent
+            .iter()
+            .map(|output| {
+                if !output.lock.is_valid_multisig() {
+                    return Err(BtcError::InvalidTransactionInput);
+                }
+                Ok(PartialInput {
+                    prev_tx_output_hash: output.hash(),
+                    sequence: crate::SEQUENCE_FINAL,
+                    lock: output.lock.clone(),
+                    signatures: BTreeMap::new(),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(PartialTransaction {
+            inputs,
+            outputs,
+            lock_time: 0,
+        })
+    }
+
+    // the hashes the signatures of the finalized transaction's
+    // inputs sign, which do not depend on the signatures
+    pub fn signature_hashes(&self) -> Vec<Hash> {
+        let inputs = self
+            .inputs
+            .iter()
+            .map(|input| (input.prev_tx_output_hash, input.sequence))
+            .collect::<Vec<_>>();
+        signature_hashes(&inputs, &self.outputs, self.lock_time)
+    }
+
+    // sign every input the key is one of the keys of, returning how
+    // many inputs were signed
+    pub fn sign(&mut self, private_key: &PrivateKey) -> usize {
+        let public_key = private_key.public_key();
+        let signature_hashes = self.signature_hashes();
+        let mut signed = 0;
+        for (input, signature_hash) in self.inputs.iter_mut().zip(signature_hashes.iter()) {
+            let Lock::Multisig { pubkeys, .. } = &input.lock else {
+                continue;
+            };
+            if let Some(index) = pubkeys.iter().position(|pubkey| *pubkey == public_key) {
+                let signature = Signature::sign(signature_hash, private_key);
+                input.signatures.insert(index, signature);
+                signed += 1;
+            }
+        }
+        signed
+    }
+
+    // add the signatures of another copy of the same transaction,
+    // skipping any that do not verify
+    pub fn combine(&mut self, other: &PartialTransaction) -> Result<()> {
+        let same_outputs = self.outputs.len() == other.outputs.len()
+            && self
+                .outputs
+                .iter()
+                .zip(other.outputs.iter())
+                .all(|(a, b)| a.hash() == b.hash());
+        let same_inputs = self.inputs.len() == other.inputs.len()
+            && self.inputs.iter().zip(other.inputs.iter()).all(|(a, b)| {
+                a.prev_tx_output_hash == b.prev_tx_output_hash
+                    && a.sequence == b.sequence
+                    && a.lock == b.lock
+            });
+        if !same_outputs || !same_inputs || self.lock_time != other.lock_time {
+            return Err(BtcError::InvalidTransaction);
+        }
+        let signature_hashes = self.signature_hashes();
+        for ((input, other), signature_hash) in self
+            .inputs
+            .iter_mut()
+            .zip(other.inputs.iter())
+            .zip(signature_hashes.iter())
+        {
+            let Lock::Multisig { pubkeys, .. } = &input.lock else {
+                continue;
+            };
+            for (index, signature) in other.signatures.iter() {
+                if pubkeys
+                    .get(*index)
+                    .is_some_and(|pubkey| signature.verify(signature_hash, pubkey))
+                {
+                    input.signatures.insert(*index, signature.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // signatures still needed over all inputs
+    pub fn missing_signatures(&self) -> usize {
+        self.inputs
+            .iter()
+            .map(PartialInput::missing_signatures)
+            .sum()
+    }
+
+    // the signed transaction, None while signatures are missing.
+    // Inputs with more signatures than needed use the ones of the
+    // first keys
+    pub fn finalize(&self) -> Option<Transaction> {
+        let inputs = self
+            .inputs
+            .iter()
+            .map(|input| {
+                let Lock::Multisig { threshold, .. } = input.lock else {
+                    return None;
+                };
+                let mut signatures = input.signatures.values().take(threshold).cloned();
+                let signature = signatures.next()?;
+                let extra_signatures = signatures.collect::<Vec<_>>();
+                if extra_signatures.len() + 1 != threshold {
+                    return None;
+                }
+                Some(TransactionInput {
+                    prev_tx_output_hash: input.prev_tx_output_hash,
+                    signature,
+                    sequence: input.sequence,
+                    pubkey: None,
+                    unlock: Default::default(),
+                    extra_signatures,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let mut transaction = Transaction::new(inputs, self.outputs.clone());
+        transaction.lock_time = self.lock_time;
+        Some(transaction)
+    }
+}
+
+impl Saveable for PartialTransaction {
+    fn load<I: Read>(reader: I) -> IoResult<Self> {
+        ciborium::de::from_reader(reader).map_err(|_| {
+            IoError::new(
+                IoErrorKind::InvalidData,
+                "Failed to deserialize PartialTransaction",
+            )
+        })
+    }
+
+    fn save<O: Write>(&self, writer: O) -> IoResult<()> {
+        ciborium::ser::into_writer(self, writer).map_err(|_| {
+            IoError::new(
+                IoErrorKind::InvalidData,
+                "Failed to serialize PartialTransaction",
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::types::amount::Amount,
+        uuid::Uuid,
+    };
+
+    fn output(value: u64, lock: Lock) -> TransactionOutput {
+        TransactionOutput {
+            value: Amount::from_sat(value),
+            unique_id: Uuid::new_v4(),
+            lock,
+        }
+    }
+
+    // the keys of a 2 of 3 multisig, and a partial transaction
+    // spending an output locked to them
+    fn two_of_three() -> ([PrivateKey; 3], TransactionOutput, PartialTransaction) {
+        let keys = [
+            PrivateKey::new_key(),
+            PrivateKey::new_key(),
+            PrivateKey::new_key(),
+        ];
+        let pubkeys = keys.iter().map(PrivateKey::public_key).collect();
+        let spent = output(1000, Lock::multisig(2, pubkeys).unwrap());
+        let outputs = vec![output(900, keys[0].public_key().into())];
+        let partial = PartialTransaction::new(std::slice::from_ref(&spent), outputs).unwrap();
+        (keys, spent, partial)
+    }
+
+    fn is_unlocked(transaction: &Transaction, spent: &TransactionOutput) -> bool {
+        let signature_hash = transaction.signature_hashes()[0];
+        spent
+            .lock
+            .is_unlocked_by(transaction, &transaction.inputs[0], &signature_hash)
+    }
+
+    #[test]
+    fn only_valid_multisig_outputs_can_be_spent() {
+        let private_key = PrivateKey::new_key();
+        let spent = output(1000, private_key.public_key().into());
+        assert!(PartialTransaction::new(&[spent], vec![]).is_err());
+        let duplicate = Lock::Multisig {
+            threshold: 2,
+            pubkeys: vec![private_key.public_key(), private_key.public_key()],
+        };
+        assert!(PartialTransaction::new(&[output(1000, duplicate)], vec![]).is_err());
+    }
+
+    #[test]
+    fn signing_one_after_another_finalizes() {
+        let (keys, spent, mut partial) = two_of_three();
+        assert_eq!(partial.missing_signatures(), 2);
+        assert_eq!(partial.sign(&PrivateKey::new_key()), 0);
+        assert_eq!(partial.sign(&keys[2]), 1);
+        assert_eq!(partial.missing_signatures(), 1);
+        assert!(partial.finalize().is_none());
+        assert_eq!(partial.sign(&keys[0]), 1);
+        assert_eq!(partial.missing_signatures(), 0);
+
+        let transaction = partial.finalize().unwrap();
+        assert_eq!(transaction.signature_hashes(), partial.signature_hashes());
+        assert!(is_unlocked(&transaction, &spent));
+    }
+
+    #[test]
+    fn finalizing_uses_only_the_threshold() {
+        let (keys, spent, mut partial) = two_of_three();
+        for private_key in &keys {
+            partial.sign(private_key);
+        }
+        let transaction = partial.finalize().unwrap();
+        assert_eq!(transaction.inputs[0].extra_signatures.len(), 1);
+        assert!(is_unlocked(&transaction, &spent));
+    }
+
+    #[test]
+    fn combining_copies_collects_their_signatures() {
+        let (keys, spent, mut partial) = two_of_three();
+        let mut other = partial.clone();
+        partial.sign(&keys[1]);
+        other.sign(&keys[2]);
+        partial.combine(&other).unwrap();
+        assert_eq!(partial.missing_signatures(), 0);
+        assert!(is_unlocked(&partial.finalize().unwrap(), &spent));
+    }
+
+    #[test]
+    fn combining_skips_signatures_that_do_not_verify() {
+        let (keys, _, mut partial) = two_of_three();
+        let mut other = partial.clone();
+        // a signature by the second key, claimed to be the first's
+        other.sign(&keys[1]);
+        let signature = other.inputs[0].signatures.remove(&1).unwrap();
+        other.inputs[0].signatures.insert(0, signature);
+        partial.combine(&other).unwrap();
+        assert_eq!(partial.missing_signatures(), 2);
+    }
+
+    #[test]
+    fn combining_a_different_transaction_fails() {
+        let (keys, _, mut partial) = two_of_three();
+        let mut other = partial.clone();
+        other.outputs[0].value = Amount::from_sat(1000);
+        other.sign(&keys[0]);
+        assert!(partial.combine(&other).is_err());
+        let mut other = partial.clone();
+        other.lock_time = 1;
+        assert!(partial.combine(&other).is_err());
+        assert_eq!(partial.missing_signatures(), 2);
+    }
+}
@@ -1,11 +1,26 @@
 use {
+    super::amount::Amount,
     crate::{
+        address::{
+            Address,
+            Network,
+            PubkeyHash,
+        },
         crypto::{
             PublicKey,
             Signature,
+            SignatureBatch,
         },
+        script::Script,
         sha256::Hash,
-        util::Saveable,
+        util::{
+            serialized_size,
+            Saveable,
+        },
+    },
+    chrono::{
+        DateTime,
+        Utc,
     },
     serde::Serialize,
     std::io::{
@@ -22,16 +37,145 @@ use {
 pub struct Transaction {
     pub inputs: Vec<TransactionInput>,
     pub outputs: Vec<TransactionOutput>,
+    /// Only set on the coinbase transaction, which has no inputs.
+    /// Left out of the encoding of every other transaction.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub coinbase: Option<CoinbaseInput>,
+    /// The transaction can only be mined after this block height,
+    /// or after this unix timestamp if at least
+    /// `LOCKTIME_THRESHOLD`, unless all of its inputs are final.
+    /// Left out of the encoding when zero.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub lock_time: u64,
 }
 
 impl Transaction {
     pub fn new(inputs: Vec<TransactionInput>, outputs: Vec<TransactionOutput>) -> Self {
-        Transaction { inputs, outputs }
+        Transaction {
+            inputs,
+            outputs,
+            coinbase: None,
+            lock_time: 0,
+        }
+    }
+
+    // coinbase transaction of the block at `height`, paying the
+    // block reward and fees to `outputs`
+    pub fn new_coinbase(height: u64, extra_data: Vec<u8>, outputs: Vec<TransactionOutput>) -> Self {
+        Transaction {
+            inputs: vec![],
+            outputs,
+            coinbase: Some(CoinbaseInput {
+                height,
+                extra_nonce: 0,
+                extra_data,
+            }),
+            lock_time: 0,
+        }
+    }
+
+    pub fn is_coinbase(&self) -> bool {
+        self.coinbase.is_some()
     }
 
     pub fn hash(&self) -> Hash {
         Hash::hash(self)
     }
+
+    // the hash the signatures of each input sign (see the
+    // `signature_hashes` function)
+    pub fn signature_hashes(&self) -> Vec<Hash> {
+        let inputs = self
+            .inputs
+            .iter()
+            .map(|input| (input.prev_tx_output_hash, input.sequence))
+            .collect::<Vec<_>>();
+        signature_hashes(&inputs, &self.outputs, self.lock_time)
+    }
+
+    // whether no signature of the inputs has a high s. Verifying
+    // rejects high s signatures anyway, this only lets the mempool
+    // tell them apart from invalid ones (`RejectReason::HighS`)
+    pub fn has_low_s_signatures(&self) -> bool {
+        self.inputs.iter().all(|input| {
+            std::iter::once(&input.signature)
+                .chain(input.extra_signatures.iter())
+                .all(Signature::is_low_s)
+        })
+    }
+
+    // replace high s signatures of the inputs by their low s form,
+    // which changes the hash
+    pub fn normalize_signatures(&mut self) {
+        for input in self.inputs.iter_mut() {
+            input.signature = input.signature.normalize_s();
+            for signature in input.extra_signatures.iter_mut() {
+                *signature = signature.normalize_s();
+            }
+        }
+    }
+
+    // size of the canonical encoding in bytes
+    pub fn size(&self) -> usize {
+        serialized_size(self)
+    }
+
+    // signatures are discounted like segwit witness data: every byte
+    // weighs WITNESS_SCALE_FACTOR units, except signature bytes,
+    // revealed public keys and unlocking scripts which weigh one
+    pub fn weight(&self) -> usize {
+        let signature_size = self
+            .inputs
+            .iter()
+            .map(|input| {
+                serialized_size(&input.signature)
+                    + if input.extra_signatures.is_empty() {
+                        0
+                    } else {
+                        serialized_size(&input.extra_signatures)
+                    }
+                    + input.pubkey.as_ref().map_or(0, serialized_size)
+                    + if input.unlock.is_empty() {
+                        0
+                    } else {
+                        serialized_size(&input.unlock)
+                    }
+            })
+            .sum::<usize>();
+        self.size() * crate::WITNESS_SCALE_FACTOR
+            - signature_size * (crate::WITNESS_SCALE_FACTOR - 1)
+    }
+
+    // weight in bytes, rounded up
+    pub fn vsize(&self) -> usize {
+        self.weight().div_ceil(crate::WITNESS_SCALE_FACTOR)
+    }
+
+    // a mempool transaction opts in to being replaced by fee if
+    // any of its inputs signals it
+    pub fn signals_replacement(&self) -> bool {
+        self.inputs
+            .iter()
+            .any(|input| input.sequence < crate::SEQUENCE_RBF_THRESHOLD)
+    }
+
+    // whether the lock time allows the transaction in the block at
+    // `height`, whose predecessors have the median time past `time`
+    pub fn is_final(&self, height: u64, time: DateTime<Utc>) -> bool {
+        if self.lock_time == 0 {
+            return true;
+        }
+        let passed = if self.lock_time < crate::LOCKTIME_THRESHOLD {
+            self.lock_time < height
+        } else {
+            i64::try_from(self.lock_time).is_ok_and(|lock_time| lock_time < time.timestamp())
+        };
+        passed
+            || self
+                .inputs
+                .iter()
+                .all(|input| input.sequence == crate::SEQUENCE_FINAL)
+    }
 }
 
 impl Saveable for Transaction {
@@ -50,20 +194,568 @@ impl Saveable for Transaction {
     }
 }
 
+/// Input of a coinbase transaction, which creates the block reward
+/// instead of spending outputs
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CoinbaseInput {
+    /// Height of the block, which keeps coinbase transactions of
+    /// different blocks from ever having the same hash
+    pub height: u64,
+    /// Changed by miners to get a fresh merkle root once they ran
+    /// through the header nonces
+    pub extra_nonce: u64,
+    /// Arbitrary data chosen by the miner, at most
+    /// `MAX_COINBASE_EXTRA_DATA` bytes
+    pub extra_data: Vec<u8>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TransactionInput {
     pub prev_tx_output_hash: Hash,
     pub signature: Signature,
+    /// Sequence number, anything below `SEQUENCE_RBF_THRESHOLD`
+    /// allows the transaction to be replaced by fee in the mempool.
+    /// Without `SEQUENCE_LOCKTIME_DISABLE_FLAG` it also holds a
+    /// relative lock (see `relative_lock`).
+    /// Final sequence numbers are left out of the encoding so that
+    /// transactions created before the field existed keep their hash.
+    #[serde(default = "sequence_final", skip_serializing_if = "is_final")]
+    pub sequence: u32,
+    /// Public key of the spent output's `Lock::PubkeyHash`, left out
+    /// of the encoding for other locks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pubkey: Option<PublicKey>,
+    /// Unlocking script of the spent output's `Lock::Script`, pushing
+    /// what the script needs on top of the signature. Left out of
+    /// the encoding when empty
+    #[serde(default, skip_serializing_if = "Script::is_empty")]
+    pub unlock: Script,
+    /// Signatures of a `Lock::Multisig` spend after `signature`, in
+    /// the order of their keys. Left out of the encoding when empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub extra_signatures: Vec<Signature>,
+}
+
+impl TransactionInput {
+    // the relative lock encoded in the sequence number, None if
+    // the disable flag is set. Unlike BIP68 there is no transaction
+    // version to opt in: relative locks apply to every transaction.
+    // Inputs created before sequence numbers existed are final,
+    // which has the flag set, so they hold none
+    pub fn relative_lock(&self) -> Option<RelativeLock> {
+        RelativeLock::from_sequence(self.sequence)
+    }
+}
+
+/// How long an input has to wait after the output it spends was
+/// confirmed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelativeLock {
+    /// Amount of blocks
+    Blocks(u64),
+    /// Seconds of median time past
+    Seconds(u64),
+}
+
+impl RelativeLock {
+    // the relative lock encoded like in input sequence numbers,
+    // None if the disable flag is set
+    pub fn from_sequence(sequence: u32) -> Option<Self> {
+        if sequence & crate::SEQUENCE_LOCKTIME_DISABLE_FLAG != 0 {
+            return None;
+        }
+        let value = (sequence & crate::SEQUENCE_LOCKTIME_MASK) as u64;
+        if sequence & crate::SEQUENCE_LOCKTIME_TYPE_FLAG != 0 {
+            Some(RelativeLock::Seconds(
+                value << crate::SEQUENCE_LOCKTIME_GRANULARITY,
+            ))
+        } else {
+            Some(RelativeLock::Blocks(value))
+        }
+    }
+
+    // whether an output confirmed in the block at `prev_height`,
+    // whose predecessors have the median time past `prev_time`,
+    // can be spent in the block at `height` with `time`
+    pub fn is_satisfied(
+        &self,
+        prev_height: u64,
+        prev_time: DateTime<Utc>,
+        height: u64,
+        time: DateTime<Utc>,
+    ) -> bool {
+        match *self {
+            RelativeLock::Blocks(blocks) => height >= prev_height.saturating_add(blocks),
+            RelativeLock::Seconds(seconds) => {
+                time.timestamp() >= prev_time.timestamp().saturating_add(seconds as i64)
+            }
+        }
+    }
+}
+
+// what the signatures of a transaction's inputs sign: the spent
+// outputs and sequence numbers of all inputs, all outputs and the
+// lock time, so that nothing but the signatures themselves can be
+// changed without them. The index of the input keeps a signature
+// from being reused for another input spending an output of the
+// same key. Inputs and outputs are hashed once for all inputs, which
+// keeps checking a transaction linear in its size
+pub fn signature_hashes(
+    inputs: &[(Hash, u32)],
+    outputs: &[TransactionOutput],
+    lock_time: u64,
+) -> Vec<Hash> {
+    let inputs_hash = Hash::hash(&inputs);
+    let outputs_hash = Hash::hash(&outputs);
+    (0..inputs.len())
+        .map(|index| Hash::hash(&(index, inputs_hash, outputs_hash, lock_time)))
+        .collect()
+}
+
+fn sequence_final() -> u32 {
+    crate::SEQUENCE_FINAL
+}
+
+fn is_final(sequence: &u32) -> bool {
+    *sequence == crate::SEQUENCE_FINAL
+}
+
+fn is_zero(lock_time: &u64) -> bool {
+    *lock_time == 0
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TransactionOutput {
-    pub value: u64,
+    pub value: Amount,
     pub unique_id: Uuid,
-    pub pubkey: PublicKey,
+    pub lock: Lock,
 }
 impl TransactionOutput {
     pub fn hash(&self) -> Hash {
         Hash::hash(self)
     }
 }
+
+/// Who can spend an output
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Lock {
+    /// Anyone with a signature by the public key, which is public
+    /// from the moment the output is created
+    PublicKey(PublicKey),
+    /// Anyone revealing a public key with this hash, and a signature
+    /// by it. This is what paying an address creates
+    PubkeyHash(PubkeyHash),
+    /// Anyone whose unlocking script makes the script succeed (see
+    /// `Script::verify`)
+    Script(Script),
+    /// Anyone with signatures by `threshold` of the public keys
+    Multisig {
+        threshold: usize,
+        pubkeys: Vec<PublicKey>,
+    },
+}
+
+impl Lock {
+    // a lock to `threshold` of `pubkeys`, None unless the threshold
+    // is between 1 and the amount of keys, there are at most
+    // MAX_MULTISIG_KEYS keys and no key is there twice
+    pub fn multisig(threshold: usize, pubkeys: Vec<PublicKey>) -> Option<Self> {
+        is_valid_multisig(threshold, &pubkeys).then_some(Lock::Multisig { threshold, pubkeys })
+    }
+
+    // whether this is a multisig lock that `Lock::multisig` would
+    // have created
+    pub fn is_valid_multisig(&self) -> bool {
+        match self {
+            Lock::Multisig { threshold, pubkeys } => is_valid_multisig(*threshold, pubkeys),
+            _ => false,
+        }
+    }
+
+    // hash of the key the output is locked to, None for scripts and
+    // multisig
+    pub fn pubkey_hash(&self) -> Option<PubkeyHash> {
+        match self {
+            Lock::PublicKey(pubkey) => Some(pubkey.pubkey_hash()),
+            Lock::PubkeyHash(pubkey_hash) => Some(*pubkey_hash),
+            Lock::Script(_) | Lock::Multisig { .. } => None,
+        }
+    }
+
+    // address of the key the output is locked to, None for scripts
+    // and multisig
+    pub fn address(&self, network: Network) -> Option<Address> {
+        self.pubkey_hash()
+            .map(|pubkey_hash| Address::new(network, pubkey_hash))
+    }
+
+    // whether the input of `transaction` carries what the lock asks
+    // for, and nothing else. Signatures have to sign the input's
+    // `signature_hash` (see `Transaction::signature_hashes`)
+    pub fn is_unlocked_by(
+        &self,
+        transaction: &Transaction,
+        input: &TransactionInput,
+        signature_hash: &Hash,
+    ) -> bool {
+        let mut batch = SignatureBatch::new();
+        self.is_unlocked_by_batched(transaction, input, signature_hash, &mut batch)
+            && batch.verify()
+    }
+
+    // like `is_unlocked_by`, but leaves the Schnorr signature of a
+    // single key lock to be verified with the rest of `batch`
+    pub fn is_unlocked_by_batched(
+        &self,
+        transaction: &Transaction,
+        input: &TransactionInput,
+        signature_hash: &Hash,
+        batch: &mut SignatureBatch,
+    ) -> bool {
+        if let Lock::Multisig { threshold, pubkeys } = self {
+            return input.pubkey.is_none()
+                && input.unlock.is_empty()
+                && is_multisig_unlocked(*threshold, pubkeys, input, signature_hash);
+        }
+        if !input.extra_signatures.is_empty() {
+            return false;
+        }
+        let pubkey = match self {
+            Lock::PublicKey(pubkey) if input.pubkey.is_none() => pubkey,
+            Lock::PubkeyHash(pubkey_hash) => {
+                match &input.pubkey {
+                    Some(pubkey) if pubkey.pubkey_hash() == *pubkey_hash => pubkey,
+                    _ => return false,
+                }
+            }
+            Lock::Script(script) if input.pubkey.is_none() => {
+                return script.verify(transaction, input, signature_hash).is_ok();
+            }
+            _ => return false,
+        };
+        input.unlock.is_empty() && batch.check(&input.signature, signature_hash, pubkey)
+    }
+}
+
+// a key listed twice would let its holder count twice towards the
+// threshold, so such locks cannot be unlocked
+fn is_valid_multisig(threshold: usize, pubkeys: &[PublicKey]) -> bool {
+    threshold != 0
+        && threshold <= pubkeys.len()
+        && pubkeys.len() <= crate::MAX_MULTISIG_KEYS
+        && pubkeys
+            .iter()
+            .enumerate()
+            .all(|(i, pubkey)| !pubkeys[..i].contains(pubkey))
+}
+
+// exactly `threshold` signatures, each by a different key and in
+// the order of the keys
+fn is_multisig_unlocked(
+    threshold: usize,
+    pubkeys: &[PublicKey],
+    input: &TransactionInput,
+    signature_hash: &Hash,
+) -> bool {
+    if !is_valid_multisig(threshold, pubkeys) || input.extra_signatures.len() + 1 != threshold {
+        return false;
+    }
+    let mut pubkeys = pubkeys.iter();
+    std::iter::once(&input.signature)
+        .chain(input.extra_signatures.iter())
+        .all(|signature| {
+            pubkeys
+                .by_ref()
+                .any(|pubkey| signature.verify(signature_hash, pubkey))
+        })
+}
+
+impl From<PublicKey> for Lock {
+    fn from(pubkey: PublicKey) -> Self {
+        Lock::PublicKey(pubkey)
+    }
+}
+
+impl From<Address> for Lock {
+    fn from(address: Address) -> Self {
+        Lock::PubkeyHash(address.pubkey_hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::crypto::PrivateKey,
+    };
+
+    fn output(value: u64, private_key: &PrivateKey) -> TransactionOutput {
+        TransactionOutput {
+            value: Amount::from_sat(value),
+            unique_id: Uuid::new_v4(),
+            lock: private_key.public_key().into(),
+        }
+    }
+
+    // a signed transaction spending outputs of `private_key`
+    fn spend(private_key: &PrivateKey, spent: &[TransactionOutput]) -> Transaction {
+        let inputs = spent
+            .iter()
+            .map(|output| {
+                TransactionInput {
+                    prev_tx_output_hash: output.hash(),
+                    signature: Signature::sign(&output.hash(), private_key),
+                    sequence: crate::SEQUENCE_FINAL,
+                    pubkey: None,
+                    unlock: Script::default(),
+                    extra_signatures: vec![],
+                }
+            })
+            .collect();
+        let mut transaction = Transaction::new(inputs, vec![output(1500, private_key)]);
+        sign(&mut transaction, private_key);
+        transaction
+    }
+
+    fn sign(transaction: &mut Transaction, private_key: &PrivateKey) {
+        let signature_hashes = transaction.signature_hashes();
+        for (input, signature_hash) in transaction.inputs.iter_mut().zip(signature_hashes) {
+            input.signature = Signature::sign(&signature_hash, private_key);
+        }
+    }
+
+    fn is_unlocked(transaction: &Transaction, spent: &[TransactionOutput]) -> bool {
+        let signature_hashes = transaction.signature_hashes();
+        spent
+            .iter()
+            .zip(transaction.inputs.iter())
+            .zip(signature_hashes.iter())
+            .all(|((output, input), signature_hash)| {
+                output
+                    .lock
+                    .is_unlocked_by(transaction, input, signature_hash)
+            })
+    }
+
+    // whether `signers`, in this order, unlock an output locked by
+    // `lock`
+    fn is_multisig_unlocked_by(lock: Lock, signers: &[&PrivateKey]) -> bool {
+        let spent = TransactionOutput {
+            value: Amount::from_sat(1000),
+            unique_id: Uuid::new_v4(),
+            lock,
+        };
+        let input = TransactionInput {
+            prev_tx_output_hash: spent.hash(),
+            signature: Signature::sign(&spent.hash(), signers[0]),
+            sequence: crate::SEQUENCE_FINAL,
+            pubkey: None,
+            unlock: Script::default(),
+            extra_signatures: vec![],
+        };
+        let mut transaction = Transaction::new(vec![input], vec![output(900, signers[0])]);
+        let signature_hash = transaction.signature_hashes()[0];
+        let mut signatures = signers
+            .iter()
+            .map(|private_key| Signature::sign(&signature_hash, private_key));
+        transaction.inputs[0].signature = signatures.next().unwrap();
+        transaction.inputs[0].extra_signatures = signatures.collect();
+        is_unlocked(&transaction, &[spent])
+    }
+
+    #[test]
+    fn multisig_needs_exactly_the_threshold() {
+        let keys = [
+            PrivateKey::new_key(),
+            PrivateKey::new_key(),
+            PrivateKey::new_key(),
+        ];
+        let [a, b, c] = &keys;
+        let pubkeys = keys.iter().map(PrivateKey::public_key).collect();
+        let lock = Lock::multisig(2, pubkeys).unwrap();
+        assert!(is_multisig_unlocked_by(lock.clone(), &[a, b]));
+        assert!(is_multisig_unlocked_by(lock.clone(), &[a, c]));
+        assert!(is_multisig_unlocked_by(lock.clone(), &[b, c]));
+        assert!(!is_multisig_unlocked_by(lock.clone(), &[a]));
+        assert!(!is_multisig_unlocked_by(lock.clone(), &[a, b, c]));
+        assert!(!is_multisig_unlocked_by(lock.clone(), &[a, a]));
+        let stranger = PrivateKey::new_key();
+        assert!(!is_multisig_unlocked_by(lock, &[a, &stranger]));
+    }
+
+    #[test]
+    fn multisig_signatures_follow_the_key_order() {
+        let keys = [PrivateKey::new_key(), PrivateKey::new_key()];
+        let [a, b] = &keys;
+        let pubkeys = keys.iter().map(PrivateKey::public_key).collect();
+        let lock = Lock::multisig(2, pubkeys).unwrap();
+        assert!(is_multisig_unlocked_by(lock.clone(), &[a, b]));
+        assert!(!is_multisig_unlocked_by(lock, &[b, a]));
+    }
+
+    #[test]
+    fn multisig_keys_have_to_be_distinct() {
+        let private_key = PrivateKey::new_key();
+        let other = PrivateKey::new_key();
+        let pubkeys = vec![private_key.public_key(), private_key.public_key()];
+        assert_eq!(Lock::multisig(2, pubkeys.clone()), None);
+        assert_eq!(Lock::multisig(0, vec![other.public_key()]), None);
+        assert_eq!(Lock::multisig(2, vec![other.public_key()]), None);
+
+        // built without `Lock::multisig`, the lock still cannot be
+        // spent by the repeated key alone
+        let lock = Lock::Multisig {
+            threshold: 2,
+            pubkeys,
+        };
+        assert!(!lock.is_valid_multisig());
+        assert!(!is_multisig_unlocked_by(
+            lock,
+            &[&private_key, &private_key]
+        ));
+    }
+
+    #[test]
+    fn signatures_commit_to_the_transaction() {
+        let private_key = PrivateKey::new_key();
+        let spent = [output(1000, &private_key), output(1000, &private_key)];
+        let transaction = spend(&private_key, &spent);
+        assert!(is_unlocked(&transaction, &spent));
+
+        let mut changed = transaction.clone();
+        changed.outputs[0].value = Amount::from_sat(2000);
+        assert!(!is_unlocked(&changed, &spent));
+        let mut changed = transaction.clone();
+        changed.outputs[0].lock = PrivateKey::new_key().public_key().into();
+        assert!(!is_unlocked(&changed, &spent));
+        let mut changed = transaction.clone();
+        changed.lock_time = 1;
+        assert!(!is_unlocked(&changed, &spent));
+        let mut changed = transaction.clone();
+        changed.inputs[1].sequence = 0;
+        assert!(!is_unlocked(&changed, &spent));
+    }
+
+    #[test]
+    fn only_sequences_without_the_disable_flag_lock() {
+        assert_eq!(RelativeLock::from_sequence(crate::SEQUENCE_FINAL), None);
+        assert_eq!(
+            RelativeLock::from_sequence(crate::SEQUENCE_RBF_THRESHOLD),
+            None
+        );
+        assert_eq!(
+            RelativeLock::from_sequence(0),
+            Some(RelativeLock::Blocks(0))
+        );
+        assert_eq!(
+            RelativeLock::from_sequence(crate::SEQUENCE_LOCKTIME_TYPE_FLAG | 2),
+            Some(RelativeLock::Seconds(
+                2 << crate::SEQUENCE_LOCKTIME_GRANULARITY
+            ))
+        );
+    }
+
+    #[test]
+    fn signatures_are_bound_to_their_input() {
+        let private_key = PrivateKey::new_key();
+        let spent = [output(1000, &private_key), output(1000, &private_key)];
+        let mut transaction = spend(&private_key, &spent);
+        let signature = transaction.inputs[0].signature.clone();
+        transaction.inputs[1].signature = signature;
+        assert!(!is_unlocked(&transaction, &spent));
+    }
+
+    #[test]
+    fn pubkey_hash_locks_need_the_key_and_its_signature() {
+        let private_key = PrivateKey::new_key();
+        let other = PrivateKey::new_key();
+        let spent = TransactionOutput {
+            value: Amount::from_sat(1000),
+            unique_id: Uuid::new_v4(),
+            lock: Lock::PubkeyHash(private_key.public_key().pubkey_hash()),
+        };
+        // spent by `signer`, revealing `pubkey`
+        let is_unlocked_with = |signer: &PrivateKey, pubkey: Option<PublicKey>| {
+            let mut transaction = spend(signer, std::slice::from_ref(&spent));
+            transaction.inputs[0].pubkey = pubkey;
+            sign(&mut transaction, signer);
+            is_unlocked(&transaction, std::slice::from_ref(&spent))
+        };
+        assert!(is_unlocked_with(
+            &private_key,
+            Some(private_key.public_key())
+        ));
+        assert!(!is_unlocked_with(&other, Some(other.public_key())));
+        assert!(!is_unlocked_with(&other, Some(private_key.public_key())));
+        assert!(!is_unlocked_with(&private_key, Some(other.public_key())));
+        assert!(!is_unlocked_with(&private_key, None));
+
+        // keys are only revealed for pubkey hash locks
+        let spent = [output(1000, &private_key)];
+        let mut transaction = spend(&private_key, &spent);
+        assert!(is_unlocked(&transaction, &spent));
+        transaction.inputs[0].pubkey = Some(private_key.public_key());
+        assert!(!is_unlocked(&transaction, &spent));
+    }
+
+    #[test]
+    fn lock_times_are_heights_or_times() {
+        let private_key = PrivateKey::new_key();
+        let mut transaction = spend(&private_key, &[output(1000, &private_key)]);
+        transaction.inputs[0].sequence = 0;
+        let time = Utc::now();
+        assert!(transaction.is_final(0, time));
+
+        // a height has to be below the block's
+        transaction.lock_time = 100;
+        assert!(!transaction.is_final(99, time));
+        assert!(!transaction.is_final(100, time));
+        assert!(transaction.is_final(101, time));
+
+        // a time below the median time past of the blocks before
+        let lock_time = crate::LOCKTIME_THRESHOLD + 1000;
+        transaction.lock_time = lock_time;
+        let at = |seconds: u64| DateTime::from_timestamp(seconds as i64, 0).unwrap();
+        assert!(!transaction.is_final(u64::MAX, at(lock_time)));
+        assert!(transaction.is_final(0, at(lock_time + 1)));
+
+        // unless all inputs are final
+        transaction.inputs[0].sequence = crate::SEQUENCE_FINAL;
+        assert!(transaction.is_final(0, at(lock_time)));
+        transaction.lock_time = 100;
+        assert!(transaction.is_final(0, time));
+    }
+
+    #[test]
+    fn signatures_weigh_a_quarter() {
+        let coinbase = Transaction::new_coinbase(0, vec![], vec![]);
+        assert_eq!(
+            coinbase.weight(),
+            coinbase.size() * crate::WITNESS_SCALE_FACTOR
+        );
+        assert_eq!(coinbase.vsize(), coinbase.size());
+
+        let private_key = PrivateKey::new_key();
+        let mut transaction = spend(&private_key, &[output(1000, &private_key)]);
+        let signature_size = serialized_size(&transaction.inputs[0].signature);
+        assert_eq!(
+            transaction.weight(),
+            transaction.size() * crate::WITNESS_SCALE_FACTOR
+                - signature_size * (crate::WITNESS_SCALE_FACTOR - 1)
+        );
+        // so are revealed keys
+        let pubkey = private_key.public_key();
+        let pubkey_size = serialized_size(&pubkey);
+        transaction.inputs[0].pubkey = Some(pubkey);
+        assert_eq!(
+            transaction.weight(),
+            transaction.size() * crate::WITNESS_SCALE_FACTOR
+                - (signature_size + pubkey_size) * (crate::WITNESS_SCALE_FACTOR - 1)
+        );
+        assert_eq!(
+            transaction.vsize(),
+            transaction.weight().div_ceil(crate::WITNESS_SCALE_FACTOR)
+        );
+        assert!(transaction.vsize() < transaction.size());
+    }
+}
@@ -0,0 +1,92 @@
+use {
+    super::{
+        amount::Amount,
+        transaction::TransactionOutput,
+    },
+    chrono::{
+        DateTime,
+        Utc,
+    },
+};
+
+/// An unspent transaction output together with the block
+/// that created it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Utxo {
+    /// Whether a mempool transaction spends the output
+    pub marked: bool,
+    pub output: TransactionOutput,
+    /// Height of the block containing the transaction
+    pub height: u64,
+    /// Median time past of the blocks before that block, which
+    /// relative time locks count from
+    pub median_time_past: DateTime<Utc>,
+    /// Whether the output pays a block reward
+    pub coinbase: bool,
+}
+
+impl Utxo {
+    pub fn new(
+        output: TransactionOutput,
+        height: u64,
+        median_time_past: DateTime<Utc>,
+        coinbase: bool,
+    ) -> Self {
+        Utxo {
+            marked: false,
+            output,
+            height,
+            median_time_past,
+            coinbase,
+        }
+    }
+
+    // whether the output may be spent by a transaction in the
+    // block at `height`. Coinbase outputs have to wait
+    // COINBASE_MATURITY blocks, so that a reorg dropping their block
+    // cannot invalidate the transactions spending them
+    pub fn is_mature(&self, height: u64) -> bool {
+        !self.coinbase || height >= self.height + crate::COINBASE_MATURITY
+    }
+}
+
+/// Amounts in satoshis owned by a public key
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Balance {
+    /// Confirmed outputs that can be spent right away
+    pub spendable: Amount,
+    /// Coinbase outputs still waiting for `COINBASE_MATURITY`
+    /// confirmations
+    pub immature: Amount,
+    /// Outputs of mempool transactions
+    pub unconfirmed: Amount,
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::crypto::PrivateKey,
+        uuid::Uuid,
+    };
+
+    fn utxo(height: u64, coinbase: bool) -> Utxo {
+        let output = TransactionOutput {
+            value: Amount::from_sat(1000),
+            unique_id: Uuid::new_v4(),
+            lock: PrivateKey::new_key().public_key().into(),
+        };
+        Utxo::new(output, height, Utc::now(), coinbase)
+    }
+
+    #[test]
+    fn coinbase_outputs_mature_after_100_blocks() {
+        let coinbase = utxo(10, true);
+        assert!(!coinbase.is_mature(10));
+        assert!(!coinbase.is_mature(10 + crate::COINBASE_MATURITY - 1));
+        assert!(coinbase.is_mature(10 + crate::COINBASE_MATURITY));
+        let output = utxo(10, false);
+        assert!(output.is_mature(10));
+        assert!(output.is_mature(11));
+    }
+}
@@ -20,23 +20,268 @@ pub struct MerkleRoot(Hash);
 impl MerkleRoot {
     // calculature the merkle root of if a block's transaction
     pub fn calculate(transactions: &[Transaction]) -> MerkleRoot {
-        let mut layer: Vec<Hash> = vec![];
-        for transaction in transactions {
-            layer.push(Hash::hash(transaction));
+        Self::calculate_checked(transactions).0
+    }
+
+    // the merkle root and whether the transaction list is mutated.
+    // Odd layers pair their last hash with itself, so a list ending
+    // in repeated transactions has the same root as the list without
+    // the repetition (CVE-2012-2459). Such lists are the only ones
+    // with two equal siblings in their tree
+    pub fn calculate_checked(transactions: &[Transaction]) -> (MerkleRoot, bool) {
+        let mut layer = transactions
+            .iter()
+            .map(Transaction::hash)
+            .collect::<Vec<_>>();
+        let mut mutated = false;
+        while layer.len() > 1 {
+            let (next_layer, layer_mutated) = merkle_layer(&layer);
+            layer = next_layer;
+            mutated |= layer_mutated;
+        }
+        (
+            MerkleRoot(layer.first().copied().unwrap_or(Hash::zero())),
+            mutated,
+        )
+    }
+
+    pub fn hash(&self) -> Hash {
+        self.0
+    }
+}
+
+fn merkle_node(left: Hash, right: Hash) -> Hash {
+    Hash::hash(&[left, right])
+}
+
+// the layer above `layer` and whether two siblings in it are equal
+fn merkle_layer(layer: &[Hash]) -> (Vec<Hash>, bool) {
+    let mut mutated = false;
+    let next_layer = layer
+        .chunks(2)
+        .map(|pair| {
+            let left = pair[0];
+            let right = pair.get(1).copied().unwrap_or(left);
+            mutated |= pair.len() == 2 && left == right;
+            merkle_node(left, right)
+        })
+        .collect();
+    (next_layer, mutated)
+}
+
+/// Proof that a transaction is part of a block, checked against the
+/// merkle root in the block header
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct MerkleProof {
+    /// Position of the transaction in the block
+    pub index: usize,
+    /// Amount of transactions in the block, which fixes the shape of
+    /// the tree
+    pub transaction_count: usize,
+    /// Hashes of the siblings on the path to the root, bottom up.
+    /// Nodes paired with themselves at the end of an odd layer have
+    /// none
+    pub siblings: Vec<Hash>,
+}
+
+impl MerkleProof {
+    // proof for the transaction at `index`, None if there is none
+    pub fn new(transactions: &[Transaction], index: usize) -> Option<Self> {
+        if index >= transactions.len() {
+            return None;
         }
+        let mut layer = transactions
+            .iter()
+            .map(Transaction::hash)
+            .collect::<Vec<_>>();
+        let mut position = index;
+        let mut siblings = vec![];
         while layer.len() > 1 {
-            let mut new_layer = vec![];
-            for pair in layer.chunks(2) {
-                let left = pair[0];
-                let right = pair.get(1).unwrap_or(&pair[0]);
-                new_layer.push(Hash::hash(&[left, *right]));
+            if let Some(sibling) = layer.get(position ^ 1) {
+                siblings.push(*sibling);
+            }
+            layer = merkle_layer(&layer).0;
+            position /= 2;
+        }
+        Some(MerkleProof {
+            index,
+            transaction_count: transactions.len(),
+            siblings,
+        })
+    }
+
+    // whether the transaction with `transaction_hash` is at `index`
+    // in a block with `merkle_root`
+    pub fn verify(&self, transaction_hash: &Hash, merkle_root: &MerkleRoot) -> bool {
+        if self.index >= self.transaction_count {
+            return false;
+        }
+        let mut hash = *transaction_hash;
+        let mut position = self.index;
+        let mut width = self.transaction_count;
+        let mut siblings = self.siblings.iter();
+        while width > 1 {
+            hash = if position ^ 1 >= width {
+                merkle_node(hash, hash)
+            } else {
+                let Some(&sibling) = siblings.next() else {
+                    return false;
+                };
+                // equal siblings only occur in mutated trees
+                if sibling == hash {
+                    return false;
+                }
+                if position & 1 == 0 {
+                    merkle_node(hash, sibling)
+                } else {
+                    merkle_node(sibling, hash)
+                }
+            };
+            position /= 2;
+            width = width.div_ceil(2);
+        }
+        siblings.next().is_none() && hash == merkle_root.0
+    }
+}
+
+/// Proof that several transactions are part of a block, in BIP37's
+/// partial merkle tree format. The tree is walked depth first with
+/// one flag per visited node, telling whether a matched transaction
+/// is below it. Nodes without one and matched transactions are given
+/// by their hash, the others are descended into
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PartialMerkleTree {
+    /// Amount of transactions in the block
+    pub transaction_count: usize,
+    pub hashes: Vec<Hash>,
+    pub flags: Vec<bool>,
+}
+
+impl PartialMerkleTree {
+    // tree proving the transactions `is_match` returns true for
+    pub fn new(transactions: &[Transaction], is_match: impl Fn(&Transaction) -> bool) -> Self {
+        let leaves = transactions
+            .iter()
+            .map(Transaction::hash)
+            .collect::<Vec<_>>();
+        let matches = transactions.iter().map(is_match).collect::<Vec<_>>();
+        let mut tree = PartialMerkleTree {
+            transaction_count: transactions.len(),
+            hashes: vec![],
+            flags: vec![],
+        };
+        if !leaves.is_empty() {
+            tree.build(tree.height(), 0, &leaves, &matches);
+        }
+        tree
+    }
+
+    // the merkle root and the matched transactions' positions and
+    // hashes, None if the tree is malformed or mutated
+    pub fn extract_matches(&self) -> Option<(MerkleRoot, Vec<(usize, Hash)>)> {
+        // the count bounds the height of the tree
+        if self.transaction_count == 0
+            || self.transaction_count > u32::MAX as usize
+            || self.hashes.len() > self.transaction_count
+        {
+            return None;
+        }
+        let mut hashes = self.hashes.iter();
+        let mut flags = self.flags.iter();
+        let mut matches = vec![];
+        let root = self.extract(self.height(), 0, &mut hashes, &mut flags, &mut matches)?;
+        // every hash and flag has to be used
+        if hashes.next().is_some() || flags.next().is_some() {
+            return None;
+        }
+        Some((MerkleRoot(root), matches))
+    }
+
+    // amount of nodes at `height`, leaves being at height 0
+    fn width(&self, height: u32) -> usize {
+        ((self.transaction_count - 1) >> height) + 1
+    }
+
+    fn height(&self) -> u32 {
+        let mut height = 0;
+        while self.width(height) > 1 {
+            height += 1;
+        }
+        height
+    }
+
+    fn node_hash(&self, height: u32, position: usize, leaves: &[Hash]) -> Hash {
+        if height == 0 {
+            return leaves[position];
+        }
+        let left = self.node_hash(height - 1, position * 2, leaves);
+        let right = if position * 2 + 1 < self.width(height - 1) {
+            self.node_hash(height - 1, position * 2 + 1, leaves)
+        } else {
+            left
+        };
+        merkle_node(left, right)
+    }
+
+    fn build(&mut self, height: u32, position: usize, leaves: &[Hash], matches: &[bool]) {
+        let first = position << height;
+        let last = ((position + 1) << height).min(leaves.len());
+        let is_parent_of_match = matches[first..last].iter().any(|is_match| *is_match);
+        self.flags.push(is_parent_of_match);
+        if height == 0 || !is_parent_of_match {
+            self.hashes.push(self.node_hash(height, position, leaves));
+        } else {
+            self.build(height - 1, position * 2, leaves, matches);
+            if position * 2 + 1 < self.width(height - 1) {
+                self.build(height - 1, position * 2 + 1, leaves, matches);
+            }
+        }
+    }
+
+    fn extract<'a>(
+        &self,
+        height: u32,
+        position: usize,
+        hashes: &mut impl Iterator<Item = &'a Hash>,
+        flags: &mut impl Iterator<Item = &'a bool>,
+        matches: &mut Vec<(usize, Hash)>,
+    ) -> Option<Hash> {
+        let is_parent_of_match = *flags.next()?;
+        if height == 0 || !is_parent_of_match {
+            let hash = *hashes.next()?;
+            if height == 0 && is_parent_of_match {
+                matches.push((position, hash));
             }
-            layer = new_layer;
+            return Some(hash);
         }
-        MerkleRoot(layer[0])
+        let left = self.extract(height - 1, position * 2, hashes, flags, matches)?;
+        let right = if position * 2 + 1 < self.width(height - 1) {
+            let right = self.extract(height - 1, position * 2 + 1, hashes, flags, matches)?;
+            // equal siblings only occur in mutated trees
+            if right == left {
+                return None;
+            }
+            right
+        } else {
+            left
+        };
+        Some(merkle_node(left, right))
     }
 }
 
+// size of the canonical (CBOR) encoding of a value in bytes
+pub fn serialized_size<T: serde::Serialize>(data: &T) -> usize {
+    let mut bytes = vec![];
+    if let Err(e) = ciborium::into_writer(data, &mut bytes) {
+        panic!(
+            "Failed to serialize data: {:?}. \
+        This should not happen",
+            e
+        );
+    }
+    bytes.len()
+}
+
 pub trait Saveable
 where
     Self: Sized,
@@ -52,3 +297,66 @@ where
         Self::load(file)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transactions(count: u64) -> Vec<Transaction> {
+        (0..count)
+            .map(|height| Transaction::new_coinbase(height, vec![], vec![]))
+            .collect()
+    }
+
+    // CVE-2012-2459: repeating the last transactions of a list with
+    // an odd layer keeps the merkle root
+    #[test]
+    fn duplicated_leaves_are_mutated() {
+        let original = transactions(3);
+        let mut duplicated = original.clone();
+        duplicated.push(original[2].clone());
+        let (root, mutated) = MerkleRoot::calculate_checked(&original);
+        assert!(!mutated);
+        assert_eq!(
+            MerkleRoot::calculate_checked(&duplicated),
+            (root.clone(), true)
+        );
+
+        let original = transactions(6);
+        let mut duplicated = original.clone();
+        duplicated.extend_from_slice(&original[4..]);
+        let (root, mutated) = MerkleRoot::calculate_checked(&original);
+        assert!(!mutated);
+        assert_eq!(MerkleRoot::calculate_checked(&duplicated), (root, true));
+    }
+
+    #[test]
+    fn proofs_of_duplicated_leaves_are_rejected() {
+        let original = transactions(3);
+        let mut duplicated = original.clone();
+        duplicated.push(original[2].clone());
+        let root = MerkleRoot::calculate(&original);
+        let proof = MerkleProof::new(&duplicated, 3).unwrap();
+        assert!(!proof.verify(&original[2].hash(), &root));
+        let tree = PartialMerkleTree::new(&duplicated, |_| true);
+        assert!(tree.extract_matches().is_none());
+    }
+
+    #[test]
+    fn proofs_round_trip() {
+        for count in 1..=9 {
+            let transactions = transactions(count);
+            let root = MerkleRoot::calculate(&transactions);
+            for (index, transaction) in transactions.iter().enumerate() {
+                let proof = MerkleProof::new(&transactions, index).unwrap();
+                assert!(proof.verify(&transaction.hash(), &root));
+            }
+            let tree = PartialMerkleTree::new(&transactions, |transaction| {
+                transaction.coinbase.as_ref().unwrap().height % 2 == 0
+            });
+            let (extracted_root, matches) = tree.extract_matches().unwrap();
+            assert_eq!(extracted_root, root);
+            assert_eq!(matches.len(), count.div_ceil(2) as usize);
+        }
+    }
+}
@@ -0,0 +1,170 @@
+use {
+    anyhow::{
+        anyhow,
+        Result,
+    },
+    btclib::{
+        crypto::PrivateKey,
+        sha256::Hash,
+        types::{
+            amount::Amount,
+            block::Block,
+            block_header::BlockHeader,
+            transaction::{
+                Transaction,
+                TransactionOutput,
+            },
+        },
+        util::MerkleRoot,
+        U256,
+    },
+    chrono::Utc,
+    std::{
+        thread,
+        time::{
+            Duration,
+            Instant,
+        },
+    },
+    uuid::Uuid,
+};
+
+// amount of nonces tried between two deadline checks
+const BATCH_SIZE: usize = 10_000;
+
+#[derive(Debug, Default)]
+struct ThreadReport {
+    hashes: u64,
+    solutions: u64,
+    invalid_solutions: u64,
+    elapsed: Duration,
+}
+
+// build a genesis-like block the same way block_gen does
+fn synthetic_block(target: U256) -> Block {
+    let private_key = PrivateKey::new_key();
+    let transactions = vec![Transaction::new_coinbase(
+        0,
+        vec![],
+        vec![TransactionOutput {
+            unique_id: Uuid::new_v4(),
+            value: Amount::from_btc(btclib::INITIAL_REWARD),
+            lock: private_key.public_key().into(),
+        }],
+    )];
+    let merkle_root = MerkleRoot::calculate(&transactions);
+    Block::new(
+        BlockHeader::new(Utc::now(), 0, Hash::zero(), merkle_root, target),
+        transactions,
+    )
+}
+
+fn mine_until(mut block: Block, target: U256, deadline: Instant) -> ThreadReport {
+    let start = Instant::now();
+    let mut report = ThreadReport::default();
+    while Instant::now() < deadline {
+        let nonce_before = block.header.nonce;
+        // `mine` hashes the starting nonce once before trying `steps`
+        // more, so a batch hashes BATCH_SIZE nonces
+        if block.header.mine(BATCH_SIZE - 1) {
+            report.hashes += block.header.nonce.wrapping_sub(nonce_before) + 1;
+            report.solutions += 1;
+            // check the solution without relying on `mine` or
+            // `matches_target`
+            if Hash::hash(&block.header).0 > target {
+                report.invalid_solutions += 1;
+            }
+        } else {
+            report.hashes += BATCH_SIZE as u64;
+        }
+        // the next batch starts after the last hashed nonce
+        block.header.nonce = block.header.nonce.wrapping_add(1);
+    }
+    report.elapsed = start.elapsed();
+    report
+}
+
+pub fn run(target: U256, duration: Duration, threads: usize) -> Result<()> {
+    let block = synthetic_block(target);
+    // the header only keeps the compact form of the target
+    let target = block.header.target();
+    println!(
+        "Benchmarking {threads} thread(s) for {}s with target: {target:x}",
+        duration.as_secs()
+    );
+
+    let deadline = Instant::now() + duration;
+    let handles = (0..threads as u64)
+        .map(|i| {
+            let mut block = block.clone();
+            // give every thread its own slice of the nonce space
+            block.header.nonce = u64::MAX / threads as u64 * i;
+            thread::spawn(move || mine_until(block, target, deadline))
+        })
+        .collect::<Vec<_>>();
+
+    let mut total_hashrate = 0.0;
+    let mut solutions = 0;
+    let mut invalid_solutions = 0;
+    for (i, handle) in handles.into_iter().enumerate() {
+        let report = handle
+            .join()
+            .map_err(|_| anyhow!("Benchmark thread {i} panicked"))?;
+        let hashrate = report.hashes as f64 / report.elapsed.as_secs_f64();
+        println!(
+            "Thread {i}: {hashrate:.0} H/s, {} hashes, {} solution(s)",
+            report.hashes, report.solutions
+        );
+        total_hashrate += hashrate;
+        solutions += report.solutions;
+        invalid_solutions += report.invalid_solutions;
+    }
+    println!("Total: {total_hashrate:.0} H/s, {solutions} solution(s)");
+
+    if invalid_solutions > 0 {
+        return Err(anyhow!(
+            "{invalid_solutions} solution(s) did not match the target"
+        ));
+    }
+    if solutions == 0 {
+        println!(
+            "No solution found, so none could be checked; run longer or pass an easier --target"
+        );
+        return Ok(());
+    }
+    println!("All {solutions} solution(s) match the target");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn batches_count_every_hash_once() {
+        // no hash matches a zero target
+        let deadline = Instant::now() + Duration::from_millis(200);
+        let report = mine_until(synthetic_block(U256::zero()), U256::zero(), deadline);
+        assert!(report.hashes > 0);
+        assert_eq!(report.hashes % BATCH_SIZE as u64, 0);
+        assert_eq!(report.solutions, 0);
+
+        // every hash matches the highest target
+        let deadline = Instant::now() + Duration::from_millis(200);
+        let report = mine_until(synthetic_block(U256::MAX), U256::MAX, deadline);
+        assert!(report.solutions > 0);
+        assert_eq!(report.hashes, report.solutions);
+        assert_eq!(report.invalid_solutions, 0);
+    }
+
+    #[test]
+    fn solutions_are_checked_against_the_target() {
+        // `mine` checks the header's own target, so solutions for an
+        // easier one do not meet a harder target
+        let block = synthetic_block(U256::MAX);
+        let deadline = Instant::now() + Duration::from_millis(200);
+        let report = mine_until(block, U256::zero(), deadline);
+        assert!(report.solutions > 0);
+        assert_eq!(report.invalid_solutions, report.solutions);
+    }
+}
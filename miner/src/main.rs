@@ -1,19 +1,32 @@
+mod bench;
+
 use {
     anyhow::{
         anyhow,
         Result,
     },
     btclib::{
+        address::{
+            Address,
+            Network,
+        },
         crypto::PublicKey,
         network::Message,
-        types::block::Block,
+        types::{
+            block::Block,
+            transaction::Lock,
+        },
         util::Saveable,
+        U256,
     },
     clap::{
         arg,
+        Args,
         Parser,
+        Subcommand,
     },
     std::{
+        future::Future,
         sync::{
             atomic::{
                 AtomicBool,
@@ -28,23 +41,99 @@ use {
         sync::Mutex,
         time::{
             interval,
+            timeout,
             Duration,
+            Instant,
         },
     },
 };
 
+// how long to wait for a node to accept a connection
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+// how long to wait for a node to answer a request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+// how often to check whether a higher priority node has recovered
+const PROBE_INTERVAL: Duration = Duration::from_secs(60);
+// how long to wait before looking for a node again after none was
+// reachable, doubling with every further miss
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(300);
+
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Cli {
-    #[arg(short, long)]
-    address: String,
-    #[arg(short, long)]
-    public_key_file: String,
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    mine: Option<MineArgs>,
+}
+
+#[derive(Args)]
+struct MineArgs {
+    /// Node addresses in order of priority, the first one is the primary
+    #[arg(short, long = "address", num_args = 1.., required = true)]
+    addresses: Vec<String>,
+    /// Public key to pay the block rewards to
+    #[arg(short, long, required_unless_present = "payout_address")]
+    public_key_file: Option<String>,
+    /// Address to pay the block rewards to, instead of a public key
+    #[arg(long, conflicts_with = "public_key_file")]
+    payout_address: Option<Address>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Mine a synthetic block offline and report the hashrate
+    Bench {
+        /// Target to mine against, in hex
+        #[arg(short, long, value_parser = parse_target)]
+        target: Option<U256>,
+        /// How long to mine for, in seconds
+        #[arg(short, long, default_value_t = 10)]
+        seconds: u64,
+        /// Number of mining threads, defaults to the number of CPUs
+        #[arg(short = 'j', long)]
+        threads: Option<usize>,
+    },
+}
+
+fn parse_target(s: &str) -> Result<U256> {
+    U256::from_str_radix(s.trim_start_matches("0x"), 16).map_err(|e| anyhow!("{e:?}"))
+}
+
+struct NodeConnection {
+    /// Index of the node in the address list, lower is preferred
+    priority: usize,
+    stream: TcpStream,
+}
+
+/// Passes over the nodes in a row that found none reachable
+struct Backoff {
+    failures: u32,
+    /// When to look for a node again
+    retry_at: Instant,
+}
+
+impl Backoff {
+    // delay after `failures` passes that found no node
+    fn delay(failures: u32) -> Duration {
+        let factor = 2u32.saturating_pow(failures.saturating_sub(1));
+        RETRY_DELAY.saturating_mul(factor).min(MAX_RETRY_DELAY)
+    }
 }
 
 struct Miner {
-    public_key: PublicKey,
-    stream: Mutex<TcpStream>,
+    /// What the coinbase outputs of the templates are locked to
+    payout: Lock,
+    addresses: Vec<String>,
+    connection: Mutex<Option<NodeConnection>>,
+    /// When the higher priority nodes were last probed
+    last_probe: Mutex<Instant>,
+    backoff: Mutex<Backoff>,
+    /// Mined block no node has taken yet, no other block is mined
+    /// until one does
+    pending_block: Mutex<Option<Block>>,
     current_template: Arc<std::sync::Mutex<Option<Block>>>,
     mining: Arc<AtomicBool>,
     mined_block_sender: flume::Sender<Block>,
@@ -52,18 +141,24 @@ struct Miner {
 }
 
 impl Miner {
-    async fn new(address: String, public_key: PublicKey) -> Result<Self> {
-        let stream = TcpStream::connect(&address).await?;
+    fn new(addresses: Vec<String>, payout: Lock) -> Self {
         let (mined_block_sender, mined_block_receiver) = flume::unbounded::<Block>();
 
-        Ok(Self {
-            public_key,
-            stream: Mutex::new(stream),
+        Self {
+            payout,
+            addresses,
+            connection: Mutex::new(None),
+            last_probe: Mutex::new(Instant::now()),
+            backoff: Mutex::new(Backoff {
+                failures: 0,
+                retry_at: Instant::now(),
+            }),
+            pending_block: Mutex::new(None),
             current_template: Arc::new(std::sync::Mutex::new(None)),
             mining: Arc::new(AtomicBool::new(false)),
             mined_block_sender,
             mined_block_receiver,
-        })
+        }
     }
 
     async fn run(&self) -> Result<()> {
@@ -73,15 +168,158 @@ impl Miner {
             let receiver_clone = self.mined_block_receiver.clone();
             tokio::select! {
                 _ = template_interval.tick() => {
-                    self.fetch_and_validate_template().await?;
+                    self.restore_preferred_node().await;
+                    self.submit_pending_block().await;
+                    // the template is kept across a switch, so a miner that
+                    // failed over re-validates it against the new node's tip
+                    if let Err(e) = self
+                        .with_fail_over(|| self.fetch_and_validate_template())
+                        .await
+                    {
+                        println!("{e}");
+                    }
                 }
                 Ok(mined_block) = receiver_clone.recv_async() => {
-                    self.submit_block(mined_block).await?;
+                    *self.pending_block.lock().await = Some(mined_block);
+                    self.submit_pending_block().await;
                 }
             }
         }
     }
 
+    // connect to a node and make sure it answers requests
+    async fn open_connection(address: &str) -> Result<TcpStream> {
+        let mut stream = timeout(CONNECT_TIMEOUT, TcpStream::connect(address)).await??;
+        timeout(REQUEST_TIMEOUT, async {
+            Message::AskDifference(0).send_async(&mut stream).await?;
+            match Message::receive_async(&mut stream).await? {
+                Message::Difference(_) => Ok(()),
+                _ => Err(anyhow!("Unexpected msg received when probing node")),
+            }
+        })
+        .await??;
+        Ok(stream)
+    }
+
+    // connect to the first reachable node, trying `failed` only as a
+    // last resort. After a pass that found no node, the next one waits
+    // for the backoff delay
+    async fn connect(&self, failed: Option<usize>) -> Result<()> {
+        let mut backoff = self.backoff.lock().await;
+        if Instant::now() < backoff.retry_at {
+            return Err(anyhow!(
+                "No node is reachable, retrying in {}s",
+                (backoff.retry_at - Instant::now()).as_secs()
+            ));
+        }
+        let candidates = (0..self.addresses.len())
+            .filter(|priority| Some(*priority) != failed)
+            .chain(failed);
+        for priority in candidates {
+            let address = &self.addresses[priority];
+            match Self::open_connection(address).await {
+                Ok(stream) => {
+                    println!("Connected to node {address}");
+                    *self.connection.lock().await = Some(NodeConnection { priority, stream });
+                    backoff.failures = 0;
+                    return Ok(());
+                }
+                Err(e) => println!("Failed to connect to node {address}: {e}"),
+            }
+        }
+        backoff.failures += 1;
+        backoff.retry_at = Instant::now() + Backoff::delay(backoff.failures);
+        Err(anyhow!("No node is reachable"))
+    }
+
+    // drop the current connection after an error and switch to another node
+    async fn fail_over(&self, error: anyhow::Error) {
+        let failed = self
+            .connection
+            .lock()
+            .await
+            .take()
+            .map(|connection| connection.priority);
+        if let Some(priority) = failed {
+            println!(
+                "Lost node {}: {error}, failing over",
+                self.addresses[priority]
+            );
+        }
+        if let Err(e) = self.connect(failed).await {
+            println!("{e}");
+        }
+    }
+
+    // run `request` against the current node. If it fails, fail over
+    // and run it once more on the new node, which is dropped as well
+    // if that fails too, so that every failure costs a single pass
+    // over the nodes
+    async fn with_fail_over<F, Fut>(&self, request: F) -> Result<()>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        let Err(e) = request().await else {
+            return Ok(());
+        };
+        self.fail_over(e).await;
+        if self.connection.lock().await.is_none() {
+            return Err(anyhow!("Not connected to any node"));
+        }
+        let result = request().await;
+        if result.is_err() {
+            self.connection.lock().await.take();
+        }
+        result
+    }
+
+    // switch back to a higher priority node once it has recovered
+    async fn restore_preferred_node(&self) {
+        let current = self
+            .connection
+            .lock()
+            .await
+            .as_ref()
+            .map(|connection| connection.priority);
+        let Some(current) = current else {
+            if let Err(e) = self.connect(None).await {
+                println!("{e}");
+            }
+            return;
+        };
+        // probing every node above the current one can take a while,
+        // which would hold up submitting blocks on every tick
+        {
+            let mut last_probe = self.last_probe.lock().await;
+            if current == 0 || last_probe.elapsed() < PROBE_INTERVAL {
+                return;
+            }
+            *last_probe = Instant::now();
+        }
+        for priority in 0..current {
+            let address = &self.addresses[priority];
+            if let Ok(stream) = Self::open_connection(address).await {
+                println!("Node {address} is back, switching to it");
+                *self.connection.lock().await = Some(NodeConnection { priority, stream });
+                return;
+            }
+        }
+    }
+
+    // send a message to the current node and wait for the reply
+    async fn request(&self, message: Message) -> Result<Message> {
+        let mut connection = self.connection.lock().await;
+        let connection = connection
+            .as_mut()
+            .ok_or_else(|| anyhow!("Not connected to any node"))?;
+        timeout(REQUEST_TIMEOUT, async {
+            message.send_async(&mut connection.stream).await?;
+            Ok(Message::receive_async(&mut connection.stream).await?)
+        })
+        .await?
+    }
+
     fn spawn_mining_thread(&self) -> thread::JoinHandle<()> {
         let template = self.current_template.clone();
         let mining = self.mining.clone();
@@ -90,12 +328,19 @@ impl Miner {
         thread::spawn(move || {
             loop {
                 if mining.load(Ordering::Relaxed) {
-                    if let Some(mut block) = template.lock().unwrap().clone() {
-                        println!("Mining block with target: {}", block.header.target);
+                    // the guard has to be gone before the template is
+                    // locked again below
+                    let block = template.lock().unwrap().clone();
+                    if let Some(mut block) = block {
+                        println!("Mining block with target: {}", block.header.target());
                         if block.header.mine(2_000_000) {
                             println!("Block mined: {}", block.hash());
                             sender.send(block).expect("Failed to send mined block");
                             mining.store(false, Ordering::Relaxed);
+                        } else if let Some(template) = template.lock().unwrap().as_mut() {
+                            // every batch starts over from the template's
+                            // nonce, so move on to a fresh merkle root
+                            template.increment_extra_nonce();
                         }
                     }
                 }
@@ -105,6 +350,10 @@ impl Miner {
     }
 
     async fn fetch_and_validate_template(&self) -> Result<()> {
+        // a new template would not build on the pending block
+        if self.pending_block.lock().await.is_some() {
+            return Ok(());
+        }
         if !self.mining.load(Ordering::Relaxed) {
             self.fetch_template().await?;
         } else {
@@ -115,18 +364,12 @@ impl Miner {
 
     async fn fetch_template(&self) -> Result<()> {
         println!("Fetching new template");
-        let message = Message::FetchTemplate(self.public_key.clone());
-        let mut stream_lock = self.stream.lock().await;
-        message.send_async(&mut *stream_lock).await?;
-        drop(stream_lock);
-
-        let mut stream_lock = self.stream.lock().await;
-        match Message::receive_async(&mut *stream_lock).await? {
+        let message = Message::FetchTemplate(self.payout.clone());
+        match self.request(message).await? {
             Message::Template(template) => {
-                drop(stream_lock);
                 println!(
                     "Received new template with target: {}",
-                    template.header.target
+                    template.header.target()
                 );
                 *self.current_template.lock().unwrap() = Some(template);
                 self.mining.store(true, Ordering::Relaxed);
@@ -140,14 +383,8 @@ impl Miner {
     async fn validate_template(&self) -> Result<()> {
         if let Some(template) = self.current_template.lock().unwrap().clone() {
             let message = Message::ValidateTemplate(template);
-            let mut stream_lock = self.stream.lock().await;
-            message.send_async(&mut *stream_lock).await?;
-            drop(stream_lock);
-
-            let mut stream_lock = self.stream.lock().await;
-            match Message::receive_async(&mut *stream_lock).await? {
+            match self.request(message).await? {
                 Message::TemplateValidity(valid) => {
-                    drop(stream_lock);
                     if !valid {
                         println!("Current template is no longer valid");
                         self.mining.store(false, Ordering::Relaxed);
@@ -167,11 +404,29 @@ impl Miner {
         }
     }
 
+    // submit the mined block, failing over between nodes, and keep it
+    // to retry on the next tick if no node takes it
+    async fn submit_pending_block(&self) {
+        let Some(block) = self.pending_block.lock().await.clone() else {
+            return;
+        };
+        match self
+            .with_fail_over(|| self.submit_block(block.clone()))
+            .await
+        {
+            Ok(()) => *self.pending_block.lock().await = None,
+            Err(e) => println!("No node took the mined block, retrying on the next tick: {e}"),
+        }
+    }
+
     async fn submit_block(&self, block: Block) -> Result<()> {
         println!("Submitting mined block");
         let message = Message::SubmitTemplate(block);
-        let mut stream_lock = self.stream.lock().await;
-        message.send_async(&mut *stream_lock).await?;
+        let mut connection = self.connection.lock().await;
+        let connection = connection
+            .as_mut()
+            .ok_or_else(|| anyhow!("Not connected to any node"))?;
+        timeout(REQUEST_TIMEOUT, message.send_async(&mut connection.stream)).await??;
         self.mining.store(false, Ordering::Relaxed);
 
         Ok(())
@@ -181,9 +436,142 @@ impl Miner {
 #[tokio::main]
 async fn main() -> Result<()> {
     let cli = Cli::parse();
-    let public_key = PublicKey::load_from_file(&cli.public_key_file)
-        .map_err(|e| anyhow!("Error reading public key: {e}"))?;
+    if let Some(Command::Bench {
+        target,
+        seconds,
+        threads,
+    }) = cli.command
+    {
+        let threads = match threads {
+            Some(threads) => threads,
+            None => thread::available_parallelism()?.get(),
+        };
+        let target = target.unwrap_or(btclib::MIN_TARGET);
+        return bench::run(target, Duration::from_secs(seconds), threads.max(1));
+    }
+
+    let Some(args) = cli.mine else {
+        return Err(anyhow!("Missing node address and payout"));
+    };
+    let payout = match (args.payout_address, args.public_key_file) {
+        (Some(address), _) => {
+            println!("Paying rewards to {address}");
+            Lock::from(address)
+        }
+        (None, Some(public_key_file)) => {
+            let public_key = PublicKey::load_from_file(&public_key_file)
+                .map_err(|e| anyhow!("Error reading public key: {e}"))?;
+            println!(
+                "Paying rewards to {}",
+                Address::from_public_key(Network::default(), &public_key)
+            );
+            // the key is only revealed when the reward is spent
+            Lock::PubkeyHash(public_key.pubkey_hash())
+        }
+        (None, None) => return Err(anyhow!("Missing public key file or payout address")),
+    };
 
-    let miner = Miner::new(cli.address, public_key).await?;
+    let miner = Miner::new(args.addresses, payout);
     miner.run().await
 }
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        btclib::{
+            crypto::PrivateKey,
+            sha256::Hash,
+            types::block_header::BlockHeader,
+            util::MerkleRoot,
+        },
+        chrono::Utc,
+        tokio::net::TcpListener,
+    };
+
+    fn miner(addresses: Vec<String>) -> Miner {
+        Miner::new(addresses, PrivateKey::new_key().public_key().into())
+    }
+
+    // an address nothing listens on
+    async fn dead_address() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        listener.local_addr().unwrap().to_string()
+    }
+
+    fn block() -> Block {
+        let header = BlockHeader::new(
+            Utc::now(),
+            0,
+            Hash::zero(),
+            MerkleRoot::calculate(&[]),
+            btclib::MIN_TARGET,
+        );
+        Block::new(header, vec![])
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() {
+        assert_eq!(Backoff::delay(1), RETRY_DELAY);
+        assert_eq!(Backoff::delay(2), RETRY_DELAY * 2);
+        assert_eq!(Backoff::delay(3), RETRY_DELAY * 4);
+        assert_eq!(Backoff::delay(10), MAX_RETRY_DELAY);
+        assert_eq!(Backoff::delay(u32::MAX), MAX_RETRY_DELAY);
+    }
+
+    #[tokio::test]
+    async fn unreachable_nodes_are_retried_after_the_backoff() {
+        let miner = miner(vec![dead_address().await, dead_address().await]);
+        assert!(miner.connect(None).await.is_err());
+        assert_eq!(miner.backoff.lock().await.failures, 1);
+        // the next pass waits for the backoff delay
+        assert!(miner.connect(None).await.is_err());
+        assert_eq!(miner.backoff.lock().await.failures, 1);
+        miner.backoff.lock().await.retry_at = Instant::now();
+        assert!(miner.connect(None).await.is_err());
+        let backoff = miner.backoff.lock().await;
+        assert_eq!(backoff.failures, 2);
+        assert!(backoff.retry_at > Instant::now() + RETRY_DELAY);
+    }
+
+    #[tokio::test]
+    async fn mined_blocks_fail_over_to_the_next_node() {
+        // a node answering the probe and taking a block
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let node = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let Message::AskDifference(_) = Message::receive_async(&mut stream).await.unwrap()
+            else {
+                panic!("expected a probe");
+            };
+            Message::Difference(0)
+                .send_async(&mut stream)
+                .await
+                .unwrap();
+            match Message::receive_async(&mut stream).await.unwrap() {
+                Message::SubmitTemplate(block) => block,
+                _ => panic!("expected a block"),
+            }
+        });
+
+        let miner = miner(vec![dead_address().await, address]);
+        let block = block();
+        *miner.pending_block.lock().await = Some(block.clone());
+        miner.submit_pending_block().await;
+        assert!(miner.pending_block.lock().await.is_none());
+        assert_eq!(node.await.unwrap().hash(), block.hash());
+        let connection = miner.connection.lock().await;
+        assert_eq!(connection.as_ref().unwrap().priority, 1);
+    }
+
+    #[tokio::test]
+    async fn nothing_is_mined_while_a_block_is_pending() {
+        let miner = miner(vec![dead_address().await]);
+        assert!(miner.fetch_and_validate_template().await.is_err());
+        *miner.pending_block.lock().await = Some(block());
+        miner.fetch_and_validate_template().await.unwrap();
+        assert!(miner.current_template.lock().unwrap().is_none());
+        assert!(!miner.mining.load(Ordering::Relaxed));
+    }
+}
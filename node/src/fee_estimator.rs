@@ -0,0 +1,317 @@
+use {
+    btclib::{
+        sha256::Hash,
+        types::{
+            block::Block,
+            fee_rate::FeeRate,
+            mempool::Mempool,
+        },
+    },
+    std::collections::HashMap,
+};
+
+// highest confirmation target, in blocks, that can be estimated
+const MAX_TARGET: u64 = 25;
+// lowest and highest tracked fee rates in satoshis per 1000 weight units
+const MIN_BUCKET_FEE_RATE: f64 = 250.0;
+const MAX_BUCKET_FEE_RATE: f64 = 2_500_000.0;
+// each bucket starts this much higher than the previous one
+const BUCKET_SPACING: f64 = 1.2;
+// weight kept by older observations every time a block is connected
+const DECAY: f64 = 0.998;
+// share of transactions that must confirm in time for a fee rate
+// to be recommended
+const SUCCESS_THRESHOLD: f64 = 0.85;
+// minimum (decayed) amount of transactions behind an estimate: an
+// average of one every 10 blocks over the time DECAY remembers
+const MIN_SAMPLES: f64 = 0.1 / (1.0 - DECAY);
+
+/// Tracks how many blocks mempool transactions take to confirm
+/// depending on their fee rate, grouped in exponentially spaced
+/// fee rate buckets
+pub struct FeeEstimator {
+    // lower bound of every bucket
+    buckets: Vec<f64>,
+    // confirmed[target - 1][bucket]: transactions confirmed
+    // within `target` blocks
+    confirmed: Vec<Vec<f64>>,
+    // transactions that confirmed or took longer than MAX_TARGET
+    total: Vec<f64>,
+    // mempool transactions being watched: hash -> (height when
+    // seen, bucket)
+    tracked: HashMap<Hash, (u64, usize)>,
+}
+
+impl Default for FeeEstimator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FeeEstimator {
+    pub fn new() -> Self {
+        let mut buckets = vec![];
+        let mut fee_rate = MIN_BUCKET_FEE_RATE;
+        while fee_rate <= MAX_BUCKET_FEE_RATE {
+            buckets.push(fee_rate);
+            fee_rate *= BUCKET_SPACING;
+        }
+        FeeEstimator {
+            confirmed: vec![vec![0.0; buckets.len()]; MAX_TARGET as usize],
+            total: vec![0.0; buckets.len()],
+            buckets,
+            tracked: HashMap::new(),
+        }
+    }
+
+    fn bucket(&self, fee_rate: f64) -> usize {
+        self.buckets
+            .iter()
+            .rposition(|lower_bound| *lower_bound <= fee_rate)
+            .unwrap_or(0)
+    }
+
+    /// Start watching a transaction that entered the mempool
+    /// while the chain was `height` blocks high
+    pub fn track(&mut self, hash: Hash, fee_rate: FeeRate, height: u64) {
+        let bucket = self.bucket(fee_rate.to_sat_per_kwu() as f64);
+        self.tracked.insert(hash, (height, bucket));
+    }
+
+    /// Record how long the block's transactions waited, the block
+    /// being the one that brought the chain to `height`
+    pub fn process_block(&mut self, block: &Block, height: u64, mempool: &Mempool) {
+        for count in self
+            .confirmed
+            .iter_mut()
+            .flatten()
+            .chain(self.total.iter_mut())
+        {
+            *count *= DECAY;
+        }
+
+        for transaction in block.transactions.iter() {
+            let Some((seen_at, bucket)) = self.tracked.remove(&transaction.hash()) else {
+                continue;
+            };
+            let blocks = height.saturating_sub(seen_at).max(1);
+            for target in blocks..=MAX_TARGET {
+                self.confirmed[target as usize - 1][bucket] += 1.0;
+            }
+            self.total[bucket] += 1.0;
+        }
+
+        // transactions waiting longer than any target count as
+        // failures, the ones that left the mempool otherwise
+        // (replaced, evicted, expired) are forgotten
+        let total = &mut self.total;
+        self.tracked.retain(|hash, (seen_at, bucket)| {
+            if !mempool.contains(hash) {
+                return false;
+            }
+            if height.saturating_sub(*seen_at) > MAX_TARGET {
+                total[*bucket] += 1.0;
+                return false;
+            }
+            true
+        });
+    }
+
+    /// Lowest fee rate that got transactions confirmed within
+    /// `target` blocks often enough. Buckets are
+    /// grouped from the highest fee rate down until they hold
+    /// enough samples, and the search stops at the first group
+    /// that confirmed too slowly.
+    pub fn estimate(&self, target: u64) -> Option<FeeRate> {
+        if target == 0 || target > MAX_TARGET {
+            return None;
+        }
+        let confirmed = &self.confirmed[target as usize - 1];
+        let mut estimate = None;
+        let mut group_confirmed = 0.0;
+        let mut group_total = 0.0;
+        for bucket in (0..self.buckets.len()).rev() {
+            group_confirmed += confirmed[bucket];
+            group_total += self.total[bucket];
+            if group_total < MIN_SAMPLES {
+                continue;
+            }
+            if group_confirmed / group_total < SUCCESS_THRESHOLD {
+                break;
+            }
+            estimate = Some(FeeRate::from_sat_per_kwu(self.buckets[bucket].ceil() as u64));
+            group_confirmed = 0.0;
+            group_total = 0.0;
+        }
+        estimate
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        btclib::{
+            crypto::{
+                PrivateKey,
+                Signature,
+            },
+            types::{
+                amount::Amount,
+                block_header::BlockHeader,
+                transaction::{
+                    Transaction,
+                    TransactionInput,
+                    TransactionOutput,
+                },
+                utxo::Utxo,
+            },
+            util::MerkleRoot,
+        },
+        chrono::Utc,
+        uuid::Uuid,
+    };
+
+    // enough transactions for an estimate
+    const SAMPLES: usize = MIN_SAMPLES as usize + 10;
+
+    // distinct transactions, which only need a hash here
+    fn transactions(count: usize) -> Vec<Transaction> {
+        (0..count)
+            .map(|_| Transaction::new_coinbase(0, Uuid::new_v4().as_bytes().to_vec(), vec![]))
+            .collect()
+    }
+
+    fn block(transactions: Vec<Transaction>) -> Block {
+        let header = BlockHeader::new(
+            Utc::now(),
+            0,
+            Hash::zero(),
+            MerkleRoot::calculate(&transactions),
+            btclib::MIN_TARGET,
+        );
+        Block::new(header, transactions)
+    }
+
+    // track `transactions` at `fee_rate` at height 0 and confirm them
+    // in the block bringing the chain to `height`
+    fn confirm(
+        estimator: &mut FeeEstimator,
+        transactions: Vec<Transaction>,
+        fee_rate: FeeRate,
+        height: u64,
+    ) {
+        for transaction in transactions.iter() {
+            estimator.track(transaction.hash(), fee_rate, 0);
+        }
+        estimator.process_block(&block(transactions), height, &Mempool::default());
+    }
+
+    // whether `estimate` is the lower bound of the bucket of
+    // `fee_rate`
+    fn is_bucket_of(estimate: Option<FeeRate>, fee_rate: FeeRate) -> bool {
+        let Some(estimate) = estimate else {
+            return false;
+        };
+        let fee_rate = fee_rate.to_sat_per_kwu() as f64;
+        let estimate = estimate.to_sat_per_kwu() as f64;
+        estimate <= fee_rate && estimate * BUCKET_SPACING > fee_rate
+    }
+
+    #[test]
+    fn nothing_is_estimated_without_data() {
+        let mut estimator = FeeEstimator::new();
+        for target in 0..=MAX_TARGET + 1 {
+            assert_eq!(estimator.estimate(target), None);
+        }
+        // nor from a handful of transactions
+        let fee_rate = FeeRate::from_sat_per_kwu(10_000);
+        confirm(&mut estimator, transactions(10), fee_rate, 1);
+        assert_eq!(estimator.estimate(1), None);
+        confirm(&mut estimator, transactions(SAMPLES), fee_rate, 1);
+        assert!(is_bucket_of(estimator.estimate(1), fee_rate));
+        assert_eq!(estimator.estimate(0), None);
+        assert_eq!(estimator.estimate(MAX_TARGET + 1), None);
+    }
+
+    #[test]
+    fn targets_get_the_lowest_fee_rate_confirming_in_time() {
+        let mut estimator = FeeEstimator::new();
+        let high = FeeRate::from_sat_per_kwu(20_000);
+        let low = FeeRate::from_sat_per_kwu(1_000);
+        confirm(&mut estimator, transactions(SAMPLES), high, 2);
+        confirm(&mut estimator, transactions(SAMPLES), low, 10);
+
+        assert_eq!(estimator.estimate(1), None);
+        for target in 2..10 {
+            assert!(is_bucket_of(estimator.estimate(target), high));
+        }
+        for target in 10..=MAX_TARGET {
+            assert!(is_bucket_of(estimator.estimate(target), low));
+        }
+    }
+
+    #[test]
+    fn old_observations_decay() {
+        let mut estimator = FeeEstimator::new();
+        let fee_rate = FeeRate::from_sat_per_kwu(10_000);
+        confirm(&mut estimator, transactions(SAMPLES), fee_rate, 1);
+        assert!(is_bucket_of(estimator.estimate(1), fee_rate));
+
+        // enough blocks for the samples to decay below MIN_SAMPLES
+        let blocks = ((MIN_SAMPLES / SAMPLES as f64).ln() / DECAY.ln()).ceil() as u64;
+        for height in 2..=blocks {
+            estimator.process_block(&block(vec![]), height, &Mempool::default());
+            assert!(is_bucket_of(estimator.estimate(1), fee_rate));
+        }
+        estimator.process_block(&block(vec![]), blocks + 1, &Mempool::default());
+        assert_eq!(estimator.estimate(1), None);
+    }
+
+    #[test]
+    fn transactions_waiting_too_long_count_as_failures() {
+        let private_key = PrivateKey::new_key();
+        let mut utxos = HashMap::new();
+        let mut mempool = Mempool::default();
+        let mut estimator = FeeEstimator::new();
+        let low = FeeRate::from_sat_per_kwu(1_000);
+        for _ in 0..SAMPLES {
+            let spent = TransactionOutput {
+                value: Amount::from_sat(1_000_000),
+                unique_id: Uuid::new_v4(),
+                lock: private_key.public_key().into(),
+            };
+            utxos.insert(spent.hash(), Utxo::new(spent.clone(), 0, Utc::now(), false));
+            let mut transaction = Transaction::new(
+                vec![TransactionInput {
+                    prev_tx_output_hash: spent.hash(),
+                    signature: Signature::sign(&spent.hash(), &private_key),
+                    sequence: btclib::SEQUENCE_FINAL,
+                    pubkey: None,
+                    unlock: Default::default(),
+                    extra_signatures: vec![],
+                }],
+                vec![TransactionOutput {
+                    value: Amount::from_sat(990_000),
+                    unique_id: Uuid::new_v4(),
+                    lock: private_key.public_key().into(),
+                }],
+            );
+            let signature_hash = transaction.signature_hashes()[0];
+            transaction.inputs[0].signature = Signature::sign(&signature_hash, &private_key);
+            estimator.track(transaction.hash(), low, 0);
+            mempool.insert(transaction, &utxos, 1, Utc::now()).unwrap();
+        }
+        let high = FeeRate::from_sat_per_kwu(20_000);
+        confirm(&mut estimator, transactions(SAMPLES), high, 1);
+        for height in 2..=MAX_TARGET + 1 {
+            estimator.process_block(&block(vec![]), height, &mempool);
+        }
+
+        // the low fee rate transactions did not confirm within any
+        // target, so only the high fee rate is recommended
+        for target in 1..=MAX_TARGET {
+            assert!(is_bucket_of(estimator.estimate(target), high));
+        }
+    }
+}
@@ -1,18 +1,14 @@
 use {
     btclib::{
+        error::BtcError,
         network::Message,
         sha256::Hash,
+        template::BlockTemplate,
         types::{
-            block::Block,
-            block_header::BlockHeader,
-            transaction::{
-                Transaction,
-                TransactionOutput,
-            },
+            blockchain::Blockchain,
+            transaction::Transaction,
         },
-        util::MerkleRoot,
     },
-    chrono::Utc,
     tokio::net::TcpStream,
 };
 
@@ -29,10 +25,29 @@ pub async fn handle_connection(mut socket: TcpStream) {
 
         use btclib::network::Message::*;
         match message {
-            UTXOs(_) | Template(_) | Difference(_) | TemplateValidity(_) | NodeList(_) => {
+            UTXOs(_)
+            | Balance(_)
+            | Template(_)
+            | Difference(_)
+            | Chainwork(..)
+            | Time(_)
+            | TemplateValidity(_)
+            | NodeList(_)
+            | TransactionAccepted(_)
+            | TransactionRejected(_)
+            | Headers(_)
+            | TransactionProof(_)
+            | FilteredBlock(_)
+            | CFilter(..)
+            | CFHeaders(_)
+            | FeeEstimate(_) => {
                 println!("");
                 return;
             }
+            AskTime => {
+                let message = Time(chrono::Utc::now());
+                message.send_async(&mut socket).await.unwrap();
+            }
             FetchBlock(height) => {
                 let blockchain = crate::BLOCKCHAIN.read().await;
                 let Some(block) = blockchain.blocks().nth(height).cloned() else {
@@ -41,26 +56,99 @@ pub async fn handle_connection(mut socket: TcpStream) {
                 let message = NewBlock(block);
                 message.send_async(&mut socket).await.unwrap();
             }
-            FetchUTXOs(key) => {
+            FetchHeaders(height) => {
+                let blockchain = crate::BLOCKCHAIN.read().await;
+                let headers = blockchain
+                    .blocks()
+                    .skip(height as usize)
+                    .take(btclib::MAX_HEADERS_PER_MESSAGE)
+                    .map(|block| block.header.clone())
+                    .collect();
+                let message = Headers(headers);
+                message.send_async(&mut socket).await.unwrap();
+            }
+            FetchTransactionProof(hash) => {
+                let blockchain = crate::BLOCKCHAIN.read().await;
+                let proof = blockchain.transaction_height(&hash).and_then(|height| {
+                    let block = blockchain.blocks().nth(height as usize)?;
+                    btclib::spv::TransactionProof::new(block, height, &hash)
+                });
+                let message = TransactionProof(proof);
+                message.send_async(&mut socket).await.unwrap();
+            }
+            FetchFilteredBlock(height, pubkey_hash, outpoints) => {
+                let blockchain = crate::BLOCKCHAIN.read().await;
+                let outpoints = outpoints.into_iter().collect();
+                let block = blockchain.blocks().nth(height as usize).map(|block| {
+                    btclib::spv::FilteredBlock::new(block, height, &pubkey_hash, &outpoints)
+                });
+                let message = FilteredBlock(block);
+                message.send_async(&mut socket).await.unwrap();
+            }
+            GetCFilters(height, count) => {
+                let blockchain = crate::BLOCKCHAIN.read().await;
+                let count = count.min(btclib::MAX_CFILTERS_PER_REQUEST) as usize;
+                for (block, filter) in blockchain
+                    .blocks()
+                    .zip(blockchain.filters())
+                    .skip(height as usize)
+                    .take(count)
+                {
+                    let message = CFilter(block.hash(), filter.clone());
+                    message.send_async(&mut socket).await.unwrap();
+                }
+            }
+            GetCFHeaders(height, count) => {
+                let blockchain = crate::BLOCKCHAIN.read().await;
+                let count = count.min(btclib::MAX_CFILTERS_PER_REQUEST) as usize;
+                let headers = blockchain
+                    .filter_headers()
+                    .skip(height as usize)
+                    .take(count)
+                    .copied()
+                    .collect();
+                let message = CFHeaders(headers);
+                message.send_async(&mut socket).await.unwrap();
+            }
+            FetchUTXOs(pubkey_hash) => {
                 println!("received request to fetch UTXOs");
                 let blockchain = crate::BLOCKCHAIN.read().await;
-                let utxos = blockchain
-                    .utxos()
-                    .iter()
-                    .filter(|(_, (_, txout))| txout.pubkey == key)
-                    .map(|(_, (marked, txout))| (txout.clone(), *marked))
-                    .collect::<Vec<_>>();
+                let utxos = blockchain.spendable_outputs(&pubkey_hash);
                 let message = UTXOs(utxos);
                 message.send_async(&mut socket).await.unwrap();
             }
+            FetchBalance(pubkey_hash) => {
+                println!("received request to fetch balance");
+                let blockchain = crate::BLOCKCHAIN.read().await;
+                let message = Balance(blockchain.balance(&pubkey_hash));
+                message.send_async(&mut socket).await.unwrap();
+            }
             SubmitTransaction(transaction) => {
                 println!("submit tx");
                 let mut blockchain = crate::BLOCKCHAIN.write().await;
-                if let Err(e) = blockchain.add_to_mempool(transaction.clone()) {
-                    println!("transaction rejected, closing connection: {e}");
-                    return;
-                }
-                println!("added transaction to mempool");
+                let message = match blockchain.add_to_mempool(transaction.clone()) {
+                    Ok(replaced) => {
+                        println!(
+                            "added transaction to mempool, replacing {} transactions",
+                            replaced.len()
+                        );
+                        track_fee(&blockchain, &transaction).await;
+                        TransactionAccepted(replaced)
+                    }
+                    Err(BtcError::Rejected(reason)) => {
+                        println!("transaction rejected: {reason}");
+                        TransactionRejected(reason)
+                            .send_async(&mut socket)
+                            .await
+                            .unwrap();
+                        continue;
+                    }
+                    Err(e) => {
+                        println!("transaction rejected, closing connection: {e}");
+                        return;
+                    }
+                };
+                message.send_async(&mut socket).await.unwrap();
                 let nodes = crate::NODES
                     .iter()
                     .map(|x| x.key().clone())
@@ -79,67 +167,21 @@ pub async fn handle_connection(mut socket: TcpStream) {
             NewTransaction(transaction) => {
                 let mut blockchain = crate::BLOCKCHAIN.write().await;
                 println!("received transaction from friend");
-                if blockchain.add_to_mempool(transaction).is_err() {
+                if blockchain.add_to_mempool(transaction.clone()).is_err() {
                     println!("transaction rejected, closing connection");
                     return;
                 }
+                track_fee(&blockchain, &transaction).await;
             }
-            FetchTemplate(pubkey) => {
+            FetchTemplate(lock) => {
                 let blockchain = crate::BLOCKCHAIN.read().await;
-                let mut txs = Vec::new();
-                // insert txs from mempool
-                txs.extend(
-                    blockchain
-                        .mempool()
-                        .iter()
-                        .take(btclib::BLOCK_TRANSACTION_CAP)
-                        .map(|(_, tx)| tx)
-                        .cloned()
-                        .collect::<Vec<_>>(),
+                let template = BlockTemplate::new(&blockchain, lock);
+                println!(
+                    "built template with {} transactions paying {} in fees",
+                    template.block.transactions.len() - 1,
+                    template.fees
                 );
-
-                // insert coinbase tx with pubkey
-                txs.insert(
-                    0,
-                    Transaction {
-                        inputs: vec![],
-                        outputs: vec![TransactionOutput {
-                            pubkey,
-                            value: 0,
-                            unique_id: uuid::Uuid::new_v4(),
-                        }],
-                    },
-                );
-
-                let merkle_root = MerkleRoot::calculate(&txs);
-                let mut block = Block::new(
-                    BlockHeader {
-                        timestamp: Utc::now(),
-                        nonce: 0,
-                        prev_block_hash: blockchain
-                            .blocks()
-                            .last()
-                            .map(|last_block| last_block.hash())
-                            .unwrap_or(Hash::zero()),
-                        merkle_root,
-                        target: blockchain.target(),
-                    },
-                    txs,
-                );
-
-                let miner_fees = match block.calculate_miner_fees(blockchain.utxos()) {
-                    Ok(fees) => fees,
-                    Err(e) => {
-                        eprintln!("{e}");
-                        return;
-                    }
-                };
-                let reward = blockchain.calculate_block_reward();
-                // update coinbase tx with reward
-                block.transactions[0].outputs[0].value = reward + miner_fees;
-                // recalculate merkle root
-                block.header.merkle_root = MerkleRoot::calculate(&block.transactions);
-                let message = Template(block);
+                let message = Template(template.block);
                 message.send_async(&mut socket).await.unwrap();
             }
             ValidateTemplate(block_template) => {
@@ -161,6 +203,11 @@ pub async fn handle_connection(mut socket: TcpStream) {
                     return;
                 }
                 blockchain.rebuild_utxos();
+                crate::FEE_ESTIMATOR.write().await.process_block(
+                    &block,
+                    blockchain.block_height(),
+                    blockchain.mempool(),
+                );
                 println!("block look good, broadcasting");
 
                 // send block to all friend nodes
@@ -192,13 +239,40 @@ pub async fn handle_connection(mut socket: TcpStream) {
                 let message = Difference(count);
                 message.send_async(&mut socket).await.unwrap();
             }
+            AskChainwork => {
+                let blockchain = crate::BLOCKCHAIN.read().await;
+                let message = Chainwork(blockchain.block_height(), blockchain.chainwork());
+                message.send_async(&mut socket).await.unwrap();
+            }
             NewBlock(block) => {
                 let mut blockchain = crate::BLOCKCHAIN.write().await;
                 println!("received new block");
-                if blockchain.add_block(block).is_err() {
+                if blockchain.add_block(block.clone()).is_err() {
                     println!("block rejected");
+                    continue;
                 }
+                crate::FEE_ESTIMATOR.write().await.process_block(
+                    &block,
+                    blockchain.block_height(),
+                    blockchain.mempool(),
+                );
+            }
+            EstimateFee(target) => {
+                let estimate = crate::FEE_ESTIMATOR.read().await.estimate(target);
+                let message = FeeEstimate(estimate);
+                message.send_async(&mut socket).await.unwrap();
             }
         }
     }
 }
+
+// let the fee estimator watch a transaction that just entered the mempool
+async fn track_fee(blockchain: &Blockchain, transaction: &Transaction) {
+    let hash = transaction.hash();
+    if let Some(entry) = blockchain.mempool().get(&hash) {
+        crate::FEE_ESTIMATOR
+            .write()
+            .await
+            .track(hash, entry.fee_rate(), blockchain.block_height());
+    }
+}
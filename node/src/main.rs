@@ -1,13 +1,22 @@
+mod fee_estimator;
 mod handler;
 mod util;
 
 use {
     anyhow::Result,
     argh::FromArgs,
-    btclib::types::blockchain::Blockchain,
+    btclib::{
+        clock::NetworkClock,
+        difficulty::DifficultyAdjustment,
+        types::blockchain::Blockchain,
+    },
     dashmap::DashMap,
+    fee_estimator::FeeEstimator,
     static_init::dynamic,
-    std::path::Path,
+    std::{
+        path::Path,
+        sync::Arc,
+    },
     tokio::{
         net::{
             TcpListener,
@@ -20,6 +29,13 @@ use {
 #[dynamic]
 pub static BLOCKCHAIN: RwLock<Blockchain> = RwLock::new(Blockchain::new());
 
+// block timestamps are checked against the time of the other nodes
+#[dynamic]
+pub static CLOCK: Arc<NetworkClock> = Arc::new(NetworkClock::default());
+
+#[dynamic]
+pub static FEE_ESTIMATOR: RwLock<FeeEstimator> = RwLock::new(FeeEstimator::new());
+
 // Node pool
 #[dynamic]
 pub static NODES: DashMap<String, TcpStream> = DashMap::new();
@@ -41,6 +57,13 @@ struct Args {
         description = "the path to the blockchain file."
     )]
     blockchain_file: String,
+    /// The difficulty adjustment algorithm of the network.
+    #[argh(
+        option,
+        default = "DifficultyAdjustment::Epoch",
+        description = "the difficulty adjustment algorithm: epoch, lwma or asert."
+    )]
+    difficulty: DifficultyAdjustment,
     /// A list of node addresses to connect to.
     #[argh(positional, description = "A list of node addresses to connect to.")]
     nodes: Vec<String>,
@@ -53,6 +76,8 @@ async fn main() -> Result<()> {
     let port = args.port;
     let blockchain_file = args.blockchain_file;
     let nodes = args.nodes;
+    BLOCKCHAIN.write().await.set_difficulty(args.difficulty);
+    BLOCKCHAIN.write().await.set_clock(CLOCK.clone());
 
     if Path::new(&blockchain_file).exists() {
         util::load_blockchain(&blockchain_file).await?;
@@ -63,10 +88,11 @@ async fn main() -> Result<()> {
         if nodes.is_empty() {
             println!("no initial nodes provided, starting as a seed node");
         } else {
-            let (longest_name, longest_count) = util::find_longest_chain_node().await?;
-            // request the blockchain from the node with the longest blockchain
-            util::download_blockchain(&longest_name, longest_count).await?;
-            println!("blockchain downloaded from {longest_name}");
+            let (best_name, best_count) = util::find_most_work_chain_node().await?;
+            // request the blockchain from the node whose blockchain
+            // has the most work
+            util::download_blockchain(&best_name, best_count).await?;
+            println!("blockchain downloaded from {best_name}");
             // recalculate utxos
             {
                 let mut blockchain = BLOCKCHAIN.write().await;
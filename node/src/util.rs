@@ -7,6 +7,7 @@ use {
         network::Message,
         types::blockchain::Blockchain,
         util::Saveable,
+        U256,
     },
     tokio::{
         net::TcpStream,
@@ -28,7 +29,8 @@ pub async fn populate_connections(nodes: &[String]) -> Result<()> {
                 println!("received NodeList from {}", node);
                 for child_node in child_nodes.iter() {
                     println!("adding node {}", child_node);
-                    let new_stream = TcpStream::connect(child_node).await?;
+                    let mut new_stream = TcpStream::connect(child_node).await?;
+                    sample_time(child_node, &mut new_stream).await?;
                     crate::NODES.insert(child_node.clone(), new_stream);
                 }
             }
@@ -36,21 +38,46 @@ pub async fn populate_connections(nodes: &[String]) -> Result<()> {
                 println!("Unexpected message from {}", node);
             }
         }
+        sample_time(node, &mut stream).await?;
         crate::NODES.insert(node.clone(), stream);
     }
 
     Ok(())
 }
 
+// add the time of a node's clock to the network-adjusted time
+async fn sample_time(node: &str, stream: &mut TcpStream) -> Result<()> {
+    Message::AskTime.send_async(&mut *stream).await?;
+    match Message::receive_async(&mut *stream).await? {
+        Message::Time(time) => {
+            println!("received Time from {node}");
+            crate::CLOCK.add_sample(time);
+        }
+        _ => {
+            println!("Unexpected message from {node}");
+        }
+    }
+    Ok(())
+}
+
 pub async fn load_blockchain(blockchain_file: &str) -> Result<()> {
     println!("blockchain file exists, loading...");
-    let new_blockchain = Blockchain::load_from_file(blockchain_file)?;
+    let mut new_blockchain = Blockchain::load_from_file(blockchain_file)?;
     println!("blockchain loaded");
     let mut blockchain = crate::BLOCKCHAIN.write().await;
+    // neither the difficulty adjustment nor the clock are saved
+    // with the blocks
+    new_blockchain.set_difficulty(blockchain.difficulty());
+    new_blockchain.set_clock(crate::CLOCK.clone());
     *blockchain = new_blockchain;
     println!("rebuilding utxos...");
     blockchain.rebuild_utxos();
     println!("utxos rebuilt");
+    // saved filters may predate a change of what goes into them, so
+    // they are not trusted any more than the utxos
+    println!("rebuilding block filters...");
+    blockchain.rebuild_filters();
+    println!("block filters rebuilt");
     println!("checking if target needs to be adjusted...");
     println!("current target: {}", blockchain.target());
     blockchain.try_adjust_target();
@@ -59,33 +86,37 @@ pub async fn load_blockchain(blockchain_file: &str) -> Result<()> {
     Ok(())
 }
 
-pub async fn find_longest_chain_node() -> Result<(String, u32)> {
-    println!("finding nodes with the highest blockchain length...");
-    let mut longest_name = String::new();
-    let mut longest_count = 0;
+pub async fn find_most_work_chain_node() -> Result<(String, u32)> {
+    println!("finding nodes with the most work in their blockchain...");
+    let mut best_name = String::new();
+    let mut best_height = 0;
+    let mut best_chainwork = U256::zero();
     let all_nodes = crate::NODES
         .iter()
         .map(|x| x.key().clone())
         .collect::<Vec<_>>();
 
     for node in all_nodes.iter() {
-        println!("asking {} for blockchain length", node);
+        println!("asking {} for blockchain work", node);
         let mut stream = crate::NODES.get_mut(node).context("no code")?;
-        let message = Message::AskDifference(0);
+        let message = Message::AskChainwork;
         message.send_async(&mut *stream).await?;
-        println!("sent AskDifference to {node}");
+        println!("sent AskChainwork to {node}");
         let message = Message::receive_async(&mut *stream).await?;
         match message {
-            Message::Difference(count) => {
-                println!("received Difference from {node}");
-                if count > longest_count {
+            Message::Chainwork(height, chainwork) => {
+                println!("received Chainwork from {node}");
+                // a longer chain is not necessarily the one that took
+                // the most work to mine
+                if chainwork > best_chainwork {
                     println!(
-                        "new longest blockchain \
-                    {count} blocks from {node}
+                        "new best blockchain \
+                    {height} blocks with {chainwork} work from {node}
                     "
                     );
-                    longest_count = count;
-                    longest_name = node.clone();
+                    best_chainwork = chainwork;
+                    best_height = height;
+                    best_name = node.clone();
                 }
             }
             e => {
@@ -94,7 +125,7 @@ pub async fn find_longest_chain_node() -> Result<(String, u32)> {
         }
     }
 
-    Ok((longest_name, longest_count as u32))
+    Ok((best_name, best_height as u32))
 }
 
 pub async fn download_blockchain(node: &str, count: u32) -> Result<()> {